@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+
+use crate::message::{prelude::*, ParsedMessage};
+
+/// A completed `BATCH +ref ... BATCH -ref` group.
+#[derive(Debug)]
+pub struct Batch {
+    pub reference: String,
+    pub kind: String,
+    pub params: Vec<String>,
+    /// The `BATCH +ref` line that opened the batch, including its tags.
+    pub start: ParsedMessage,
+    pub items: Vec<BatchItem>,
+}
+
+#[derive(Debug)]
+pub enum BatchItem {
+    Message(ParsedMessage),
+    Batch(Batch),
+}
+
+impl Batch {
+    /// All messages of the batch in order, descending into nested batches.
+    pub fn messages(&self) -> Vec<&ParsedMessage> {
+        let mut messages = Vec::new();
+        for item in &self.items {
+            match item {
+                BatchItem::Message(msg) => messages.push(msg),
+                BatchItem::Batch(batch) => messages.extend(batch.messages()),
+            }
+        }
+        messages
+    }
+}
+
+/// What became of a message pushed into a [`BatchCollector`].
+#[derive(Debug)]
+pub enum Collected {
+    /// The message is not part of any batch.
+    Message(ParsedMessage),
+    /// The message closed an outermost batch.
+    Batch(Batch),
+}
+
+/// Groups messages carrying a `batch` tag into [`Batch`]es.
+#[derive(Debug, Default)]
+pub struct BatchCollector {
+    open: HashMap<String, Batch>,
+    parents: HashMap<String, String>,
+}
+
+impl BatchCollector {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_open(&self, reference: &str) -> bool {
+        self.open.contains_key(reference)
+    }
+
+    /// Feeds a message into the collector.
+    /// Returns `None` while the message is held back as part of an open batch.
+    pub fn push(&mut self, msg: ParsedMessage) -> Option<Collected> {
+        let parent = msg.tag("batch").filter(|parent| self.is_open(parent));
+
+        if msg.command() == "BATCH" {
+            let mut params = msg.params();
+            if !params.is_empty() {
+                let reference = params.remove(0);
+                if let Some(reference) = reference.strip_prefix('+') {
+                    let kind = if params.is_empty() {
+                        String::new()
+                    } else {
+                        params.remove(0)
+                    };
+                    if let Some(parent) = parent {
+                        self.parents.insert(reference.to_string(), parent);
+                    }
+                    self.open.insert(
+                        reference.to_string(),
+                        Batch {
+                            reference: reference.to_string(),
+                            kind,
+                            params,
+                            start: msg,
+                            items: Vec::new(),
+                        },
+                    );
+                    return None;
+                }
+                if let Some(reference) = reference.strip_prefix('-') {
+                    if let Some(batch) = self.open.remove(reference) {
+                        return match self.parents.remove(reference) {
+                            Some(parent) => {
+                                self.add(&parent, BatchItem::Batch(batch));
+                                None
+                            }
+                            None => Some(Collected::Batch(batch)),
+                        };
+                    }
+                }
+            }
+        }
+
+        match parent {
+            Some(parent) => {
+                self.add(&parent, BatchItem::Message(msg));
+                None
+            }
+            None => Some(Collected::Message(msg)),
+        }
+    }
+
+    fn add(&mut self, reference: &str, item: BatchItem) {
+        if let Some(batch) = self.open.get_mut(reference) {
+            batch.items.push(item);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect(lines: &[&str]) -> Vec<Collected> {
+        let mut collector = BatchCollector::new();
+        lines
+            .iter()
+            .filter_map(|line| collector.push(ParsedMessage::parse(line.to_string())))
+            .collect()
+    }
+
+    #[test]
+    fn test_batch() {
+        let collected = collect(&[
+            ":irc.example.com BATCH +yXNAbvnRHTRBv netsplit irc.hub other.host",
+            "@batch=yXNAbvnRHTRBv :aji!a@a QUIT :irc.hub other.host",
+            "@batch=yXNAbvnRHTRBv :nenolod!a@a QUIT :irc.hub other.host",
+            ":nick!user@host PRIVMSG #channel :Hello",
+            ":irc.example.com BATCH -yXNAbvnRHTRBv",
+        ]);
+
+        assert_eq!(collected.len(), 2);
+        assert!(matches!(&collected[0], Collected::Message(msg) if msg.command() == "PRIVMSG"));
+        match &collected[1] {
+            Collected::Batch(batch) => {
+                assert_eq!(batch.reference, "yXNAbvnRHTRBv");
+                assert_eq!(batch.kind, "netsplit");
+                assert_eq!(batch.params, vec!["irc.hub", "other.host"]);
+                assert_eq!(batch.messages().len(), 2);
+            }
+            _ => panic!("expected a batch"),
+        }
+    }
+
+    #[test]
+    fn test_nested_batch() {
+        let collected = collect(&[
+            "@label=7 :irc.example.com BATCH +outer labeled-response",
+            "@batch=outer :irc.example.com BATCH +inner netjoin",
+            "@batch=inner :aji!a@a JOIN #channel",
+            "@batch=outer :irc.example.com BATCH -inner",
+            "@batch=outer :irc.example.com 315 test #channel :End of WHO",
+            ":irc.example.com BATCH -outer",
+        ]);
+
+        assert_eq!(collected.len(), 1);
+        match &collected[0] {
+            Collected::Batch(batch) => {
+                assert_eq!(batch.start.tag("label"), Some("7".to_string()));
+                assert_eq!(batch.items.len(), 2);
+                assert!(
+                    matches!(&batch.items[0], BatchItem::Batch(inner) if inner.kind == "netjoin")
+                );
+                assert_eq!(batch.messages().len(), 2);
+            }
+            _ => panic!("expected a batch"),
+        }
+    }
+}
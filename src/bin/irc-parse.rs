@@ -0,0 +1,207 @@
+//! Reads raw IRC lines from stdin or a file and prints them parsed.
+//!
+//! ```text
+//! irc-parse [--format json|table|line] [--strict] [FILE]
+//! ```
+
+use std::env;
+use std::fs::File;
+use std::io::{stdin, stdout, BufRead, BufReader, BufWriter, Result, Write};
+use std::process::exit;
+
+use tiny_irc::message::{prelude::*, MessageBuilder, ParsedMessage};
+use tiny_irc::parser::{Parser, ParserConfig};
+
+const USAGE: &str = "usage: irc-parse [--format json|table|line] [--strict] [FILE]";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    /// One JSON object per line.
+    Json,
+    /// Aligned columns for reading.
+    Table,
+    /// The message serialized again, normalizing spacing and escapes.
+    Line,
+}
+
+/// Parses each line of `input` on its own so errors can be given a line number.
+/// Messages go to `out`, errors to `err`. Returns the number of errors.
+fn run<R: BufRead, W: Write, E: Write>(
+    mut input: R,
+    format: Format,
+    config: ParserConfig,
+    out: &mut W,
+    err: &mut E,
+) -> Result<usize> {
+    let mut parser = Parser::with_config(config);
+    let mut line = Vec::new();
+    let mut errors = 0;
+    for number in 1.. {
+        line.clear();
+        if input.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        if !line.ends_with(b"\n") {
+            line.extend_from_slice(b"\r\n");
+        }
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        parser.push_buf(&line);
+        let mut parsed = false;
+        while let Some(event) = parser.next_event() {
+            parsed = true;
+            match event {
+                Ok(msg) => print(out, format, number, &msg)?,
+                Err(error) => {
+                    errors += 1;
+                    writeln!(err, "line {}: {}", number, error)?;
+                }
+            }
+        }
+        if !parsed {
+            errors += 1;
+            let text = String::from_utf8_lossy(&line);
+            writeln!(
+                err,
+                "line {}: not a valid message: {:?}",
+                number,
+                text.trim_end()
+            )?;
+        }
+    }
+    Ok(errors)
+}
+
+fn print<W: Write>(out: &mut W, format: Format, number: usize, msg: &ParsedMessage) -> Result<()> {
+    match format {
+        Format::Json => {
+            serde_json::to_writer(&mut *out, msg)?;
+            writeln!(out)
+        }
+        Format::Table => {
+            write!(
+                out,
+                "{:>5}  {:<10} {:<30} {:?}",
+                number,
+                msg.command(),
+                msg.prefix().unwrap_or_default(),
+                msg.params()
+            )?;
+            for (key, value) in msg.tags() {
+                write!(out, " {}={:?}", key, value)?;
+            }
+            for leniency in msg.leniencies().iter() {
+                write!(out, " ({:?})", leniency)?;
+            }
+            writeln!(out)
+        }
+        Format::Line => writeln!(out, "{}", MessageBuilder::from(msg)),
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("irc-parse: {}\n{}", message, USAGE);
+    exit(2)
+}
+
+fn main() {
+    let mut format = Format::Table;
+    let mut config = ParserConfig::lenient();
+    let mut path = None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-f" | "--format" => {
+                format = match args.next().as_deref() {
+                    Some("json") => Format::Json,
+                    Some("table") => Format::Table,
+                    Some("line") => Format::Line,
+                    _ => fail("--format takes json, table or line"),
+                }
+            }
+            "--strict" => config = ParserConfig::strict(),
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            }
+            "-" if path.is_none() => path = Some(arg),
+            _ if arg.starts_with('-') => fail(&format!("unknown option {}", arg)),
+            _ if path.is_none() => path = Some(arg),
+            _ => fail("only one input file can be given"),
+        }
+    }
+
+    let input: Box<dyn BufRead> = match path.as_deref() {
+        None | Some("-") => Box::new(stdin().lock()),
+        Some(path) => match File::open(path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(error) => {
+                eprintln!("irc-parse: {}: {}", path, error);
+                exit(2)
+            }
+        },
+    };
+    let mut out = BufWriter::new(stdout().lock());
+    let result = run(input, format, config, &mut out, &mut std::io::stderr());
+    match result.and_then(|errors| out.flush().map(|_| errors)) {
+        Ok(0) => {}
+        Ok(_) => exit(1),
+        Err(error) => {
+            eprintln!("irc-parse: {}", error);
+            exit(2)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_str(input: &str, format: Format, config: ParserConfig) -> (String, String, usize) {
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let errors = run(input.as_bytes(), format, config, &mut out, &mut err).unwrap();
+        (
+            String::from_utf8(out).unwrap(),
+            String::from_utf8(err).unwrap(),
+            errors,
+        )
+    }
+
+    #[test]
+    fn test_formats() {
+        let input = "@a=b\\sc :nick!u@h PRIVMSG  #chan :hi\n\nPING x";
+        let (out, err, errors) = run_str(input, Format::Line, ParserConfig::lenient());
+        assert_eq!(out, "@a=b\\sc :nick!u@h PRIVMSG #chan hi\nPING x\n");
+        assert_eq!((err.as_str(), errors), ("", 0));
+
+        let (out, _, _) = run_str(input, Format::Json, ParserConfig::lenient());
+        let first: serde_json::Value = serde_json::from_str(out.lines().next().unwrap()).unwrap();
+        assert_eq!(first["tags"]["a"], "b c");
+        assert_eq!(first["prefix"]["nick"], "nick");
+
+        let (out, _, _) = run_str(input, Format::Table, ParserConfig::lenient());
+        assert!(out.starts_with("    1  PRIVMSG    nick!u@h"));
+        assert!(out.lines().nth(1).unwrap().starts_with("    3  PING"));
+    }
+
+    #[test]
+    fn test_errors_with_line_numbers() {
+        let input = "PING a\r\n:only.prefix\r\nPING  b\r\n";
+        let (out, err, errors) = run_str(input, Format::Line, ParserConfig::strict());
+        assert_eq!(out, "PING a\n");
+        assert_eq!(errors, 2);
+        assert_eq!(
+            err,
+            "line 2: not a valid message: \":only.prefix\"\nline 3: not a valid message: \"PING  b\"\n"
+        );
+
+        let config = ParserConfig {
+            max_line_len: 16,
+            ..ParserConfig::default()
+        };
+        let (_, err, errors) = run_str("PING :a-rather-long-token\n", Format::Line, config);
+        assert_eq!(errors, 1);
+        assert!(err.starts_with("line 1: dropped a line"));
+    }
+}
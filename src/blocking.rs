@@ -0,0 +1,278 @@
+use std::fmt::Display;
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::message::{prelude::*, BuildError, MessageBuilder, ParsedMessage};
+use crate::parser::Parser;
+
+/// A blocking client for scripts that don't need an async runtime.
+pub struct SyncClient<S = TcpStream> {
+    stream: S,
+    parser: Parser,
+    parse_errors: usize,
+}
+
+impl SyncClient<TcpStream> {
+    /// Connects to an IRC server over TCP.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self> {
+        Ok(Self::new(TcpStream::connect(addr)?))
+    }
+
+    /// Makes [`SyncClient::recv`] fail with `WouldBlock` or `TimedOut`
+    /// once no data arrived for `timeout`. A partially received line is kept.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+}
+
+impl<S: Read + Write> SyncClient<S> {
+    pub fn new(stream: S) -> Self {
+        Self {
+            stream,
+            parser: Parser::new(),
+            parse_errors: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// How many times the parser dropped input, an over-long line or one that
+    /// didn't fit in its buffer. [`SyncClient::recv`] skips these.
+    pub fn parse_errors(&self) -> usize {
+        self.parse_errors
+    }
+
+    /// Sends NICK and USER, answering PINGs until the server's welcome (001),
+    /// which is returned. Fails if the server rejects the registration, or with
+    /// `InvalidInput` before sending anything if `nick` or `user` can't be sent.
+    pub fn register(&mut self, nick: &str, user: &str, realname: &str) -> Result<ParsedMessage> {
+        let nick = MessageBuilder::new("NICK").param(nick);
+        let user = MessageBuilder::new("USER").params(&[user, "0", "*", realname]);
+        let nick = nick.try_build().map_err(invalid_input)?;
+        let user = user.try_build().map_err(invalid_input)?;
+        self.send(nick)?;
+        self.send(user)?;
+        loop {
+            let msg = self.recv()?;
+            let command = msg.command();
+            match command.as_str() {
+                "001" => return Ok(msg),
+                "PING" => {
+                    let token = msg.params().pop().unwrap_or_default();
+                    let pong = MessageBuilder::new("PONG").param(&token);
+                    self.send(pong.try_build().map_err(invalid_input)?)?;
+                }
+                "ERROR" | "431" | "432" | "433" | "436" | "437" | "462" | "464" | "465" => {
+                    return Err(Error::new(ErrorKind::ConnectionRefused, msg.to_string()))
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Sends a message such as a [`MessageBuilder`] or [`ParsedMessage`],
+    /// replacing any line ending it already has. A CR, LF or NUL anywhere else
+    /// would start another command, and fails with `InvalidInput`.
+    /// A builder is sent as it is, check it with [`MessageBuilder::try_build`].
+    pub fn send<M: Display>(&mut self, msg: M) -> Result<()> {
+        let line = msg.to_string();
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.contains(['\r', '\n', '\0']) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("line break or NUL inside {:?}", line),
+            ));
+        }
+        self.stream.write_all(format!("{}\r\n", line).as_bytes())?;
+        self.stream.flush()
+    }
+
+    /// Blocks until the next message arrives.
+    /// Fails with `UnexpectedEof` once the connection is closed.
+    pub fn recv(&mut self) -> Result<ParsedMessage> {
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.parser.next_event() {
+                Some(Ok(msg)) => return Ok(msg),
+                Some(Err(_)) => self.parse_errors += 1,
+                None => {
+                    let n = self.stream.read(&mut buf)?;
+                    if n == 0 {
+                        return Err(Error::from(ErrorKind::UnexpectedEof));
+                    }
+                    self.parser.push_buf(&buf[..n]);
+                }
+            }
+        }
+    }
+}
+
+fn invalid_input(err: BuildError) -> Error {
+    Error::new(ErrorKind::InvalidInput, err)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_register() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut lines = Vec::new();
+            for _ in 0..2 {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                lines.push(line);
+            }
+            stream.write_all(b"PING :abc\r\n").unwrap();
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            lines.push(line);
+            stream
+                .write_all(b":srv 001 test :Welcome\r\n:srv NOTICE test :hi\r\n")
+                .unwrap();
+            lines
+        });
+
+        let mut client = SyncClient::connect(addr).unwrap();
+        let welcome = client.register("test", "user", "Real Name").unwrap();
+        assert_eq!(welcome.params(), vec!["test", "Welcome"]);
+        assert_eq!(client.recv().unwrap().command(), "NOTICE");
+        assert_eq!(
+            server.join().unwrap(),
+            vec![
+                "NICK test\r\n",
+                "USER user 0 * :Real Name\r\n",
+                "PONG abc\r\n"
+            ]
+        );
+        assert_eq!(client.recv().unwrap_err().kind(), ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_send_parsed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = SyncClient::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+
+        client
+            .send(ParsedMessage::parse("PRIVMSG #c :hi\r\n".to_string()))
+            .unwrap();
+        client.send("PING x\n").unwrap();
+        drop(client);
+        let mut sent = String::new();
+        BufReader::new(stream).read_to_string(&mut sent).unwrap();
+        assert_eq!(sent, "PRIVMSG #c :hi\r\nPING x\r\n");
+    }
+
+    #[test]
+    fn test_send_rejects_line_breaks() {
+        let mut client = SyncClient::new(Mock {
+            input: std::io::Cursor::new(Vec::new()),
+            output: Vec::new(),
+        });
+        for line in [
+            "PRIVMSG #c :hi\r\nQUIT",
+            "PRIVMSG #c :a\rb",
+            "PRIVMSG #c :a\0b",
+        ] {
+            let err = client.send(line).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+        let builder = MessageBuilder::new("PRIVMSG").params(&["#c", "hi\nQUIT"]);
+        assert_eq!(
+            client.send(builder).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert!(client.get_ref().output.is_empty());
+    }
+
+    #[test]
+    fn test_recv_skips_overlong_line() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = SyncClient::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut input = format!("PRIVMSG #c :{}\r\n", "a".repeat(10_000));
+        input.push_str("PING :x\r\n");
+        stream.write_all(input.as_bytes()).unwrap();
+
+        assert_eq!(client.recv().unwrap().params(), vec!["x"]);
+        assert_eq!(client.parse_errors(), 1);
+    }
+
+    #[test]
+    fn test_read_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = SyncClient::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(20)))
+            .unwrap();
+
+        stream.write_all(b":srv PRIVMSG test :par").unwrap();
+        let err = client.recv().unwrap_err();
+        assert!(matches!(
+            err.kind(),
+            ErrorKind::WouldBlock | ErrorKind::TimedOut
+        ));
+        stream.write_all(b"tial\r\n").unwrap();
+        assert_eq!(client.recv().unwrap().params(), vec!["test", "partial"]);
+    }
+
+    struct Mock {
+        input: std::io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Mock {
+        fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Mock {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_rejected() {
+        let mut client = SyncClient::new(Mock {
+            input: std::io::Cursor::new(
+                b":srv 433 * test :Nickname is already in use\r\n".to_vec(),
+            ),
+            output: Vec::new(),
+        });
+        let err = client.register("test", "user", "Real").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::ConnectionRefused);
+        assert!(client.get_ref().output.starts_with(b"NICK test\r\n"));
+    }
+
+    #[test]
+    fn test_register_invalid() {
+        let mut client = SyncClient::new(Mock {
+            input: std::io::Cursor::new(b":srv 001 test :Welcome\r\n".to_vec()),
+            output: Vec::new(),
+        });
+        for (nick, user) in [("test", ""), ("test", "a b"), ("test\r\nQUIT", "user")] {
+            let err = client.register(nick, user, "Real").unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        }
+        assert!(client.get_ref().output.is_empty());
+    }
+}
@@ -0,0 +1,586 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Result};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use super::{Event, Format, Record, MONTHS};
+use crate::ctcp::Ctcp;
+use crate::message::{
+    days_from_civil, format_rfc3339, parse_rfc3339, MessageBuilder, ParsedMessage,
+};
+
+impl Record {
+    /// The message that caused the record, with its time as a `time` tag.
+    /// The prefix is only as complete as the log: `nick!user@host` after
+    /// joins, parts and quits, just the nick otherwise. In queries the
+    /// target is the buffer, as logs don't say who we were.
+    /// `None` if the logged names or mode params can't form a message.
+    pub fn to_message(&self) -> Option<ParsedMessage> {
+        let target = self.target.as_str();
+        let prefix = |nick: &str, userhost: &str| match userhost {
+            "" => nick.to_string(),
+            userhost if userhost.contains('@') => format!("{}!{}", nick, userhost),
+            host => format!("{}@{}", nick, host),
+        };
+        let (command, prefix, params) = match &self.event {
+            Event::Message { nick, text } => ("PRIVMSG", prefix(nick, ""), vec![target, text]),
+            Event::Action { nick, text } => {
+                let ctcp = Ctcp::action(text).encode();
+                let msg = MessageBuilder::new("PRIVMSG")
+                    .prefix(nick)
+                    .params(&[target, &ctcp]);
+                return self.with_time(msg);
+            }
+            Event::Notice { nick, text } => ("NOTICE", prefix(nick, ""), vec![target, text]),
+            Event::Join { nick, userhost } => ("JOIN", prefix(nick, userhost), vec![target]),
+            Event::Part {
+                nick,
+                userhost,
+                reason,
+            } => {
+                let mut params = vec![target];
+                if !reason.is_empty() {
+                    params.push(reason);
+                }
+                ("PART", prefix(nick, userhost), params)
+            }
+            Event::Quit {
+                nick,
+                userhost,
+                reason,
+            } => ("QUIT", prefix(nick, userhost), vec![reason.as_str()]),
+            Event::Kick {
+                nick,
+                target: kicked,
+                reason,
+            } => ("KICK", prefix(nick, ""), vec![target, kicked, reason]),
+            Event::Nick { nick, new } => ("NICK", prefix(nick, ""), vec![new.as_str()]),
+            Event::Topic { nick, topic } => ("TOPIC", prefix(nick, ""), vec![target, topic]),
+            Event::Mode { nick, modes } => {
+                let mut params = vec![target];
+                params.extend(modes.split(' ').filter(|mode| !mode.is_empty()));
+                ("MODE", prefix(nick, ""), params)
+            }
+        };
+        let msg = MessageBuilder::new(command).prefix(&prefix).params(&params);
+        self.with_time(msg)
+    }
+
+    fn with_time(&self, msg: MessageBuilder) -> Option<ParsedMessage> {
+        let msg = msg.tag("time", &format_rfc3339(self.time));
+        msg.try_to_message().ok()
+    }
+}
+
+/// Parses a line of a raw log: a message as sent by the server, optionally
+/// after an RFC 3339 timestamp which becomes its received time.
+/// `None` for blank lines and lines that aren't messages.
+pub fn parse_raw_line(line: &str) -> Option<ParsedMessage> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let (time, line) = match line.split_once(' ') {
+        Some((time, rest)) => match parse_rfc3339(time) {
+            Some(time) => (Some(time), rest),
+            None => (None, line),
+        },
+        None => (None, line),
+    };
+    let msg = ParsedMessage::try_parse(line.to_string())?;
+    Some(match time {
+        Some(time) => msg.with_received(time),
+        None => msg,
+    })
+}
+
+/// Reads a raw log, skipping lines that aren't messages.
+pub fn read_raw<R: BufRead>(reader: R) -> Result<Vec<ParsedMessage>> {
+    let mut messages = Vec::new();
+    for line in reader.lines() {
+        messages.extend(parse_raw_line(&line?));
+    }
+    Ok(messages)
+}
+
+/// Parses a timestamp with the conversions of [`super::format_time`],
+/// returning the date if it has one, the seconds into the day and the rest.
+fn parse_time<'a>(s: &'a str, format: &str) -> Option<(Option<i64>, i64, &'a str)> {
+    fn number(rest: &mut &str, max_len: usize) -> Option<i64> {
+        let len = rest
+            .bytes()
+            .take(max_len)
+            .take_while(u8::is_ascii_digit)
+            .count();
+        let value = rest.get(..len).filter(|_| len > 0)?.parse().ok()?;
+        *rest = &rest[len..];
+        Some(value)
+    }
+
+    let mut rest = s;
+    let (mut year, mut month, mut day) = (None, None, None);
+    let mut secs = 0;
+    let mut unix = None;
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            rest = rest.strip_prefix(c)?;
+            continue;
+        }
+        match chars.next() {
+            Some('Y') => year = Some(number(&mut rest, 4)?),
+            Some('m') => month = Some(number(&mut rest, 2)?),
+            Some('d') => day = Some(number(&mut rest, 2)?),
+            Some('H') => secs += number(&mut rest, 2)? * 3600,
+            Some('M') => secs += number(&mut rest, 2)? * 60,
+            Some('S') => secs += number(&mut rest, 2)?,
+            Some('s') => unix = Some(number(&mut rest, 20)?),
+            Some('a') => {
+                rest.get(..3)
+                    .filter(|name| name.bytes().all(|b| b.is_ascii_alphabetic()))?;
+                rest = &rest[3..];
+            }
+            Some('b') => {
+                let name = rest.get(..3)?;
+                month = Some(MONTHS.iter().position(|month| *month == name)? as i64 + 1);
+                rest = &rest[3..];
+            }
+            Some('%') | None => rest = rest.strip_prefix('%')?,
+            Some(other) => rest = rest.strip_prefix('%')?.strip_prefix(other)?,
+        }
+    }
+    if let Some(unix) = unix {
+        return Some((Some(unix.div_euclid(86400)), unix.rem_euclid(86400), rest));
+    }
+    let date = match (year, month, day) {
+        (Some(year), Some(month), Some(day)) => Some(days_from_civil(year, month, day)),
+        _ => None,
+    };
+    Some((date, secs, rest))
+}
+
+/// Strips brackets around a reason, e.g. `[Quit: bye]`.
+fn unwrap(s: &str, open: char, close: char) -> String {
+    s.strip_prefix(open)
+        .and_then(|s| s.strip_suffix(close))
+        .unwrap_or(s)
+        .to_string()
+}
+
+/// Strips the status prefix and padding some clients show on nicks.
+fn clean(nick: &str) -> String {
+    nick.trim()
+        .trim_start_matches(['~', '&', '@', '%', '+'])
+        .to_string()
+}
+
+/// Parses `nick> text`, after the `<`.
+fn message(rest: &str) -> Option<Event> {
+    let (nick, text) = match rest.split_once("> ") {
+        Some(split) => split,
+        None => (rest.strip_suffix('>')?, ""),
+    };
+    let (nick, text) = (clean(nick), text.to_string());
+    Some(Event::Message { nick, text })
+}
+
+/// Splits `nick (user@host) rest` with the given brackets.
+fn split_userhost(s: &str, open: char, close: char) -> Option<(String, String, &str)> {
+    let (nick, rest) = s.split_once(' ')?;
+    let rest = rest.strip_prefix(open)?;
+    let end = rest.find(close)?;
+    let after = rest[end + close.len_utf8()..].trim_start();
+    Some((nick.to_string(), rest[..end].to_string(), after))
+}
+
+fn irssi(body: &str) -> Option<Event> {
+    if let Some(rest) = body.strip_prefix("-!- ") {
+        if let Some(rest) = rest.strip_prefix("mode/") {
+            let (_, rest) = rest.split_once(" [")?;
+            let (modes, nick) = rest.rsplit_once("] by ")?;
+            let (nick, modes) = (nick.to_string(), modes.to_string());
+            return Some(Event::Mode { nick, modes });
+        }
+        if let Some((target, rest)) = rest.split_once(" was kicked from ") {
+            let (_, rest) = rest.split_once(" by ")?;
+            let (nick, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+            return Some(Event::Kick {
+                nick: nick.to_string(),
+                target: target.to_string(),
+                reason: unwrap(reason, '[', ']'),
+            });
+        }
+        if let Some((nick, new)) = rest.split_once(" is now known as ") {
+            let (nick, new) = (nick.to_string(), new.to_string());
+            return Some(Event::Nick { nick, new });
+        }
+        if let Some((nick, rest)) = rest.split_once(" changed the topic of ") {
+            let (_, topic) = rest.split_once(" to: ")?;
+            let (nick, topic) = (nick.to_string(), topic.to_string());
+            return Some(Event::Topic { nick, topic });
+        }
+        let (nick, userhost, rest) = split_userhost(rest, '[', ']')?;
+        if rest.starts_with("has joined ") {
+            return Some(Event::Join { nick, userhost });
+        }
+        if let Some(rest) = rest.strip_prefix("has left ") {
+            let reason = rest.split_once(' ').map(|(_, reason)| reason).unwrap_or("");
+            let reason = unwrap(reason, '[', ']');
+            return Some(Event::Part {
+                nick,
+                userhost,
+                reason,
+            });
+        }
+        let reason = unwrap(rest.strip_prefix("has quit")?.trim_start(), '[', ']');
+        return Some(Event::Quit {
+            nick,
+            userhost,
+            reason,
+        });
+    }
+    if let Some(rest) = body.strip_prefix(" * ") {
+        let (nick, text) = rest.split_once(' ').unwrap_or((rest, ""));
+        let (nick, text) = (nick.to_string(), text.to_string());
+        return Some(Event::Action { nick, text });
+    }
+    if let Some(rest) = body.strip_prefix('<') {
+        return message(rest);
+    }
+    // `-nick- text`, or `-nick:#channel- text` for channel notices.
+    let (nick, text) = body.strip_prefix('-')?.split_once("- ")?;
+    let nick = nick.split(':').next().unwrap_or(nick).to_string();
+    let text = text.to_string();
+    Some(Event::Notice { nick, text })
+}
+
+fn weechat(body: &str) -> Option<Event> {
+    let (prefix, text) = body.split_once('\t')?;
+    match prefix {
+        "-->" => {
+            let (nick, userhost, _) = split_userhost(text, '(', ')')?;
+            Some(Event::Join { nick, userhost })
+        }
+        "<--" => {
+            if let Some((nick, rest)) = text.split_once(" has kicked ") {
+                let (target, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+                return Some(Event::Kick {
+                    nick: nick.to_string(),
+                    target: target.to_string(),
+                    reason: unwrap(reason, '(', ')'),
+                });
+            }
+            let (nick, userhost, rest) = split_userhost(text, '(', ')')?;
+            if let Some(rest) = rest.strip_prefix("has left ") {
+                let reason = rest.split_once(' ').map(|(_, reason)| reason).unwrap_or("");
+                let reason = unwrap(reason, '(', ')');
+                return Some(Event::Part {
+                    nick,
+                    userhost,
+                    reason,
+                });
+            }
+            let reason = unwrap(rest.strip_prefix("has quit")?.trim_start(), '(', ')');
+            Some(Event::Quit {
+                nick,
+                userhost,
+                reason,
+            })
+        }
+        "--" => {
+            if let Some(rest) = text.strip_prefix("Notice(") {
+                let (nick, text) = rest.split_once("): ")?;
+                let (nick, text) = (nick.to_string(), text.to_string());
+                return Some(Event::Notice { nick, text });
+            }
+            if let Some(rest) = text.strip_prefix("Mode ") {
+                let (_, rest) = rest.split_once(" [")?;
+                let (modes, nick) = rest.rsplit_once("] by ")?;
+                let (nick, modes) = (nick.to_string(), modes.to_string());
+                return Some(Event::Mode { nick, modes });
+            }
+            if let Some((nick, new)) = text.split_once(" is now known as ") {
+                let (nick, new) = (nick.to_string(), new.to_string());
+                return Some(Event::Nick { nick, new });
+            }
+            let (nick, rest) = text.split_once(" has changed topic for ")?;
+            let (_, topic) = rest.split_once(" to ")?;
+            let (nick, topic) = (nick.to_string(), unwrap(topic, '"', '"'));
+            Some(Event::Topic { nick, topic })
+        }
+        " *" => {
+            let (nick, text) = text.split_once(' ').unwrap_or((text, ""));
+            let (nick, text) = (nick.to_string(), text.to_string());
+            Some(Event::Action { nick, text })
+        }
+        // Other prefixes such as `=!=` are client messages.
+        _ if prefix.is_empty() || prefix.starts_with(['-', '<', '=']) => None,
+        nick => {
+            let (nick, text) = (clean(nick), text.to_string());
+            Some(Event::Message { nick, text })
+        }
+    }
+}
+
+fn znc(body: &str) -> Option<Event> {
+    if let Some(rest) = body.strip_prefix("*** ") {
+        if let Some(rest) = rest.strip_prefix("Joins: ") {
+            let (nick, userhost, _) = split_userhost(rest, '(', ')')?;
+            return Some(Event::Join { nick, userhost });
+        }
+        if let Some(rest) = rest.strip_prefix("Parts: ") {
+            let (nick, userhost, reason) = split_userhost(rest, '(', ')')?;
+            let reason = unwrap(reason, '(', ')');
+            return Some(Event::Part {
+                nick,
+                userhost,
+                reason,
+            });
+        }
+        if let Some(rest) = rest.strip_prefix("Quits: ") {
+            let (nick, userhost, reason) = split_userhost(rest, '(', ')')?;
+            let reason = unwrap(reason, '(', ')');
+            return Some(Event::Quit {
+                nick,
+                userhost,
+                reason,
+            });
+        }
+        if let Some((target, rest)) = rest.split_once(" was kicked by ") {
+            let (nick, reason) = rest.split_once(' ').unwrap_or((rest, ""));
+            return Some(Event::Kick {
+                nick: nick.to_string(),
+                target: target.to_string(),
+                reason: unwrap(reason, '(', ')'),
+            });
+        }
+        if let Some((nick, new)) = rest.split_once(" is now known as ") {
+            let (nick, new) = (nick.to_string(), new.to_string());
+            return Some(Event::Nick { nick, new });
+        }
+        if let Some((nick, topic)) = rest.split_once(" changes topic to ") {
+            let (nick, topic) = (nick.to_string(), unwrap(topic, '\'', '\''));
+            return Some(Event::Topic { nick, topic });
+        }
+        let (nick, modes) = rest.split_once(" sets mode: ")?;
+        let (nick, modes) = (nick.to_string(), modes.to_string());
+        return Some(Event::Mode { nick, modes });
+    }
+    if let Some(rest) = body.strip_prefix("* ") {
+        let (nick, text) = rest.split_once(' ').unwrap_or((rest, ""));
+        let (nick, text) = (nick.to_string(), text.to_string());
+        return Some(Event::Action { nick, text });
+    }
+    if let Some(rest) = body.strip_prefix('<') {
+        return message(rest);
+    }
+    let (nick, text) = body.strip_prefix('-')?.split_once("- ")?;
+    let (nick, text) = (nick.to_string(), text.to_string());
+    Some(Event::Notice { nick, text })
+}
+
+/// Reads a text log of one buffer back into [`Record`]s.
+///
+/// Timestamps are read with the same format used to write them. When they
+/// have no date, it comes from irssi's `--- Log opened` and `--- Day changed`
+/// lines, a `YYYY-MM-DD` file name or [`Importer::date`]. JSON Lines logs
+/// are left to a JSON parser.
+pub struct Importer {
+    format: Format,
+    target: String,
+    timestamp: String,
+    /// Days since the epoch.
+    date: Option<i64>,
+}
+
+impl Importer {
+    /// An importer for the log of `target`, a channel or the nick of a query.
+    pub fn new(format: Format, target: &str) -> Self {
+        Self {
+            format,
+            target: target.to_string(),
+            timestamp: format.default_timestamp().to_string(),
+            date: None,
+        }
+    }
+
+    pub fn timestamp_format(mut self, timestamp: &str) -> Self {
+        self.timestamp = timestamp.to_string();
+        self
+    }
+
+    /// The date of lines whose timestamp has none, until the log says otherwise.
+    pub fn date(mut self, year: i64, month: i64, day: i64) -> Self {
+        self.date = Some(days_from_civil(year, month, day));
+        self
+    }
+
+    /// Parses a line, `None` for those that aren't events, such as headers.
+    /// Lines before any date are dated 1970-01-01.
+    pub fn parse_line(&mut self, line: &str) -> Option<Record> {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if self.format == Format::Irssi {
+            if let Some(header) = line.strip_prefix("--- ") {
+                let date = parse_time(header, "Log opened %a %b %d %H:%M:%S %Y")
+                    .or_else(|| parse_time(header, "Day changed %a %b %d %Y"));
+                if let Some((Some(date), _, _)) = date {
+                    self.date = Some(date);
+                }
+                return None;
+            }
+        }
+
+        let (date, secs, rest) = parse_time(line, &self.timestamp)?;
+        if date.is_some() {
+            self.date = date;
+        }
+        let separator = if self.format == Format::Weechat {
+            '\t'
+        } else {
+            ' '
+        };
+        let body = rest.strip_prefix(separator)?;
+        let event = match self.format {
+            Format::Irssi => irssi(body),
+            Format::Weechat => weechat(body),
+            Format::Znc => znc(body),
+            Format::JsonLines => None,
+        }?;
+        let secs = self.date.unwrap_or(0) * 86400 + secs;
+        let time = if secs >= 0 {
+            UNIX_EPOCH + Duration::from_secs(secs as u64)
+        } else {
+            UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs())
+        };
+        Some(Record {
+            time,
+            target: self.target.clone(),
+            event,
+        })
+    }
+
+    /// Reads a log into the messages its events came from, skipping lines
+    /// that don't give one.
+    pub fn read<R: BufRead>(&mut self, reader: R) -> Result<Vec<ParsedMessage>> {
+        let mut messages = Vec::new();
+        for line in reader.lines() {
+            messages.extend(
+                self.parse_line(&line?)
+                    .and_then(|record| record.to_message()),
+            );
+        }
+        Ok(messages)
+    }
+
+    /// Reads a log file, taking the date from a `YYYY-MM-DD` name such as
+    /// those [`super::ChatLogger`] writes.
+    pub fn read_file<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<ParsedMessage>> {
+        let path = path.as_ref();
+        let stem = path.file_stem().and_then(|stem| stem.to_str());
+        if let Some((Some(date), _, "")) = stem.and_then(|stem| parse_time(stem, "%Y-%m-%d")) {
+            self.date = Some(date);
+        }
+        self.read(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chatlog::Tracker;
+    use crate::message::civil_from_days;
+    use crate::message::prelude::*;
+    use std::time::SystemTime;
+
+    fn date_of(time: SystemTime) -> (i64, i64, i64) {
+        let secs = time.duration_since(UNIX_EPOCH).unwrap().as_secs();
+        civil_from_days(secs as i64 / 86400)
+    }
+
+    const LINES: [&str; 10] = [
+        ":alice!al@host JOIN #chan",
+        ":alice!al@host PRIVMSG #chan :hello there",
+        ":alice!al@host PRIVMSG #chan :\x01ACTION waves\x01",
+        ":alice!al@host NOTICE #chan :heads up",
+        ":alice!al@host TOPIC #chan :new topic",
+        ":alice!al@host MODE #chan +o bob",
+        ":alice!al@host KICK #chan bob :behave",
+        ":alice!al@host NICK ally",
+        ":ally!al@host PART #chan :later",
+        ":me!u@h JOIN #chan",
+    ];
+
+    /// Logs LINES in `format` and imports them again.
+    fn round_trip(format: Format) -> Vec<Event> {
+        let mut tracker = Tracker::new();
+        tracker.set_nick("me");
+        tracker.records(&ParsedMessage::parse(":me!u@h JOIN #chan".to_string()));
+        let timestamp = "%Y-%m-%d %H:%M:%S";
+        let mut importer = Importer::new(format, "#chan").timestamp_format(timestamp);
+        LINES
+            .iter()
+            .map(|line| {
+                let line = format!("@time=2024-03-05T14:07:09.000Z {}", line);
+                let record = tracker.records(&ParsedMessage::parse(line)).remove(0);
+                let rendered = format.render(&record, timestamp);
+                let imported = importer.parse_line(&rendered).expect(&rendered);
+                assert_eq!(imported.time, record.time, "{}", rendered);
+                assert_eq!(imported.event, record.event, "{}", rendered);
+                imported.event
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for format in [Format::Irssi, Format::Weechat, Format::Znc] {
+            assert_eq!(round_trip(format).len(), LINES.len());
+        }
+    }
+
+    #[test]
+    fn test_irssi() {
+        let log = "--- Log opened Tue Mar 05 23:59:00 2024\n\
+                   23:59 <@alice> late\n\
+                   --- Day changed Wed Mar 06 2024\n\
+                   00:01 -!- bob [b@host] has quit [Ping timeout]\n\
+                   00:02 -alice:#chan- notice\n";
+        let messages = Importer::new(Format::Irssi, "#chan")
+            .read(log.as_bytes())
+            .unwrap();
+        let lines: Vec<String> = messages.iter().map(|msg| msg.to_string()).collect();
+        assert_eq!(
+            lines,
+            [
+                "@time=2024-03-05T23:59:00.000Z :alice PRIVMSG #chan late",
+                "@time=2024-03-06T00:01:00.000Z :bob!b@host QUIT :Ping timeout",
+                "@time=2024-03-06T00:02:00.000Z :alice NOTICE #chan notice",
+            ]
+        );
+        assert_eq!(messages[1].nick().as_deref(), Some("bob"));
+    }
+
+    #[test]
+    fn test_skips_invalid_messages() {
+        let log = "14:07 -!- mode/#chan [+ov :x bob] by alice\n\
+                   14:08 <alice> hi\n";
+        let mut importer = Importer::new(Format::Irssi, "#chan");
+        let record = importer.parse_line(log.lines().next().unwrap()).unwrap();
+        assert_eq!(record.to_message(), None);
+        let messages = importer.read(log.as_bytes()).unwrap();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].params(), vec!["#chan", "hi"]);
+    }
+
+    #[test]
+    fn test_raw() {
+        let log = "2024-03-05T14:07:09.500Z :alice!a@h PRIVMSG #chan :hi\r\n\
+                   \r\n\
+                   @time=2024-03-05T14:08:00.000Z :irc.test PING :x\r\n";
+        let messages = read_raw(log.as_bytes()).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].command(), "PRIVMSG");
+        assert_eq!(date_of(messages[0].time().unwrap()), (2024, 3, 5));
+        assert_eq!(
+            messages[1].time(),
+            parse_rfc3339("2024-03-05T14:08:00.000Z")
+        );
+    }
+}
@@ -0,0 +1,846 @@
+//! Channel and query logs in the formats of common clients.
+//!
+//! A [`Tracker`] turns messages into [`Record`]s in the buffers they belong
+//! to, a [`Format`] renders them and a [`ChatLogger`] writes them to a file
+//! per buffer and day. Times are in UTC, using `server-time` when sent.
+//! An [`Importer`] reads such logs back into messages.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Result, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::ctcp::Ctcp;
+use crate::message::{civil_from_days, format_rfc3339, prelude::*, ParsedMessage};
+
+mod import;
+pub use import::{parse_raw_line, read_raw, Importer};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// What happened in a buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Event {
+    Message {
+        nick: String,
+        text: String,
+    },
+    Action {
+        nick: String,
+        text: String,
+    },
+    Notice {
+        nick: String,
+        text: String,
+    },
+    /// `userhost` is `user@host`, empty when the prefix has neither.
+    Join {
+        nick: String,
+        userhost: String,
+    },
+    Part {
+        nick: String,
+        userhost: String,
+        reason: String,
+    },
+    Quit {
+        nick: String,
+        userhost: String,
+        reason: String,
+    },
+    /// `nick` kicked `target`.
+    Kick {
+        nick: String,
+        target: String,
+        reason: String,
+    },
+    Nick {
+        nick: String,
+        new: String,
+    },
+    Topic {
+        nick: String,
+        topic: String,
+    },
+    /// `modes` are the mode string and its arguments, e.g. `+o alice`.
+    Mode {
+        nick: String,
+        modes: String,
+    },
+}
+
+impl Event {
+    /// Who caused the event.
+    pub fn nick(&self) -> &str {
+        match self {
+            Event::Message { nick, .. }
+            | Event::Action { nick, .. }
+            | Event::Notice { nick, .. }
+            | Event::Join { nick, .. }
+            | Event::Part { nick, .. }
+            | Event::Quit { nick, .. }
+            | Event::Kick { nick, .. }
+            | Event::Nick { nick, .. }
+            | Event::Topic { nick, .. }
+            | Event::Mode { nick, .. } => nick,
+        }
+    }
+}
+
+/// An event in a buffer: a channel, or the nick a query is with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub time: SystemTime,
+    pub target: String,
+    pub event: Event,
+}
+
+fn fold(name: &str) -> String {
+    name.to_ascii_lowercase()
+}
+
+fn is_channel(target: &str) -> bool {
+    target.starts_with(['#', '&', '+', '!'])
+}
+
+/// Follows our nick and channel members, to tell which buffers a message
+/// belongs in. QUIT and NICK go to every channel the user shares with us.
+#[derive(Debug, Clone, Default)]
+pub struct Tracker {
+    nick: Option<String>,
+    /// Folded channel name to the folded nicks in it.
+    channels: HashMap<String, HashSet<String>>,
+}
+
+impl Tracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Our nick, learnt from the welcome (001) and our NICK changes.
+    pub fn nick(&self) -> Option<&str> {
+        self.nick.as_deref()
+    }
+
+    pub fn set_nick(&mut self, nick: &str) {
+        self.nick = Some(nick.to_string());
+    }
+
+    fn is_me(&self, nick: &str) -> bool {
+        self.nick
+            .as_deref()
+            .is_some_and(|me| fold(me) == fold(nick))
+    }
+
+    /// Removes a nick from the channels it is in, returning those.
+    fn remove_everywhere(&mut self, nick: &str) -> Vec<String> {
+        let nick = fold(nick);
+        let mut channels: Vec<String> = self
+            .channels
+            .iter_mut()
+            .filter_map(|(channel, members)| members.remove(&nick).then(|| channel.clone()))
+            .collect();
+        channels.sort();
+        channels
+    }
+
+    /// The records for a message, none for those that aren't logged.
+    pub fn records(&mut self, msg: &ParsedMessage) -> Vec<Record> {
+        let time = msg.time().unwrap_or_else(SystemTime::now);
+        let params = msg.params();
+        let param = |i: usize| params.get(i).cloned().unwrap_or_default();
+        let nick = msg.nick().unwrap_or_default();
+        let userhost = match (msg.user(), msg.host()) {
+            (Some(user), Some(host)) => format!("{}@{}", user, host),
+            (Some(user), None) => user,
+            (None, Some(host)) => host,
+            (None, None) => String::new(),
+        };
+        let record = |target: &str, event| Record {
+            time,
+            target: target.to_string(),
+            event,
+        };
+
+        match msg.command().as_str() {
+            "001" => {
+                self.nick = params.first().cloned();
+                Vec::new()
+            }
+            "353" => {
+                if let Some(members) = self.channels.get_mut(&fold(&param(2))) {
+                    for name in param(3).split(' ').filter(|name| !name.is_empty()) {
+                        members.insert(fold(name.trim_start_matches(['~', '&', '@', '%', '+'])));
+                    }
+                }
+                Vec::new()
+            }
+            "JOIN" => {
+                let channel = param(0);
+                if self.is_me(&nick) {
+                    self.channels.insert(fold(&channel), HashSet::new());
+                }
+                if let Some(members) = self.channels.get_mut(&fold(&channel)) {
+                    members.insert(fold(&nick));
+                }
+                vec![record(&channel, Event::Join { nick, userhost })]
+            }
+            "PART" => {
+                let channel = param(0);
+                if self.is_me(&nick) {
+                    self.channels.remove(&fold(&channel));
+                } else if let Some(members) = self.channels.get_mut(&fold(&channel)) {
+                    members.remove(&fold(&nick));
+                }
+                let reason = param(1);
+                vec![record(
+                    &channel,
+                    Event::Part {
+                        nick,
+                        userhost,
+                        reason,
+                    },
+                )]
+            }
+            "KICK" => {
+                let (channel, target) = (param(0), param(1));
+                if self.is_me(&target) {
+                    self.channels.remove(&fold(&channel));
+                } else if let Some(members) = self.channels.get_mut(&fold(&channel)) {
+                    members.remove(&fold(&target));
+                }
+                let reason = param(2);
+                vec![record(
+                    &channel,
+                    Event::Kick {
+                        nick,
+                        target,
+                        reason,
+                    },
+                )]
+            }
+            "QUIT" => {
+                let reason = param(0);
+                self.remove_everywhere(&nick)
+                    .iter()
+                    .map(|channel| {
+                        let event = Event::Quit {
+                            nick: nick.clone(),
+                            userhost: userhost.clone(),
+                            reason: reason.clone(),
+                        };
+                        record(channel, event)
+                    })
+                    .collect()
+            }
+            "NICK" => {
+                let new = param(0);
+                if self.is_me(&nick) {
+                    self.nick = Some(new.clone());
+                }
+                let channels = self.remove_everywhere(&nick);
+                for channel in &channels {
+                    self.channels.get_mut(channel).unwrap().insert(fold(&new));
+                }
+                channels
+                    .iter()
+                    .map(|channel| {
+                        let event = Event::Nick {
+                            nick: nick.clone(),
+                            new: new.clone(),
+                        };
+                        record(channel, event)
+                    })
+                    .collect()
+            }
+            "TOPIC" => {
+                let topic = param(1);
+                vec![record(&param(0), Event::Topic { nick, topic })]
+            }
+            "MODE" if is_channel(&param(0)) => {
+                let modes = params[1..].join(" ");
+                vec![record(&param(0), Event::Mode { nick, modes })]
+            }
+            command @ ("PRIVMSG" | "NOTICE") if !nick.is_empty() => {
+                let target = param(0);
+                let buffer = if self.is_me(&target) {
+                    nick.clone()
+                } else if is_channel(&target) || self.is_me(&nick) {
+                    // Our own messages to a nick go to the query with them.
+                    target
+                } else {
+                    return Vec::new();
+                };
+                let text = param(1);
+                let event = match Ctcp::decode(&text) {
+                    Some(ctcp) if command == "PRIVMSG" && ctcp.command == "ACTION" => {
+                        Event::Action {
+                            nick,
+                            text: ctcp.argument,
+                        }
+                    }
+                    Some(_) => return Vec::new(),
+                    None if command == "NOTICE" => Event::Notice { nick, text },
+                    None => Event::Message { nick, text },
+                };
+                vec![record(&buffer, event)]
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Formats a time in UTC with the `strftime` conversions `%Y %m %d %H %M %S`,
+/// `%a` and `%b` for English day and month names, `%s` for Unix time and `%%`.
+pub fn format_time(time: SystemTime, format: &str) -> String {
+    let secs = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64) - 1,
+    };
+    let days = secs.div_euclid(86400);
+    let secs_of_day = secs.rem_euclid(86400);
+    let (year, month, day) = civil_from_days(days);
+
+    let mut out = String::with_capacity(format.len() + 8);
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        let _ = match chars.next() {
+            Some('Y') => write!(out, "{:04}", year),
+            Some('m') => write!(out, "{:02}", month),
+            Some('d') => write!(out, "{:02}", day),
+            Some('H') => write!(out, "{:02}", secs_of_day / 3600),
+            Some('M') => write!(out, "{:02}", secs_of_day % 3600 / 60),
+            Some('S') => write!(out, "{:02}", secs_of_day % 60),
+            // The epoch was a Thursday.
+            Some('a') => out.write_str(WEEKDAYS[(days + 4).rem_euclid(7) as usize]),
+            Some('b') => out.write_str(MONTHS[month as usize - 1]),
+            Some('s') => write!(out, "{}", secs),
+            Some('%') => out.write_str("%"),
+            Some(other) => write!(out, "%{}", other),
+            None => out.write_str("%"),
+        };
+    }
+    out
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c < ' ' => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A log format, named after the client whose default logs it follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// `12:00 <alice> hello`, with `-!-` lines for other events.
+    Irssi,
+    /// `2024-01-01 12:00:00\talice\thello`, tab separated.
+    Weechat,
+    /// `[12:00:00] <alice> hello`, with `***` lines for other events.
+    Znc,
+    /// A JSON object per line with an RFC 3339 `time`.
+    JsonLines,
+}
+
+impl Format {
+    /// The timestamp format used unless another is configured.
+    pub fn default_timestamp(&self) -> &'static str {
+        match self {
+            Format::Irssi => "%H:%M",
+            Format::Weechat => "%Y-%m-%d %H:%M:%S",
+            Format::Znc => "[%H:%M:%S]",
+            Format::JsonLines => "",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::JsonLines => "jsonl",
+            _ => "log",
+        }
+    }
+
+    /// Renders a record as a line without its line ending. JSON Lines
+    /// always use RFC 3339 times and ignore `timestamp`.
+    pub fn render(&self, record: &Record, timestamp: &str) -> String {
+        let body = match self {
+            Format::Irssi => irssi(record),
+            Format::Weechat => weechat(record),
+            Format::Znc => znc(&record.event),
+            Format::JsonLines => return json(record),
+        };
+        let separator = if *self == Format::Weechat { '\t' } else { ' ' };
+        format!(
+            "{}{}{}",
+            format_time(record.time, timestamp),
+            separator,
+            body
+        )
+    }
+}
+
+fn irssi(record: &Record) -> String {
+    let channel = &record.target;
+    match &record.event {
+        Event::Message { nick, text } => format!("<{}> {}", nick, text),
+        Event::Action { nick, text } => format!(" * {} {}", nick, text),
+        Event::Notice { nick, text } => format!("-{}- {}", nick, text),
+        Event::Join { nick, userhost } => {
+            format!("-!- {} [{}] has joined {}", nick, userhost, channel)
+        }
+        Event::Part {
+            nick,
+            userhost,
+            reason,
+        } => format!(
+            "-!- {} [{}] has left {} [{}]",
+            nick, userhost, channel, reason
+        ),
+        Event::Quit {
+            nick,
+            userhost,
+            reason,
+        } => format!("-!- {} [{}] has quit [{}]", nick, userhost, reason),
+        Event::Kick {
+            nick,
+            target,
+            reason,
+        } => format!(
+            "-!- {} was kicked from {} by {} [{}]",
+            target, channel, nick, reason
+        ),
+        Event::Nick { nick, new } => format!("-!- {} is now known as {}", nick, new),
+        Event::Topic { nick, topic } => {
+            format!(
+                "-!- {} changed the topic of {} to: {}",
+                nick, channel, topic
+            )
+        }
+        Event::Mode { nick, modes } => format!("-!- mode/{} [{}] by {}", channel, modes, nick),
+    }
+}
+
+fn weechat(record: &Record) -> String {
+    let channel = &record.target;
+    match &record.event {
+        Event::Message { nick, text } => format!("{}\t{}", nick, text),
+        Event::Action { nick, text } => format!(" *\t{} {}", nick, text),
+        Event::Notice { nick, text } => format!("--\tNotice({}): {}", nick, text),
+        Event::Join { nick, userhost } => {
+            format!("-->\t{} ({}) has joined {}", nick, userhost, channel)
+        }
+        Event::Part {
+            nick,
+            userhost,
+            reason,
+        } => format!(
+            "<--\t{} ({}) has left {} ({})",
+            nick, userhost, channel, reason
+        ),
+        Event::Quit {
+            nick,
+            userhost,
+            reason,
+        } => format!("<--\t{} ({}) has quit ({})", nick, userhost, reason),
+        Event::Kick {
+            nick,
+            target,
+            reason,
+        } => format!("<--\t{} has kicked {} ({})", nick, target, reason),
+        Event::Nick { nick, new } => format!("--\t{} is now known as {}", nick, new),
+        Event::Topic { nick, topic } => {
+            format!(
+                "--\t{} has changed topic for {} to \"{}\"",
+                nick, channel, topic
+            )
+        }
+        Event::Mode { nick, modes } => format!("--\tMode {} [{}] by {}", channel, modes, nick),
+    }
+}
+
+fn znc(event: &Event) -> String {
+    match event {
+        Event::Message { nick, text } => format!("<{}> {}", nick, text),
+        Event::Action { nick, text } => format!("* {} {}", nick, text),
+        Event::Notice { nick, text } => format!("-{}- {}", nick, text),
+        Event::Join { nick, userhost } => format!("*** Joins: {} ({})", nick, userhost),
+        Event::Part {
+            nick,
+            userhost,
+            reason,
+        } => format!("*** Parts: {} ({}) ({})", nick, userhost, reason),
+        Event::Quit {
+            nick,
+            userhost,
+            reason,
+        } => format!("*** Quits: {} ({}) ({})", nick, userhost, reason),
+        Event::Kick {
+            nick,
+            target,
+            reason,
+        } => format!("*** {} was kicked by {} ({})", target, nick, reason),
+        Event::Nick { nick, new } => format!("*** {} is now known as {}", nick, new),
+        Event::Topic { nick, topic } => format!("*** {} changes topic to '{}'", nick, topic),
+        Event::Mode { nick, modes } => format!("*** {} sets mode: {}", nick, modes),
+    }
+}
+
+fn json(record: &Record) -> String {
+    let (kind, fields): (&str, Vec<(&str, &str)>) = match &record.event {
+        Event::Message { nick, text } => ("message", vec![("nick", nick), ("text", text)]),
+        Event::Action { nick, text } => ("action", vec![("nick", nick), ("text", text)]),
+        Event::Notice { nick, text } => ("notice", vec![("nick", nick), ("text", text)]),
+        Event::Join { nick, userhost } => ("join", vec![("nick", nick), ("userhost", userhost)]),
+        Event::Part {
+            nick,
+            userhost,
+            reason,
+        } => (
+            "part",
+            vec![("nick", nick), ("userhost", userhost), ("reason", reason)],
+        ),
+        Event::Quit {
+            nick,
+            userhost,
+            reason,
+        } => (
+            "quit",
+            vec![("nick", nick), ("userhost", userhost), ("reason", reason)],
+        ),
+        Event::Kick {
+            nick,
+            target,
+            reason,
+        } => (
+            "kick",
+            vec![("nick", nick), ("target", target), ("reason", reason)],
+        ),
+        Event::Nick { nick, new } => ("nick", vec![("nick", nick), ("new", new)]),
+        Event::Topic { nick, topic } => ("topic", vec![("nick", nick), ("topic", topic)]),
+        Event::Mode { nick, modes } => ("mode", vec![("nick", nick), ("modes", modes)]),
+    };
+    let mut out = format!(
+        "{{\"time\":{},\"buffer\":{},\"event\":{}",
+        json_string(&format_rfc3339(record.time)),
+        json_string(&record.target),
+        json_string(kind)
+    );
+    for (key, value) in fields {
+        let _ = write!(out, ",{}:{}", json_string(key), json_string(value));
+    }
+    out.push('}');
+    out
+}
+
+/// Makes a buffer name safe to use as a file name.
+fn file_name(target: &str) -> String {
+    let name: String = fold(target)
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' | '%' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    match name.strip_prefix('.') {
+        Some(rest) => format!("_{}", rest),
+        None => name,
+    }
+}
+
+/// Writes the records of a connection to a log file per buffer.
+///
+/// Files are appended to and rotated as the path, which is formatted with
+/// the record's time, changes. Irssi logs start with a `--- Log opened` line.
+pub struct ChatLogger {
+    dir: PathBuf,
+    format: Format,
+    path: String,
+    timestamp: String,
+    tracker: Tracker,
+    /// Folded buffer name to the file it is logged to.
+    files: HashMap<String, Log>,
+    max_open: usize,
+    writes: u64,
+}
+
+/// A buffer's current log file, closed while idle.
+struct Log {
+    path: PathBuf,
+    file: Option<File>,
+    /// When it was last written to, counted in writes.
+    used: u64,
+}
+
+impl ChatLogger {
+    /// Logs under `dir` to `<buffer>/<YYYY-MM-DD>.log`, or `.jsonl`.
+    pub fn new<P: AsRef<Path>>(dir: P, format: Format) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            format,
+            path: format!("{{target}}/%Y-%m-%d.{}", format.extension()),
+            timestamp: format.default_timestamp().to_string(),
+            tracker: Tracker::new(),
+            files: HashMap::new(),
+            max_open: 32,
+            writes: 0,
+        }
+    }
+
+    /// Sets the path of a buffer's log relative to the directory, where
+    /// `{target}` is the buffer name and the rest goes through [`format_time`],
+    /// e.g. `{target}.log` to never rotate.
+    pub fn path_format(mut self, path: &str) -> Self {
+        self.path = path.to_string();
+        self
+    }
+
+    /// Sets the timestamp format, see [`format_time`].
+    pub fn timestamp_format(mut self, timestamp: &str) -> Self {
+        self.timestamp = timestamp.to_string();
+        self
+    }
+
+    /// Sets how many log files are kept open, 32 by default. The one written
+    /// to least recently is closed first, and reopened when needed again.
+    pub fn max_open_files(mut self, max: usize) -> Self {
+        self.max_open = max.max(1);
+        self
+    }
+
+    pub fn tracker(&self) -> &Tracker {
+        &self.tracker
+    }
+
+    pub fn tracker_mut(&mut self) -> &mut Tracker {
+        &mut self.tracker
+    }
+
+    /// Logs a received message, or a sent one with our prefix.
+    pub fn log(&mut self, msg: &ParsedMessage) -> Result<()> {
+        for record in self.tracker.records(msg) {
+            self.write(&record)?;
+        }
+        Ok(())
+    }
+
+    /// Appends a record to its buffer's log.
+    pub fn write(&mut self, record: &Record) -> Result<()> {
+        // Times are formatted first, so a `%` in a buffer name is never a conversion.
+        let relative = format_time(record.time, &self.path);
+        let path = self
+            .dir
+            .join(relative.replace("{target}", &file_name(&record.target)));
+        let key = fold(&record.target);
+        let rotated = self.files.get(&key).is_none_or(|log| log.path != path);
+        if rotated {
+            // Closes the file the buffer logged to before.
+            self.files.remove(&key);
+        }
+        if self.files.get(&key).is_none_or(|log| log.file.is_none()) {
+            self.close_idle();
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+            if rotated && self.format == Format::Irssi {
+                let opened = format_time(record.time, "%a %b %d %H:%M:%S %Y");
+                writeln!(file, "--- Log opened {}", opened)?;
+            }
+            let log = Log {
+                path,
+                file: Some(file),
+                used: 0,
+            };
+            self.files.insert(key.clone(), log);
+        }
+        self.writes += 1;
+        let log = self.files.get_mut(&key).unwrap();
+        log.used = self.writes;
+        let line = format!("{}\n", self.format.render(record, &self.timestamp));
+        log.file.as_mut().unwrap().write_all(line.as_bytes())
+    }
+
+    /// Closes the file written to least recently once `max_open` are open.
+    fn close_idle(&mut self) {
+        let mut open: Vec<&mut Log> = (self.files.values_mut())
+            .filter(|log| log.file.is_some())
+            .collect();
+        if open.len() >= self.max_open {
+            open.sort_by_key(|log| log.used);
+            open[0].file = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // 2024-03-05T14:07:09Z, a Tuesday.
+    const TIME: u64 = 1709647629;
+
+    fn parse(line: &str) -> ParsedMessage {
+        ParsedMessage::parse(line.to_string())
+    }
+
+    #[test]
+    fn test_format_time() {
+        let time = UNIX_EPOCH + Duration::from_secs(TIME);
+        assert_eq!(
+            format_time(time, "%a %b %d %Y %H:%M:%S %% %q"),
+            "Tue Mar 05 2024 14:07:09 % %q"
+        );
+        assert_eq!(format_time(time, "%s"), TIME.to_string());
+    }
+
+    #[test]
+    fn test_tracker() {
+        let mut tracker = Tracker::new();
+        for line in [
+            ":irc.test 001 me :Welcome",
+            ":me!u@h JOIN #a",
+            ":me!u@h JOIN #b",
+            ":irc.test 353 me = #a :@alice me",
+            ":bob!b@h JOIN #b",
+        ] {
+            assert!(tracker.records(&parse(line)).len() <= 1);
+        }
+        let targets = |records: Vec<Record>| -> Vec<String> {
+            records.into_iter().map(|record| record.target).collect()
+        };
+        assert_eq!(
+            targets(tracker.records(&parse(":ALICE!a@h NICK carol"))),
+            ["#a"]
+        );
+        assert_eq!(targets(tracker.records(&parse(":bob!b@h PART #b"))), ["#b"]);
+        assert_eq!(
+            targets(tracker.records(&parse(":me!u@h NICK you"))),
+            ["#a", "#b"]
+        );
+        assert_eq!(tracker.nick(), Some("you"));
+        assert_eq!(
+            targets(tracker.records(&parse(":carol!a@h QUIT :bye"))),
+            ["#a"]
+        );
+        assert_eq!(
+            targets(tracker.records(&parse(":dave!d@h PRIVMSG you :hi"))),
+            ["dave"]
+        );
+        assert_eq!(
+            targets(tracker.records(&parse(":you!u@h PRIVMSG dave :hello"))),
+            ["dave"]
+        );
+        assert!(tracker
+            .records(&parse(":dave!d@h PRIVMSG you :\x01VERSION\x01"))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_render() {
+        let mut tracker = Tracker::new();
+        tracker.set_nick("me");
+        let render = |tracker: &mut Tracker, format: Format, line: &str| {
+            let line = format!("@time=2024-03-05T14:07:09.000Z {}", line);
+            let record = tracker.records(&parse(&line)).remove(0);
+            format.render(&record, format.default_timestamp())
+        };
+        let join = ":alice!al@host JOIN #chan";
+        let action = ":alice!al@host PRIVMSG #chan :\x01ACTION waves\x01";
+        let topic = ":alice!al@host TOPIC #chan :a \"topic\"";
+        assert_eq!(
+            render(&mut tracker, Format::Irssi, join),
+            "14:07 -!- alice [al@host] has joined #chan"
+        );
+        assert_eq!(
+            render(&mut tracker, Format::Weechat, action),
+            "2024-03-05 14:07:09\t *\talice waves"
+        );
+        assert_eq!(
+            render(&mut tracker, Format::Znc, topic),
+            "[14:07:09] *** alice changes topic to 'a \"topic\"'"
+        );
+        assert_eq!(
+            render(&mut tracker, Format::JsonLines, topic),
+            r##"{"time":"2024-03-05T14:07:09.000Z","buffer":"#chan","event":"topic","nick":"alice","topic":"a \"topic\""}"##
+        );
+    }
+
+    #[test]
+    fn test_logger() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("tiny-irc-chatlog-{}", std::process::id()));
+        let mut logger = ChatLogger::new(&dir, Format::Irssi);
+        logger.tracker_mut().set_nick("me");
+        for line in [
+            "@time=2024-03-05T23:59:00.000Z :alice!a@h PRIVMSG #Chan :late",
+            "@time=2024-03-06T00:01:00.000Z :alice!a@h PRIVMSG #chan :early",
+            "@time=2024-03-06T00:02:00.000Z :bob!b@h PRIVMSG me :psst",
+        ] {
+            logger.log(&parse(line))?;
+        }
+        let read = |path: &str| fs::read_to_string(dir.join(path));
+        assert_eq!(
+            read("#chan/2024-03-05.log")?,
+            "--- Log opened Tue Mar 05 23:59:00 2024\n23:59 <alice> late\n"
+        );
+        assert_eq!(
+            read("#chan/2024-03-06.log")?,
+            "--- Log opened Wed Mar 06 00:01:00 2024\n00:01 <alice> early\n"
+        );
+        assert!(read("bob/2024-03-06.log")?.ends_with("00:02 <bob> psst\n"));
+        fs::remove_dir_all(&dir)
+    }
+
+    #[test]
+    fn test_logger_open_files() -> Result<()> {
+        let dir =
+            std::env::temp_dir().join(format!("tiny-irc-chatlog-open-{}", std::process::id()));
+        let mut logger = ChatLogger::new(&dir, Format::Irssi)
+            .path_format("{target}-%Y.log")
+            .max_open_files(2);
+        logger.tracker_mut().set_nick("me");
+        for line in [
+            "@time=2024-03-05T10:00:00.000Z :alice!a@h PRIVMSG #a :one",
+            "@time=2024-03-05T10:01:00.000Z :alice!a@h PRIVMSG #b :two",
+            "@time=2024-03-05T10:02:00.000Z :alice!a@h PRIVMSG #c :three",
+            "@time=2024-03-05T10:03:00.000Z :alice!a@h PRIVMSG #a :four",
+            "@time=2024-03-05T10:04:00.000Z :alice!a@h PRIVMSG #100%d :five",
+        ] {
+            logger.log(&parse(line))?;
+            let open = logger.files.values().filter(|log| log.file.is_some());
+            assert!(open.count() <= 2);
+        }
+        let read = |path: &str| fs::read_to_string(dir.join(path));
+        assert_eq!(
+            read("#a-2024.log")?,
+            "--- Log opened Tue Mar 05 10:00:00 2024\n10:00 <alice> one\n10:03 <alice> four\n"
+        );
+        assert!(read("#100_d-2024.log")?.ends_with("10:04 <alice> five\n"));
+        fs::remove_dir_all(&dir)
+    }
+}
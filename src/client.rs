@@ -0,0 +1,493 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{Error, ErrorKind, Result};
+use std::time::Instant;
+
+use futures::channel::oneshot;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::batch::{Batch, BatchCollector, Collected};
+use crate::ctcp::CtcpResponder;
+use crate::message::{prelude::*, ParsedMessage};
+use crate::nick::NickManager;
+use crate::parser::Parser;
+
+/// The server's answer to a labeled request.
+#[derive(Debug)]
+pub enum Response {
+    Message(ParsedMessage),
+    Batch(Batch),
+    /// The command was processed but produced no other reply.
+    Ack(ParsedMessage),
+}
+
+/// Resolves once the response to a labeled request has been received.
+pub type PendingResponse = oneshot::Receiver<Response>;
+
+pub struct Client<R, W> {
+    reader: R,
+    writer: W,
+    parser: Parser,
+    caps: HashSet<String>,
+    next_label: u64,
+    pending: HashMap<String, oneshot::Sender<Response>>,
+    batches: BatchCollector,
+    queue: VecDeque<ParsedMessage>,
+    ctcp: Option<CtcpResponder>,
+    nick: Option<NickManager>,
+    replies: VecDeque<String>,
+    /// Bytes still to be written, kept here so a cancelled write resumes where it stopped.
+    outgoing: Vec<u8>,
+    unflushed: bool,
+    parse_errors: usize,
+}
+
+impl<R, W> Client<R, W>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite + Unpin,
+{
+    pub fn new(reader: R, writer: W) -> Self {
+        Self {
+            reader,
+            writer,
+            parser: Parser::new(),
+            caps: HashSet::new(),
+            next_label: 0,
+            pending: HashMap::new(),
+            batches: BatchCollector::new(),
+            queue: VecDeque::new(),
+            ctcp: None,
+            nick: None,
+            replies: VecDeque::new(),
+            outgoing: Vec::new(),
+            unflushed: false,
+            parse_errors: 0,
+        }
+    }
+
+    /// Answers CTCP queries automatically while receiving, `None` turns it off.
+    pub fn set_ctcp_responder(&mut self, responder: Option<CtcpResponder>) {
+        self.ctcp = responder;
+    }
+
+    /// Handles nick collisions and regains the primary nick while receiving,
+    /// `None` turns it off. Its events are read with [`Client::nick_manager`].
+    pub fn set_nick_manager(&mut self, manager: Option<NickManager>) {
+        self.nick = manager;
+    }
+
+    pub fn nick_manager(&mut self) -> Option<&mut NickManager> {
+        self.nick.as_mut()
+    }
+
+    /// Returns the underlying reader and writer.
+    /// Input buffered but not yet parsed is lost.
+    pub fn into_inner(self) -> (R, W) {
+        (self.reader, self.writer)
+    }
+
+    /// Whether the server acknowledged the capability.
+    pub fn has_cap(&self, cap: &str) -> bool {
+        self.caps.contains(cap)
+    }
+
+    /// How many times the parser dropped input, an over-long line or one that
+    /// didn't fit in its buffer. Receiving skips these.
+    pub fn parse_errors(&self) -> usize {
+        self.parse_errors
+    }
+
+    /// Sends a single line, appending the line ending.
+    /// If the future is dropped, the line is still sent by the next call that writes.
+    pub async fn send(&mut self, line: &str) -> Result<()> {
+        self.outgoing.extend_from_slice(line.as_bytes());
+        self.outgoing.extend_from_slice(b"\r\n");
+        self.write_outgoing().await
+    }
+
+    /// Writes the queued bytes. Each `write` either completes or writes
+    /// nothing, so this can be cancelled without cutting a line short.
+    async fn write_outgoing(&mut self) -> Result<()> {
+        while !self.outgoing.is_empty() {
+            let n = self.writer.write(&self.outgoing).await?;
+            if n == 0 {
+                return Err(Error::from(ErrorKind::WriteZero));
+            }
+            self.outgoing.drain(..n);
+            self.unflushed = true;
+        }
+        if self.unflushed {
+            self.writer.flush().await?;
+            self.unflushed = false;
+        }
+        Ok(())
+    }
+
+    pub async fn cap_req(&mut self, caps: &[&str]) -> Result<()> {
+        self.send(&format!("CAP REQ :{}", caps.join(" "))).await
+    }
+
+    /// Sends a line with a fresh `label` tag attached.
+    /// The returned response resolves while messages are being received.
+    pub async fn send_labeled(&mut self, line: &str) -> Result<PendingResponse> {
+        if !self.has_cap("labeled-response") {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "labeled-response capability not enabled",
+            ));
+        }
+
+        let label = self.next_label.to_string();
+        self.next_label += 1;
+
+        let line = match line.strip_prefix('@') {
+            Some(rest) => format!("@label={};{}", label, rest),
+            None => format!("@label={} {}", label, line),
+        };
+
+        let (tx, rx) = oneshot::channel();
+        self.pending.insert(label, tx);
+        self.send(&line).await?;
+        Ok(rx)
+    }
+
+    /// Sends a labeled line and waits for its response.
+    /// Unrelated messages received meanwhile are kept for [`Client::recv`].
+    pub async fn request(&mut self, line: &str) -> Result<Response> {
+        let mut response = self.send_labeled(line).await?;
+        loop {
+            if let Ok(Some(response)) = response.try_recv() {
+                return Ok(response);
+            }
+            match self.read_message().await? {
+                Some(msg) => {
+                    let msg = self.dispatch(msg);
+                    self.flush_replies().await?;
+                    if let Some(msg) = msg {
+                        self.queue.push_back(msg);
+                    }
+                }
+                None => return Err(Error::from(ErrorKind::UnexpectedEof)),
+            }
+        }
+    }
+
+    /// Receives the next message not consumed as a labeled response.
+    /// Returns `None` once the connection is closed.
+    ///
+    /// Cancel-safe: when the future is dropped, e.g. by a timeout, a message
+    /// already read is kept for the next call and automatic replies are
+    /// finished by it.
+    pub async fn recv(&mut self) -> Result<Option<ParsedMessage>> {
+        loop {
+            self.flush_replies().await?;
+            if let Some(msg) = self.queue.pop_front() {
+                return Ok(Some(msg));
+            }
+            match self.read_message().await? {
+                Some(msg) => {
+                    if let Some(msg) = self.dispatch(msg) {
+                        self.queue.push_back(msg);
+                    }
+                }
+                None => return Ok(None),
+            }
+        }
+    }
+
+    async fn flush_replies(&mut self) -> Result<()> {
+        while let Some(reply) = self.replies.pop_front() {
+            self.outgoing.extend_from_slice(reply.as_bytes());
+            self.outgoing.extend_from_slice(b"\r\n");
+        }
+        self.write_outgoing().await
+    }
+
+    async fn read_message(&mut self) -> Result<Option<ParsedMessage>> {
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.parser.next_event() {
+                Some(Ok(msg)) => return Ok(Some(msg)),
+                Some(Err(_)) => self.parse_errors += 1,
+                None => {
+                    let n = self.reader.read(&mut buf).await?;
+                    if n == 0 {
+                        return Ok(None);
+                    }
+                    self.parser.push_buf(&buf[..n]);
+                }
+            }
+        }
+    }
+
+    fn dispatch(&mut self, msg: ParsedMessage) -> Option<ParsedMessage> {
+        let command = msg.command();
+        let label = msg.tag("label");
+
+        if command == "CAP" {
+            let params = msg.params();
+            if params.len() >= 3 && params[1] == "ACK" {
+                for cap in params[2].split_whitespace() {
+                    match cap.strip_prefix('-') {
+                        Some(cap) => self.caps.remove(cap),
+                        None => self.caps.insert(cap.to_string()),
+                    };
+                }
+            }
+        }
+
+        if let Some(ctcp) = &mut self.ctcp {
+            if let Some(reply) = ctcp.respond(&msg) {
+                self.replies.push_back(reply);
+            }
+        }
+
+        if let Some(nick) = &mut self.nick {
+            self.replies.extend(nick.handle(&msg));
+            self.replies.extend(nick.poll(Instant::now()));
+        }
+
+        let collect = if command == "BATCH" {
+            match msg.params().first() {
+                Some(reference) if reference.starts_with('+') => {
+                    let labeled = matches!(&label, Some(label) if self.pending.contains_key(label));
+                    let nested =
+                        matches!(msg.tag("batch"), Some(parent) if self.batches.is_open(&parent));
+                    labeled || nested
+                }
+                Some(reference) => self.batches.is_open(reference.trim_start_matches('-')),
+                None => false,
+            }
+        } else {
+            matches!(msg.tag("batch"), Some(reference) if self.batches.is_open(&reference))
+        };
+
+        if collect {
+            if let Some(Collected::Batch(batch)) = self.batches.push(msg) {
+                if let Some(label) = batch.start.tag("label") {
+                    self.resolve(&label, Response::Batch(batch));
+                }
+            }
+            return None;
+        }
+
+        if let Some(label) = label {
+            if self.pending.contains_key(&label) {
+                let response = if command == "ACK" {
+                    Response::Ack(msg)
+                } else {
+                    Response::Message(msg)
+                };
+                self.resolve(&label, response);
+                return None;
+            }
+        }
+
+        Some(msg)
+    }
+
+    fn resolve(&mut self, label: &str, response: Response) {
+        if let Some(tx) = self.pending.remove(label) {
+            let _ = tx.send(response);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+
+    #[test]
+    fn test_without_runtime() {
+        let input = Cursor::new(b":nick!user@host PRIVMSG test :\x01PING 1234\x01\r\n".to_vec());
+        let mut client = Client::new(input, Vec::new());
+        client.set_ctcp_responder(Some(CtcpResponder::new()));
+        block_on(async {
+            assert_eq!(client.recv().await.unwrap().unwrap().command(), "PRIVMSG");
+            assert_eq!(client.recv().await.unwrap(), None);
+        });
+        let (_, output) = client.into_inner();
+        assert_eq!(output, b"NOTICE nick :\x01PING 1234\x01\r\n");
+    }
+
+    #[test]
+    fn test_nick_manager() {
+        let input = Cursor::new(
+            concat!(
+                ":irc.example.com 433 * test :Nickname is already in use\r\n",
+                ":irc.example.com 001 test_ :Welcome\r\n",
+            )
+            .as_bytes()
+            .to_vec(),
+        );
+        let mut client = Client::new(input, Vec::new());
+        client.set_nick_manager(Some(NickManager::new("test")));
+        block_on(async { while client.recv().await.unwrap().is_some() {} });
+        let manager = client.nick_manager().unwrap();
+        assert_eq!(manager.current(), Some("test_"));
+        let (_, output) = client.into_inner();
+        assert_eq!(output, b"NICK test_\r\n");
+    }
+}
+
+#[cfg(all(test, feature = "tokio"))]
+mod tokio_tests {
+    use super::*;
+    use crate::runtime::tokio::client;
+    use std::time::Duration;
+    use tokio::io::{duplex, split, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+
+    #[tokio::test]
+    async fn test_labeled_batch() {
+        let (stream, server) = duplex(4096);
+        let (reader, writer) = split(stream);
+        let mut client = client(reader, writer);
+        let (server_reader, mut server_writer) = split(server);
+        let mut server_reader = BufReader::new(server_reader).lines();
+
+        client
+            .cap_req(&["batch", "labeled-response"])
+            .await
+            .unwrap();
+        assert_eq!(
+            server_reader.next_line().await.unwrap().unwrap(),
+            "CAP REQ :batch labeled-response"
+        );
+        server_writer
+            .write_all(b":irc.example.com CAP * ACK :batch labeled-response\r\n")
+            .await
+            .unwrap();
+        assert_eq!(client.recv().await.unwrap().unwrap().command(), "CAP");
+        assert!(client.has_cap("labeled-response"));
+
+        let mut response = client.send_labeled("WHO #channel").await.unwrap();
+        assert_eq!(
+            server_reader.next_line().await.unwrap().unwrap(),
+            "@label=0 WHO #channel"
+        );
+        server_writer
+            .write_all(
+                concat!(
+                    "@label=0 :irc.example.com BATCH +who labeled-response\r\n",
+                    "@batch=who :irc.example.com 352 test #channel u h s nick H :0 Real\r\n",
+                    ":nick!user@host PRIVMSG #channel :Unrelated\r\n",
+                    "@batch=who :irc.example.com 315 test #channel :End of WHO\r\n",
+                    ":irc.example.com BATCH -who\r\n",
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        let msg = client.recv().await.unwrap().unwrap();
+        assert_eq!(msg.command(), "PRIVMSG");
+        assert!(response.try_recv().unwrap().is_none());
+
+        drop(server_reader);
+        drop(server_writer);
+        assert_eq!(client.recv().await.unwrap(), None);
+        match response.try_recv().unwrap() {
+            Some(Response::Batch(batch)) => assert_eq!(batch.messages().len(), 2),
+            other => panic!("unexpected response {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_ack() {
+        let (stream, server) = duplex(4096);
+        let (reader, writer) = split(stream);
+        let mut client = client(reader, writer);
+        let (_server_reader, mut server_writer) = split(server);
+
+        server_writer
+            .write_all(b":irc.example.com CAP * ACK :labeled-response\r\n")
+            .await
+            .unwrap();
+        assert_eq!(client.recv().await.unwrap().unwrap().command(), "CAP");
+
+        server_writer
+            .write_all(b"@label=0 :irc.example.com ACK\r\n")
+            .await
+            .unwrap();
+        assert!(matches!(
+            client
+                .request("@+draft/typing=active TAGMSG #channel")
+                .await
+                .unwrap(),
+            Response::Ack(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_request_skips_overlong_line() {
+        let (stream, server) = duplex(4096);
+        let (reader, writer) = split(stream);
+        let mut client = client(reader, writer);
+        let (_server_reader, mut server_writer) = split(server);
+
+        server_writer
+            .write_all(b":irc.example.com CAP * ACK :labeled-response\r\n")
+            .await
+            .unwrap();
+        assert_eq!(client.recv().await.unwrap().unwrap().command(), "CAP");
+
+        tokio::spawn(async move {
+            let mut input = format!("PRIVMSG #c :{}\r\n", "a".repeat(10_000));
+            input.push_str("@label=0 :irc.example.com ACK\r\n");
+            server_writer.write_all(input.as_bytes()).await.unwrap();
+            server_writer
+        });
+        assert!(matches!(
+            client.request("TAGMSG #channel").await.unwrap(),
+            Response::Ack(_)
+        ));
+        assert_eq!(client.parse_errors(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_recv_cancelled() {
+        let (stream, mut server_writer) = duplex(4096);
+        // Small enough that the CTCP reply blocks halfway.
+        let (mut server_reader, writer) = duplex(8);
+        let mut client = client(stream, writer);
+        client.set_ctcp_responder(Some(CtcpResponder::new()));
+
+        server_writer
+            .write_all(b":nick!user@host PRIVMSG test :\x01PING 1234\x01\r\n")
+            .await
+            .unwrap();
+        let recv = tokio::time::timeout(Duration::from_secs(1), client.recv());
+        assert!(recv.await.is_err());
+
+        let reply = tokio::spawn(async move {
+            let mut reply = Vec::new();
+            server_reader.read_to_end(&mut reply).await.unwrap();
+            reply
+        });
+        assert_eq!(client.recv().await.unwrap().unwrap().command(), "PRIVMSG");
+        drop(client);
+        assert_eq!(reply.await.unwrap(), b"NOTICE nick :\x01PING 1234\x01\r\n");
+    }
+
+    #[tokio::test]
+    async fn test_ctcp_responder() {
+        let (stream, server) = duplex(4096);
+        let (reader, writer) = split(stream);
+        let mut client = client(reader, writer);
+        client.set_ctcp_responder(Some(CtcpResponder::new()));
+        let (server_reader, mut server_writer) = split(server);
+        let mut server_reader = BufReader::new(server_reader).lines();
+
+        server_writer
+            .write_all(b":nick!user@host PRIVMSG test :\x01PING 1234\x01\r\n")
+            .await
+            .unwrap();
+        assert_eq!(client.recv().await.unwrap().unwrap().command(), "PRIVMSG");
+        assert_eq!(
+            server_reader.next_line().await.unwrap().unwrap(),
+            "NOTICE nick :\x01PING 1234\x01"
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//! The ircdocs parser-tests vectors, vendored in `tests/parser-tests`.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::message::{is_valid_hostname, prelude::*, MessageBuilder, ParsedMessage};
+use crate::parser::{Parser, ParserConfig};
+
+#[derive(Debug, Deserialize)]
+struct Suite<T> {
+    tests: Vec<T>,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize)]
+struct Atoms {
+    #[serde(default)]
+    tags: HashMap<String, String>,
+    source: Option<String>,
+    verb: String,
+    #[serde(default)]
+    params: Vec<String>,
+}
+
+impl Atoms {
+    fn of(msg: &ParsedMessage) -> Self {
+        let tags = msg
+            .tags()
+            .into_iter()
+            .map(|(key, _)| {
+                let value = msg.tag(&key).unwrap();
+                (key, value)
+            })
+            .collect();
+        Self {
+            tags,
+            source: msg.prefix(),
+            verb: msg.command(),
+            params: msg.params(),
+        }
+    }
+
+    fn builder(&self) -> MessageBuilder {
+        let mut tags: Vec<_> = self.tags.iter().collect();
+        tags.sort();
+        let mut builder = MessageBuilder::new(&self.verb);
+        for (key, value) in tags {
+            builder = builder.tag(key, value);
+        }
+        if let Some(source) = &self.source {
+            builder = builder.prefix(source);
+        }
+        for param in &self.params {
+            builder = builder.param(param);
+        }
+        builder
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Split {
+    input: String,
+    atoms: Atoms,
+}
+
+#[derive(Debug, Deserialize)]
+struct Join {
+    desc: String,
+    atoms: Atoms,
+    matches: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserHost {
+    source: String,
+    atoms: UserHostAtoms,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+struct UserHostAtoms {
+    nick: Option<String>,
+    user: Option<String>,
+    host: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Hostname {
+    host: String,
+    valid: bool,
+}
+
+fn load<T: for<'de> Deserialize<'de>>(yaml: &str) -> Vec<T> {
+    serde_yaml::from_str::<Suite<T>>(yaml).unwrap().tests
+}
+
+fn parse(line: &str) -> ParsedMessage {
+    let mut parser = Parser::new();
+    parser.push(format!("{}\r\n", line));
+    parser
+        .next()
+        .unwrap_or_else(|| panic!("failed to parse {:?}", line))
+}
+
+#[test]
+fn test_msg_split() {
+    for test in load::<Split>(include_str!("../tests/parser-tests/msg-split.yaml")) {
+        let msg = parse(&test.input);
+        assert_eq!(Atoms::of(&msg), test.atoms, "{:?}", test.input);
+
+        // Serializing what was parsed has to give back the same atoms.
+        let line = MessageBuilder::from(&msg).build();
+        assert_eq!(Atoms::of(&parse(&line)), test.atoms, "{:?}", line);
+    }
+}
+
+/// `ParsedMessage::parse_with` and `Parser` share one engine and must agree,
+/// however the stream is chunked.
+#[test]
+fn test_one_shot_matches_stream() {
+    let mut lines: Vec<String> =
+        load::<Split>(include_str!("../tests/parser-tests/msg-split.yaml"))
+            .into_iter()
+            .map(|test| format!("{}\r\n", test.input))
+            .collect();
+    for line in [
+        "\r\n",
+        " \r\n",
+        ":src\r\n",
+        "@tags\r\n",
+        "@a :b\r\n",
+        "PING\n",
+        "  PING x\n",
+        "CMD a\rb\r\n",
+        "CMD  a  :b \r\n",
+        "x\u{e9}y z :\u{e9}\r\n",
+    ] {
+        lines.push(line.to_string());
+    }
+
+    for config in [ParserConfig::strict(), ParserConfig::lenient()] {
+        for line in &lines {
+            let one_shot = ParsedMessage::parse_with(line.clone(), config);
+            for chunk_size in [1, 7, line.len()] {
+                let mut parser = Parser::with_config(config);
+                let mut streamed = Vec::new();
+                for chunk in line.as_bytes().chunks(chunk_size) {
+                    parser.push_buf(chunk);
+                    streamed.extend(&mut parser);
+                }
+                let streamed = streamed.pop();
+                assert_eq!(
+                    one_shot
+                        .as_ref()
+                        .map(|msg| (Atoms::of(msg), msg.leniencies())),
+                    streamed
+                        .as_ref()
+                        .map(|msg| (Atoms::of(msg), msg.leniencies())),
+                    "{:?} {:?}",
+                    config,
+                    line
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn test_msg_join() {
+    for test in load::<Join>(include_str!("../tests/parser-tests/msg-join.yaml")) {
+        let line = test.atoms.builder().build();
+        assert!(test.matches.contains(&line), "{}: {:?}", test.desc, line);
+        for line in &test.matches {
+            assert_eq!(Atoms::of(&parse(line)), test.atoms, "{}", test.desc);
+        }
+    }
+}
+
+#[test]
+fn test_userhost_split() {
+    for test in load::<UserHost>(include_str!("../tests/parser-tests/userhost-split.yaml")) {
+        let msg = parse(&format!(":{} PRIVMSG #channel :hi", test.source));
+        let atoms = UserHostAtoms {
+            nick: msg.nick(),
+            user: msg.user(),
+            host: msg.host(),
+        };
+        assert_eq!(atoms, test.atoms, "{:?}", test.source);
+        assert_eq!(msg.prefix().as_ref(), Some(&test.source));
+    }
+}
+
+#[test]
+fn test_validate_hostname() {
+    for test in load::<Hostname>(include_str!("../tests/parser-tests/validate-hostname.yaml")) {
+        assert_eq!(is_valid_hostname(&test.host), test.valid, "{:?}", test.host);
+    }
+}
@@ -0,0 +1,257 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::message::{format_rfc3339, prelude::*, ParsedMessage};
+
+const DELIM: char = '\x01';
+const M_QUOTE: char = '\x10';
+const X_QUOTE: char = '\\';
+
+/// A CTCP query or reply, e.g. `\x01ACTION waves\x01`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Ctcp {
+    pub command: String,
+    pub argument: String,
+}
+
+impl Ctcp {
+    pub fn new(command: &str, argument: &str) -> Self {
+        Self {
+            command: command.to_ascii_uppercase(),
+            argument: argument.to_string(),
+        }
+    }
+
+    pub fn action(text: &str) -> Self {
+        Self::new("ACTION", text)
+    }
+
+    /// Decodes a PRIVMSG/NOTICE body, `None` if it isn't a CTCP message.
+    /// The closing `\x01` is optional as some clients omit it.
+    pub fn decode(text: &str) -> Option<Self> {
+        let text = low_level_dequote(text);
+        let body = text.strip_prefix(DELIM)?;
+        let body = body.strip_suffix(DELIM).unwrap_or(body);
+        let body = ctcp_dequote(body);
+        let (command, argument) = body.split_once(' ').unwrap_or((&body, ""));
+        if command.is_empty() {
+            return None;
+        }
+        Some(Self {
+            command: command.to_ascii_uppercase(),
+            argument: argument.to_string(),
+        })
+    }
+
+    /// Encodes the CTCP payload with both levels of quoting applied.
+    pub fn encode(&self) -> String {
+        let mut body = self.command.clone();
+        if !self.argument.is_empty() {
+            body.push(' ');
+            body.push_str(&self.argument);
+        }
+        format!("{}{}{}", DELIM, low_level_quote(&ctcp_quote(&body)), DELIM)
+    }
+
+    /// A line sending this CTCP as a query to `target`.
+    pub fn request(&self, target: &str) -> String {
+        format!("PRIVMSG {} :{}", target, self.encode())
+    }
+
+    /// A line sending this CTCP as a reply to `target`.
+    pub fn reply(&self, target: &str) -> String {
+        format!("NOTICE {} :{}", target, self.encode())
+    }
+
+    /// The CTCP carried by a PRIVMSG or NOTICE.
+    pub fn from_message(msg: &ParsedMessage) -> Option<Self> {
+        match msg.command().as_str() {
+            "PRIVMSG" | "NOTICE" => Self::decode(msg.params().get(1)?),
+            _ => None,
+        }
+    }
+}
+
+fn low_level_quote(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\0' => out.push_str("\x100"),
+            '\n' => out.push_str("\x10n"),
+            '\r' => out.push_str("\x10r"),
+            M_QUOTE => out.push_str("\x10\x10"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn low_level_dequote(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != M_QUOTE {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('0') => out.push('\0'),
+            Some('n') => out.push('\n'),
+            Some('r') => out.push('\r'),
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+fn ctcp_quote(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            DELIM => out.push_str("\\a"),
+            X_QUOTE => out.push_str("\\\\"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn ctcp_dequote(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != X_QUOTE {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('a') => out.push(DELIM),
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+/// Answers VERSION, PING, TIME, CLIENTINFO and SOURCE queries.
+/// Replies are limited to `max_replies` per `per` to avoid being used for CTCP floods.
+#[derive(Debug, Clone)]
+pub struct CtcpResponder {
+    pub version: String,
+    pub source: Option<String>,
+    pub max_replies: usize,
+    pub per: Duration,
+    sent: VecDeque<Instant>,
+}
+
+impl Default for CtcpResponder {
+    fn default() -> Self {
+        Self {
+            version: format!("tiny-irc {}", env!("CARGO_PKG_VERSION")),
+            source: None,
+            max_replies: 3,
+            per: Duration::from_secs(10),
+            sent: VecDeque::new(),
+        }
+    }
+}
+
+impl CtcpResponder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The reply line for a CTCP query, if one should be sent.
+    pub fn respond(&mut self, msg: &ParsedMessage) -> Option<String> {
+        self.respond_at(msg, Instant::now())
+    }
+
+    pub fn respond_at(&mut self, msg: &ParsedMessage, now: Instant) -> Option<String> {
+        if msg.command() != "PRIVMSG" {
+            return None;
+        }
+        let query = Ctcp::from_message(msg)?;
+        let argument = match query.command.as_str() {
+            "VERSION" => self.version.clone(),
+            "PING" => query.argument.clone(),
+            "TIME" => format_rfc3339(SystemTime::now()),
+            "CLIENTINFO" => {
+                let mut commands = vec!["ACTION", "CLIENTINFO", "PING", "TIME", "VERSION"];
+                if self.source.is_some() {
+                    commands.push("SOURCE");
+                }
+                commands.join(" ")
+            }
+            "SOURCE" => self.source.clone()?,
+            _ => return None,
+        };
+
+        while let Some(&sent) = self.sent.front() {
+            if now.duration_since(sent) < self.per {
+                break;
+            }
+            self.sent.pop_front();
+        }
+        if self.sent.len() >= self.max_replies {
+            return None;
+        }
+        self.sent.push_back(now);
+
+        Some(Ctcp::new(&query.command, &argument).reply(&msg.nick()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let msg =
+            ParsedMessage::parse(":nick!u@h PRIVMSG #channel :\x01ACTION waves\x01".to_string());
+        assert_eq!(Ctcp::from_message(&msg), Some(Ctcp::action("waves")));
+
+        assert_eq!(Ctcp::decode("\x01VERSION"), Some(Ctcp::new("VERSION", "")));
+        assert_eq!(
+            Ctcp::decode("\x01PING 123 \\a\x10n\x01"),
+            Some(Ctcp::new("PING", "123 \x01\n"))
+        );
+        assert_eq!(Ctcp::decode("hello"), None);
+        assert_eq!(Ctcp::decode("\x01\x01"), None);
+    }
+
+    #[test]
+    fn test_encode() {
+        let ctcp = Ctcp::new("ping", "1 \x01\r\\");
+        assert_eq!(ctcp.encode(), "\x01PING 1 \\a\x10r\\\\\x01");
+        assert_eq!(Ctcp::decode(&ctcp.encode()), Some(ctcp));
+        assert_eq!(
+            Ctcp::action("waves").request("#channel"),
+            "PRIVMSG #channel :\x01ACTION waves\x01"
+        );
+    }
+
+    #[test]
+    fn test_responder() {
+        let mut responder = CtcpResponder {
+            max_replies: 2,
+            ..CtcpResponder::new()
+        };
+        let ping = ParsedMessage::parse(":nick!u@h PRIVMSG me :\x01PING 42\x01".to_string());
+        let action = ParsedMessage::parse(":nick!u@h PRIVMSG me :\x01ACTION waves\x01".to_string());
+        let now = Instant::now();
+
+        assert_eq!(responder.respond_at(&action, now), None);
+        assert_eq!(
+            responder.respond_at(&ping, now),
+            Some("NOTICE nick :\x01PING 42\x01".to_string())
+        );
+        assert!(responder.respond_at(&ping, now).is_some());
+        assert_eq!(responder.respond_at(&ping, now), None);
+        assert!(responder
+            .respond_at(&ping, now + Duration::from_secs(11))
+            .is_some());
+    }
+}
@@ -0,0 +1,101 @@
+//! The in-memory connection behind [`MockStream`](crate::testing::MockStream)
+//! and [`Replay`](crate::transcript::Replay): lines from a scripted server are
+//! read, and lines the client writes are checked by it.
+
+use std::io::Result;
+use std::mem::{replace, take};
+use std::task::Waker;
+use std::time::Instant;
+
+/// What the client reads once the current line is used up.
+pub(crate) enum Next {
+    Line(String),
+    /// The server waits for the client to send this line first.
+    WaitForWrite(String),
+    /// The next line is due at this time.
+    WaitUntil(Instant),
+    End,
+}
+
+/// The scripted server side of a [`Duplex`].
+pub(crate) trait Peer {
+    fn next(&mut self) -> Next;
+
+    /// Checks a complete line the client wrote, without its line ending.
+    fn receive(&mut self, line: &str) -> Result<()>;
+
+    /// Fails every write once the server gave up on the client.
+    fn check(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub(crate) enum ReadStep {
+    Read(usize),
+    WaitForWrite(String),
+    WaitUntil(Instant),
+}
+
+pub(crate) struct Duplex<P> {
+    pub(crate) peer: P,
+    /// The rest of the line being read.
+    reading: Vec<u8>,
+    /// A partially written line.
+    written: Vec<u8>,
+    /// The reader waiting for the client to write.
+    pub(crate) waker: Option<Waker>,
+}
+
+impl<P: Peer> Duplex<P> {
+    pub(crate) fn new(peer: P) -> Self {
+        Self {
+            peer,
+            reading: Vec::new(),
+            written: Vec::new(),
+            waker: None,
+        }
+    }
+
+    pub(crate) fn read(&mut self, buf: &mut [u8]) -> ReadStep {
+        if self.reading.is_empty() {
+            match self.peer.next() {
+                Next::Line(line) => self.reading = format!("{}\r\n", line).into_bytes(),
+                Next::WaitForWrite(line) => return ReadStep::WaitForWrite(line),
+                Next::WaitUntil(due) => return ReadStep::WaitUntil(due),
+                Next::End => return ReadStep::Read(0),
+            }
+        }
+        let n = buf.len().min(self.reading.len());
+        buf[..n].copy_from_slice(&self.reading[..n]);
+        self.reading.drain(..n);
+        ReadStep::Read(n)
+    }
+
+    pub(crate) fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.peer.check()?;
+        self.written.extend_from_slice(buf);
+        while let Some(end) = self.written.iter().position(|&b| b == b'\n') {
+            let rest = self.written.split_off(end + 1);
+            let line = replace(&mut self.written, rest);
+            let line = String::from_utf8_lossy(&line);
+            if let Err(err) = self.peer.receive(line.trim_end_matches(['\r', '\n'])) {
+                // The reader may be waiting for this line, and has to see the failure.
+                self.wake();
+                return Err(err);
+            }
+        }
+        self.wake();
+        Ok(buf.len())
+    }
+
+    /// A line the client started writing but didn't finish.
+    pub(crate) fn unterminated(&self) -> &[u8] {
+        &self.written
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = take(&mut self.waker) {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,530 @@
+use std::fmt::Write;
+
+const BOLD: char = '\x02';
+const COLOR: char = '\x03';
+const HEX_COLOR: char = '\x04';
+const RESET: char = '\x0F';
+const MONOSPACE: char = '\x11';
+const REVERSE: char = '\x16';
+const ITALIC: char = '\x1D';
+const STRIKETHROUGH: char = '\x1E';
+const UNDERLINE: char = '\x1F';
+
+/// The RGB values of the mIRC colour codes 0 to 98, 99 being the default colour.
+const PALETTE: [u32; 99] = [
+    0xffffff, 0x000000, 0x00007f, 0x009300, 0xff0000, 0x7f0000, 0x9c009c, 0xfc7f00, 0xffff00,
+    0x00fc00, 0x009393, 0x00ffff, 0x0000fc, 0xff00ff, 0x7f7f7f, 0xd2d2d2, 0x470000, 0x472100,
+    0x474700, 0x324700, 0x004700, 0x00472c, 0x004747, 0x002747, 0x000047, 0x2e0047, 0x470047,
+    0x47002a, 0x740000, 0x743a00, 0x747400, 0x517400, 0x007400, 0x007449, 0x007474, 0x004074,
+    0x000074, 0x4b0074, 0x740074, 0x740045, 0xb50000, 0xb56300, 0xb5b500, 0x7db500, 0x00b500,
+    0x00b571, 0x00b5b5, 0x0063b5, 0x0000b5, 0x7500b5, 0xb500b5, 0xb5006b, 0xff0000, 0xff8c00,
+    0xffff00, 0xb2ff00, 0x00ff00, 0x00ffa0, 0x00ffff, 0x008cff, 0x0000ff, 0xa500ff, 0xff00ff,
+    0xff0098, 0xff5959, 0xffb459, 0xffff71, 0xcfff60, 0x6fff6f, 0x65ffc9, 0x6dffff, 0x59b4ff,
+    0x5959ff, 0xc459ff, 0xff66ff, 0xff59bc, 0xff9c9c, 0xffd39c, 0xffff9c, 0xe2ff9c, 0x9cff9c,
+    0x9cffdb, 0x9cffff, 0x9cd3ff, 0x9c9cff, 0xdc9cff, 0xff9cff, 0xff94d3, 0x000000, 0x131313,
+    0x282828, 0x363636, 0x4d4d4d, 0x656565, 0x818181, 0x9f9f9f, 0xbcbcbc, 0xe2e2e2, 0xffffff,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Color {
+    /// A `\x03` colour code, 0 to 98.
+    Palette(u8),
+    /// A `\x04` hex colour.
+    Rgb(u8, u8, u8),
+}
+
+impl Color {
+    pub const WHITE: Color = Color::Palette(0);
+    pub const BLACK: Color = Color::Palette(1);
+    pub const BLUE: Color = Color::Palette(2);
+    pub const GREEN: Color = Color::Palette(3);
+    pub const RED: Color = Color::Palette(4);
+    pub const BROWN: Color = Color::Palette(5);
+    pub const MAGENTA: Color = Color::Palette(6);
+    pub const ORANGE: Color = Color::Palette(7);
+    pub const YELLOW: Color = Color::Palette(8);
+    pub const LIGHT_GREEN: Color = Color::Palette(9);
+    pub const CYAN: Color = Color::Palette(10);
+    pub const LIGHT_CYAN: Color = Color::Palette(11);
+    pub const LIGHT_BLUE: Color = Color::Palette(12);
+    pub const PINK: Color = Color::Palette(13);
+    pub const GREY: Color = Color::Palette(14);
+    pub const LIGHT_GREY: Color = Color::Palette(15);
+
+    /// The colour as RGB, `None` for the default colour 99.
+    pub fn rgb(&self) -> Option<(u8, u8, u8)> {
+        match *self {
+            Color::Palette(code) => PALETTE
+                .get(code as usize)
+                .map(|&rgb| ((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)),
+            Color::Rgb(r, g, b) => Some((r, g, b)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Style {
+    pub bold: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub strikethrough: bool,
+    pub monospace: bool,
+    pub reverse: bool,
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+}
+
+impl Style {
+    pub fn is_plain(&self) -> bool {
+        *self == Style::default()
+    }
+}
+
+/// A run of text sharing the same style.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+}
+
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>, max: usize) -> Option<u8> {
+    let mut value = None;
+    for _ in 0..max {
+        match chars.peek().and_then(|c| c.to_digit(10)) {
+            Some(digit) => {
+                value = Some(value.unwrap_or(0) * 10 + digit as u8);
+                chars.next();
+            }
+            None => break,
+        }
+    }
+    value
+}
+
+fn take_hex(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<Color> {
+    let hex: String = chars.clone().take(6).collect();
+    if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    chars.nth(5);
+    let rgb = u32::from_str_radix(&hex, 16).ok()?;
+    Some(Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
+/// Splits formatted text into styled spans.
+pub fn parse(text: &str) -> Vec<Span> {
+    let mut spans: Vec<Span> = Vec::new();
+    let mut style = Style::default();
+    let mut current = String::new();
+    let mut chars = text.chars().peekable();
+
+    let mut flush = |current: &mut String, style: &Style| {
+        if current.is_empty() {
+            return;
+        }
+        match spans.last_mut() {
+            Some(last) if last.style == *style => last.text.push_str(current),
+            _ => spans.push(Span {
+                text: current.clone(),
+                style: style.clone(),
+            }),
+        }
+        current.clear();
+    };
+
+    while let Some(c) = chars.next() {
+        match c {
+            BOLD | ITALIC | UNDERLINE | STRIKETHROUGH | MONOSPACE | REVERSE | RESET => {
+                flush(&mut current, &style);
+                match c {
+                    BOLD => style.bold = !style.bold,
+                    ITALIC => style.italic = !style.italic,
+                    UNDERLINE => style.underline = !style.underline,
+                    STRIKETHROUGH => style.strikethrough = !style.strikethrough,
+                    MONOSPACE => style.monospace = !style.monospace,
+                    REVERSE => style.reverse = !style.reverse,
+                    _ => style = Style::default(),
+                }
+            }
+            COLOR => {
+                flush(&mut current, &style);
+                match take_digits(&mut chars, 2) {
+                    Some(fg) => {
+                        style.fg = Some(Color::Palette(fg)).filter(|_| fg != 99);
+                        let mut lookahead = chars.clone();
+                        if lookahead.next() == Some(',')
+                            && lookahead.peek().is_some_and(char::is_ascii_digit)
+                        {
+                            chars.next();
+                            let bg = take_digits(&mut chars, 2).unwrap_or(99);
+                            style.bg = Some(Color::Palette(bg)).filter(|_| bg != 99);
+                        }
+                    }
+                    None => {
+                        style.fg = None;
+                        style.bg = None;
+                    }
+                }
+            }
+            HEX_COLOR => {
+                flush(&mut current, &style);
+                match take_hex(&mut chars) {
+                    Some(fg) => {
+                        style.fg = Some(fg);
+                        let mut lookahead = chars.clone();
+                        if lookahead.next() == Some(',') {
+                            if let Some(bg) = take_hex(&mut lookahead) {
+                                style.bg = Some(bg);
+                                chars = lookahead;
+                            }
+                        }
+                    }
+                    None => {
+                        style.fg = None;
+                        style.bg = None;
+                    }
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    flush(&mut current, &style);
+
+    spans
+}
+
+/// Removes all formatting codes.
+pub fn strip(text: &str) -> String {
+    parse(text).into_iter().map(|span| span.text).collect()
+}
+
+/// Renders formatted text with ANSI terminal escape sequences.
+/// Control characters other than tab, such as an `ESC` starting a sequence
+/// of the sender's own, are dropped.
+pub fn to_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut styled = false;
+    for span in parse(text) {
+        if styled {
+            out.push_str("\x1b[0m");
+        }
+        styled = !span.style.is_plain();
+        if styled {
+            let style = &span.style;
+            let mut codes = Vec::new();
+            if style.bold {
+                codes.push("1".to_string());
+            }
+            if style.italic {
+                codes.push("3".to_string());
+            }
+            if style.underline {
+                codes.push("4".to_string());
+            }
+            if style.reverse {
+                codes.push("7".to_string());
+            }
+            if style.strikethrough {
+                codes.push("9".to_string());
+            }
+            if let Some((r, g, b)) = style.fg.and_then(|fg| fg.rgb()) {
+                codes.push(format!("38;2;{};{};{}", r, g, b));
+            }
+            if let Some((r, g, b)) = style.bg.and_then(|bg| bg.rgb()) {
+                codes.push(format!("48;2;{};{};{}", r, g, b));
+            }
+            if !codes.is_empty() {
+                write!(out, "\x1b[{}m", codes.join(";")).unwrap();
+            }
+        }
+        out.extend(span.text.chars().filter(|&c| c == '\t' || !c.is_control()));
+    }
+    if styled {
+        out.push_str("\x1b[0m");
+    }
+    out
+}
+
+fn escape_html(text: &str, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Renders formatted text as HTML, wrapping styled spans in `<span style="...">`.
+pub fn to_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for span in parse(text) {
+        let style = &span.style;
+        let mut css = Vec::new();
+        if style.bold {
+            css.push("font-weight:bold".to_string());
+        }
+        if style.italic {
+            css.push("font-style:italic".to_string());
+        }
+        match (style.underline, style.strikethrough) {
+            (true, true) => css.push("text-decoration:underline line-through".to_string()),
+            (true, false) => css.push("text-decoration:underline".to_string()),
+            (false, true) => css.push("text-decoration:line-through".to_string()),
+            (false, false) => {}
+        }
+        if style.monospace {
+            css.push("font-family:monospace".to_string());
+        }
+        let (mut fg, mut bg) = (
+            style.fg.and_then(|fg| fg.rgb()),
+            style.bg.and_then(|bg| bg.rgb()),
+        );
+        if style.reverse {
+            (fg, bg) = (
+                Some(bg.unwrap_or((0xff, 0xff, 0xff))),
+                Some(fg.unwrap_or((0, 0, 0))),
+            );
+        }
+        if let Some((r, g, b)) = fg {
+            css.push(format!("color:#{:02x}{:02x}{:02x}", r, g, b));
+        }
+        if let Some((r, g, b)) = bg {
+            css.push(format!("background-color:#{:02x}{:02x}{:02x}", r, g, b));
+        }
+
+        if css.is_empty() {
+            escape_html(&span.text, &mut out);
+        } else {
+            write!(out, "<span style=\"{}\">", css.join(";")).unwrap();
+            escape_html(&span.text, &mut out);
+            out.push_str("</span>");
+        }
+    }
+    out
+}
+
+/// Builds formatted text, stripping codes from the input and resetting after each styled run.
+#[derive(Debug, Clone, Default)]
+pub struct FormatBuilder {
+    out: String,
+}
+
+impl FormatBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn text(mut self, text: &str) -> Self {
+        self.out.push_str(&strip(text));
+        self
+    }
+
+    pub fn styled(mut self, text: &str, style: &Style) -> Self {
+        let text = strip(text);
+        if style.is_plain() || text.is_empty() {
+            self.out.push_str(&text);
+            return self;
+        }
+        let toggles = [
+            (style.bold, BOLD),
+            (style.italic, ITALIC),
+            (style.underline, UNDERLINE),
+            (style.strikethrough, STRIKETHROUGH),
+            (style.monospace, MONOSPACE),
+            (style.reverse, REVERSE),
+        ];
+        for (enabled, code) in toggles {
+            if enabled {
+                self.out.push(code);
+            }
+        }
+        if style.fg.is_some() || style.bg.is_some() {
+            let fg = style.fg.unwrap_or(Color::Palette(99));
+            match (fg, style.bg) {
+                (Color::Palette(fg), None) => write!(self.out, "{}{:02}", COLOR, fg),
+                (Color::Palette(fg), Some(Color::Palette(bg))) => {
+                    write!(self.out, "{}{:02},{:02}", COLOR, fg, bg)
+                }
+                (fg, bg) => {
+                    let hex = |color: Color| {
+                        let (r, g, b) = color.rgb().unwrap_or((0, 0, 0));
+                        format!("{:02X}{:02X}{:02X}", r, g, b)
+                    };
+                    match bg {
+                        Some(bg) => write!(self.out, "{}{},{}", HEX_COLOR, hex(fg), hex(bg)),
+                        None => write!(self.out, "{}{}", HEX_COLOR, hex(fg)),
+                    }
+                }
+            }
+            .unwrap();
+            // Keep a leading ",1" of the text from being read as a background colour.
+            if text.starts_with(',') {
+                self.out.push_str("\x02\x02");
+            }
+        }
+        self.out.push_str(&text);
+        self.out.push(RESET);
+        self
+    }
+
+    pub fn bold(self, text: &str) -> Self {
+        self.styled(
+            text,
+            &Style {
+                bold: true,
+                ..Style::default()
+            },
+        )
+    }
+
+    pub fn italic(self, text: &str) -> Self {
+        self.styled(
+            text,
+            &Style {
+                italic: true,
+                ..Style::default()
+            },
+        )
+    }
+
+    pub fn underline(self, text: &str) -> Self {
+        self.styled(
+            text,
+            &Style {
+                underline: true,
+                ..Style::default()
+            },
+        )
+    }
+
+    pub fn color(self, text: &str, fg: Color, bg: Option<Color>) -> Self {
+        self.styled(
+            text,
+            &Style {
+                fg: Some(fg),
+                bg,
+                ..Style::default()
+            },
+        )
+    }
+
+    pub fn build(self) -> String {
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let spans = parse("plain \x02bold\x034,12 colored\x03 \x1Ditalic\x0F end");
+        assert_eq!(
+            spans,
+            vec![
+                Span {
+                    text: "plain ".to_string(),
+                    style: Style::default(),
+                },
+                Span {
+                    text: "bold".to_string(),
+                    style: Style {
+                        bold: true,
+                        ..Style::default()
+                    },
+                },
+                Span {
+                    text: " colored".to_string(),
+                    style: Style {
+                        bold: true,
+                        fg: Some(Color::RED),
+                        bg: Some(Color::LIGHT_BLUE),
+                        ..Style::default()
+                    },
+                },
+                Span {
+                    text: " ".to_string(),
+                    style: Style {
+                        bold: true,
+                        ..Style::default()
+                    },
+                },
+                Span {
+                    text: "italic".to_string(),
+                    style: Style {
+                        bold: true,
+                        italic: true,
+                        ..Style::default()
+                    },
+                },
+                Span {
+                    text: " end".to_string(),
+                    style: Style::default(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_colors() {
+        let spans = parse("\x0312,x\x04FF8000,000000hex\x04, \x03100");
+        assert_eq!(spans[0].text, ",x");
+        assert_eq!(spans[0].style.fg, Some(Color::LIGHT_BLUE));
+        assert_eq!(spans[0].style.bg, None);
+        assert_eq!(spans[1].text, "hex");
+        assert_eq!(spans[1].style.fg, Some(Color::Rgb(0xff, 0x80, 0)));
+        assert_eq!(spans[1].style.bg, Some(Color::Rgb(0, 0, 0)));
+        assert_eq!(spans[2].text, ", ");
+        assert_eq!(spans[2].style, Style::default());
+        assert_eq!(spans[3].text, "0");
+        assert_eq!(spans[3].style.fg, Some(Color::Palette(10)));
+    }
+
+    #[test]
+    fn test_strip() {
+        assert_eq!(
+            strip("\x02\x0304,01Hello\x0F \x1Fworld\x1F\x16!\x16\x04ff0000"),
+            "Hello world!"
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(to_ansi("a \x02b\x02"), "a \x1b[1mb\x1b[0m");
+        assert_eq!(to_ansi("\x034red"), "\x1b[38;2;255;0;0mred\x1b[0m");
+        assert_eq!(
+            to_ansi("a\x1b[2J\x1b]0;title\x07\u{9b}31mb\tc\x02d"),
+            "a[2J]0;title31mb\tc\x1b[1md\x1b[0m"
+        );
+        assert_eq!(
+            to_html("<\x02b\x1Di\x0F>"),
+            "&lt;<span style=\"font-weight:bold\">b</span>\
+             <span style=\"font-weight:bold;font-style:italic\">i</span>&gt;"
+        );
+        assert_eq!(
+            to_html("\x0304,01x"),
+            "<span style=\"color:#ff0000;background-color:#000000\">x</span>"
+        );
+    }
+
+    #[test]
+    fn test_builder() {
+        let text = FormatBuilder::new()
+            .text("a \x02")
+            .bold("b")
+            .color(",5", Color::RED, None)
+            .color("c", Color::Rgb(1, 2, 3), Some(Color::BLACK))
+            .build();
+        assert_eq!(
+            text,
+            "a \x02b\x0F\x0304\x02\x02,5\x0F\x04010203,000000c\x0F"
+        );
+        assert_eq!(strip(&text), "a b,5c");
+    }
+}
@@ -0,0 +1,297 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt::{Display, Formatter, Result as FResult};
+
+use super::traits::*;
+use super::{escape_tag_value, ParsedMessage};
+
+/// Why a [`MessageBuilder`] can't be turned into a line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    /// The command is neither letters nor a three digit numeric.
+    Command(String),
+    /// The prefix is empty or contains a space, CR, LF or NUL.
+    Prefix(String),
+    /// A tag key is empty or contains `=`, `;`, a space, CR, LF or NUL,
+    /// or its value contains NUL.
+    Tag(String),
+    /// A middle param is empty, starts with `:` or contains a space,
+    /// or any param contains CR, LF or NUL.
+    Param { index: usize, param: String },
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        match self {
+            BuildError::Command(command) => write!(f, "invalid command {:?}", command),
+            BuildError::Prefix(prefix) => write!(f, "invalid prefix {:?}", prefix),
+            BuildError::Tag(key) => write!(f, "invalid tag {:?}", key),
+            BuildError::Param { index, param } => {
+                write!(f, "invalid param {} {:?}", index, param)
+            }
+        }
+    }
+}
+
+impl Error for BuildError {}
+
+fn has_line_break(s: &str) -> bool {
+    s.contains(['\r', '\n', '\0'])
+}
+
+/// Builds a line to send from tags, prefix, command and params.
+/// The last param is sent as trailing when it has to be, tag values are escaped.
+/// Anything that would change the message on the wire, such as a space in a
+/// middle param or a line break anywhere, is a [`BuildError`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MessageBuilder {
+    tags: Vec<(String, String)>,
+    prefix: Option<String>,
+    command: String,
+    params: Vec<String>,
+}
+
+impl MessageBuilder {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+            ..Self::default()
+        }
+    }
+
+    /// Adds a tag, an empty value sends just the key.
+    pub fn tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.push((key.to_string(), value.to_string()));
+        self
+    }
+
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = Some(prefix.to_string());
+        self
+    }
+
+    /// Adds a param. Only the last one may be empty, contain spaces or start with `:`.
+    pub fn param(mut self, param: &str) -> Self {
+        self.params.push(param.to_string());
+        self
+    }
+
+    pub fn params(mut self, params: &[&str]) -> Self {
+        self.params
+            .extend(params.iter().map(|param| param.to_string()));
+        self
+    }
+
+    /// Checks that the line would parse back into the same message.
+    pub fn validate(&self) -> Result<(), BuildError> {
+        let command = &self.command;
+        let numeric = command.len() == 3 && command.bytes().all(|b| b.is_ascii_digit());
+        if !numeric && (command.is_empty() || !command.bytes().all(|b| b.is_ascii_alphabetic())) {
+            return Err(BuildError::Command(command.clone()));
+        }
+        if let Some(prefix) = &self.prefix {
+            if prefix.is_empty() || prefix.contains(' ') || has_line_break(prefix) {
+                return Err(BuildError::Prefix(prefix.clone()));
+            }
+        }
+        for (key, value) in &self.tags {
+            if key.is_empty()
+                || key.contains(['=', ';', ' '])
+                || has_line_break(key)
+                || value.contains('\0')
+            {
+                return Err(BuildError::Tag(key.clone()));
+            }
+        }
+        let last = self.params.len().saturating_sub(1);
+        for (index, param) in self.params.iter().enumerate() {
+            let middle =
+                index < last && (param.is_empty() || param.contains(' ') || param.starts_with(':'));
+            if middle || has_line_break(param) {
+                let param = param.clone();
+                return Err(BuildError::Param { index, param });
+            }
+        }
+        Ok(())
+    }
+
+    /// The line without `\r\n`, or why it can't be built.
+    pub fn try_build(&self) -> Result<String, BuildError> {
+        self.validate()?;
+        Ok(self.to_string())
+    }
+
+    /// The line without `\r\n`.
+    /// Panics if the message is invalid, use [`MessageBuilder::try_build`]
+    /// for untrusted input.
+    pub fn build(&self) -> String {
+        match self.try_build() {
+            Ok(line) => line,
+            Err(err) => panic!("MessageBuilder: {}", err),
+        }
+    }
+
+    pub fn try_to_message(&self) -> Result<ParsedMessage, BuildError> {
+        self.try_build().map(ParsedMessage::parse)
+    }
+
+    /// Panics like [`MessageBuilder::build`].
+    pub fn to_message(&self) -> ParsedMessage {
+        ParsedMessage::parse(self.build())
+    }
+}
+
+/// Writes the line as it is, without [`MessageBuilder::validate`].
+impl Display for MessageBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        if !self.tags.is_empty() {
+            f.write_str("@")?;
+            for (i, (key, value)) in self.tags.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(";")?;
+                }
+                f.write_str(key)?;
+                if !value.is_empty() {
+                    write!(f, "={}", escape_tag_value(value))?;
+                }
+            }
+            f.write_str(" ")?;
+        }
+        if let Some(prefix) = &self.prefix {
+            write!(f, ":{} ", prefix)?;
+        }
+        f.write_str(&self.command)?;
+        if let Some((last, middle)) = self.params.split_last() {
+            for param in middle {
+                write!(f, " {}", param)?;
+            }
+            if last.is_empty() || last.contains(' ') || last.starts_with(':') {
+                write!(f, " :{}", last)?;
+            } else {
+                write!(f, " {}", last)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<&ParsedMessage> for MessageBuilder {
+    fn from(msg: &ParsedMessage) -> Self {
+        Self {
+            tags: msg.tags(),
+            prefix: msg.prefix(),
+            command: msg.command(),
+            params: msg.params(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let line = MessageBuilder::new("PRIVMSG")
+            .tag("+draft/reply", "abc;1")
+            .tag("+draft/typing", "")
+            .prefix("nick!user@host")
+            .params(&["#channel", "hello world"])
+            .build();
+        assert_eq!(
+            line,
+            "@+draft/reply=abc\\:1;+draft/typing :nick!user@host PRIVMSG #channel :hello world"
+        );
+
+        assert_eq!(MessageBuilder::new("AWAY").param("").build(), "AWAY :");
+        assert_eq!(
+            MessageBuilder::new("MODE").param("#c").param(":+i").build(),
+            "MODE #c ::+i"
+        );
+        assert_eq!(MessageBuilder::new("JOIN").param("#c").build(), "JOIN #c");
+    }
+
+    #[test]
+    fn test_invalid() {
+        let param = |index, param: &str| BuildError::Param {
+            index,
+            param: param.to_string(),
+        };
+        let cases = [
+            (MessageBuilder::new(""), BuildError::Command("".to_string())),
+            (
+                MessageBuilder::new("PRIV MSG"),
+                BuildError::Command("PRIV MSG".to_string()),
+            ),
+            (
+                MessageBuilder::new("0001"),
+                BuildError::Command("0001".to_string()),
+            ),
+            (
+                MessageBuilder::new("PING").prefix("a b"),
+                BuildError::Prefix("a b".to_string()),
+            ),
+            (
+                MessageBuilder::new("PING").prefix("srv\r\nQUIT"),
+                BuildError::Prefix("srv\r\nQUIT".to_string()),
+            ),
+            (
+                MessageBuilder::new("PING").tag("a=b", "c"),
+                BuildError::Tag("a=b".to_string()),
+            ),
+            (
+                MessageBuilder::new("PING").tag("a", "\0"),
+                BuildError::Tag("a".to_string()),
+            ),
+            (
+                MessageBuilder::new("PRIVMSG").params(&["", "x"]),
+                param(0, ""),
+            ),
+            (
+                MessageBuilder::new("PRIVMSG").params(&["a b", "x"]),
+                param(0, "a b"),
+            ),
+            (
+                MessageBuilder::new("PRIVMSG").params(&[":a", "x"]),
+                param(0, ":a"),
+            ),
+            (
+                MessageBuilder::new("PRIVMSG").params(&["#c", "hi\r\nQUIT"]),
+                param(1, "hi\r\nQUIT"),
+            ),
+            (
+                MessageBuilder::new("PRIVMSG").params(&["#c", "a\0"]),
+                param(1, "a\0"),
+            ),
+            (
+                MessageBuilder::new("PRIVMSG").params(&["#\nc", "x"]),
+                param(0, "#\nc"),
+            ),
+        ];
+        for (builder, err) in cases {
+            assert_eq!(builder.try_build(), Err(err));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "MessageBuilder: invalid param 0")]
+    fn test_build_panics() {
+        MessageBuilder::new("PRIVMSG").params(&["a b", "c"]).build();
+    }
+
+    #[test]
+    fn test_display_unvalidated() {
+        let builder = MessageBuilder::new("PRIVMSG").params(&["a b", "c"]);
+        assert_eq!(builder.to_string(), "PRIVMSG a b c");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let msg = ParsedMessage::parse("@a=b\\sc :srv 001 me :Welcome home".to_string());
+        let builder = MessageBuilder::from(&msg);
+        assert_eq!(builder.build(), "@a=b\\sc :srv 001 me :Welcome home");
+        assert_eq!(builder.to_message(), msg);
+    }
+}
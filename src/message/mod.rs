@@ -1,21 +1,41 @@
-use std::fmt::{Debug, Display, Formatter, Result as FResult};
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::fmt::{Debug, Display, Formatter, Result as FResult};
+#[cfg(feature = "std")]
+use std::time::SystemTime;
+mod builder;
+pub use builder::{BuildError, MessageBuilder};
+#[cfg(feature = "serde")]
+mod serde_impl;
 mod util;
-use util::UntilExt;
+#[cfg(feature = "std")]
+pub(crate) use util::{civil_from_days, days_from_civil};
+pub use util::{escape_tag_value, is_valid_hostname, unescape_tag_value};
+#[cfg(feature = "std")]
+pub use util::{format_rfc3339, parse_rfc3339};
 mod traits;
 use traits::*;
 pub mod prelude;
 
+use crate::parser::{parse_single, Leniencies, ParserConfig};
 use smallvec::SmallVec;
 
+/// Longest message that can be parsed, as spans are stored as `u32` offsets.
+pub const MAX_LEN: usize = u32::MAX as usize;
+
 // #[derive(Debug, PartialEq)]
 pub struct ParsedMessage {
     raw: String,
-    command: (u16, u16),
-    params: SmallVec<[(u16, u16); 2]>,
-    prefix: Option<(u16, u16)>,
-    nick: Option<(u16, u16)>,
-    user: Option<(u16, u16)>,
-    host: Option<(u16, u16)>,
+    command: (u32, u32),
+    params: SmallVec<[(u32, u32); 2]>,
+    prefix: Option<(u32, u32)>,
+    nick: Option<(u32, u32)>,
+    user: Option<(u32, u32)>,
+    host: Option<(u32, u32)>,
+    tags: Option<(u32, u32)>,
+    #[cfg(feature = "std")]
+    received: Option<SystemTime>,
+    leniencies: Leniencies,
 }
 
 impl Default for ParsedMessage {
@@ -28,6 +48,10 @@ impl Default for ParsedMessage {
             nick: None,
             user: None,
             host: None,
+            tags: None,
+            #[cfg(feature = "std")]
+            received: None,
+            leniencies: Leniencies::default(),
         }
     }
 }
@@ -53,13 +77,7 @@ impl Debug for ParsedMessage {
 impl Message for ParsedMessage {
     fn command(&self) -> String {
         let (begin, end) = self.command;
-        debug_assert!(begin <= end);
-        // self.raw[begin as usize..end as usize].to_string()
-        unsafe {
-            self.raw
-                .get_unchecked(begin as usize..end as usize)
-                .to_string()
-        }
+        self.raw[begin as usize..end as usize].to_string()
     }
 }
 
@@ -67,55 +85,70 @@ impl Parameterized for ParsedMessage {
     fn params(&self) -> Vec<String> {
         self.params
             .iter()
-            .map(|&(begin, end)| unsafe {
-                self.raw
-                    .get_unchecked(begin as usize..end as usize)
-                    .to_string()
-            })
+            .map(|&(begin, end)| self.raw[begin as usize..end as usize].to_string())
             .collect()
     }
 }
 impl Prefixed for ParsedMessage {
     fn prefix(&self) -> Option<String> {
-        self.prefix.map(|(begin, end)| unsafe {
-            self.raw
-                .get_unchecked(begin as usize..end as usize)
-                .to_string()
-        })
+        self.prefix
+            .map(|(begin, end)| self.raw[begin as usize..end as usize].to_string())
     }
     fn nick(&self) -> Option<String> {
-        self.nick.map(|(begin, end)| unsafe {
-            self.raw
-                .get_unchecked(begin as usize..end as usize)
-                .to_string()
-        })
+        self.nick
+            .map(|(begin, end)| self.raw[begin as usize..end as usize].to_string())
     }
     fn user(&self) -> Option<String> {
-        self.user.map(|(begin, end)| unsafe {
-            self.raw
-                .get_unchecked(begin as usize..end as usize)
-                .to_string()
-        })
+        self.user
+            .map(|(begin, end)| self.raw[begin as usize..end as usize].to_string())
     }
     fn host(&self) -> Option<String> {
-        self.host.map(|(begin, end)| unsafe {
-            self.raw
-                .get_unchecked(begin as usize..end as usize)
-                .to_string()
-        })
+        self.host
+            .map(|(begin, end)| self.raw[begin as usize..end as usize].to_string())
+    }
+}
+
+impl Tagged for ParsedMessage {
+    fn tags(&self) -> Vec<(String, String)> {
+        let (begin, end) = match self.tags {
+            Some(tags) => tags,
+            None => return Vec::new(),
+        };
+        let tags = &self.raw[begin as usize..end as usize];
+        tags.split(';')
+            .filter(|tag| !tag.is_empty())
+            .map(|tag| match tag.split_once('=') {
+                Some((key, value)) => (key.to_string(), unescape_tag_value(value)),
+                None => (tag.to_string(), String::new()),
+            })
+            .collect()
+    }
+    fn tag(&self, key: &str) -> Option<String> {
+        // Later occurrences of a key override earlier ones.
+        self.tags()
+            .into_iter()
+            .rev()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value)
     }
 }
 
 impl ParsedMessage {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         raw: String,
-        prefix: Option<(u16, u16)>,
-        nick: Option<(u16, u16)>,
-        user: Option<(u16, u16)>,
-        host: Option<(u16, u16)>,
-        command: (u16, u16),
-        params: SmallVec<[(u16, u16); 2]>,
+        prefix: Option<(u32, u32)>,
+        nick: Option<(u32, u32)>,
+        user: Option<(u32, u32)>,
+        host: Option<(u32, u32)>,
+        command: (u32, u32),
+        params: SmallVec<[(u32, u32); 2]>,
+        tags: Option<(u32, u32)>,
     ) -> Self {
+        debug_assert!(core::iter::once(command)
+            .chain(params.iter().copied())
+            .chain([prefix, nick, user, host, tags].into_iter().flatten())
+            .all(|(begin, end)| raw.get(begin as usize..end as usize).is_some()));
         Self {
             raw,
             command,
@@ -124,973 +157,71 @@ impl ParsedMessage {
             nick,
             user,
             host,
+            tags,
+            #[cfg(feature = "std")]
+            received: None,
+            leniencies: Leniencies::default(),
         }
     }
 
-    /// Parse a message from a string.
-    /// Example:
-    /// ```
-    /// use tiny_irc::message::ParsedMessage;
-    /// let msg = ParsedMessage::parse(":irc.example.com 001 test :Welcome to the Internet Relay Network".to_string());
-    /// ```
-    pub fn parse(raw: String) -> Self {
-        let mut prefix: Option<(u16, u16)> = None;
-        let mut nick: Option<(u16, u16)> = None;
-        let mut user: Option<(u16, u16)> = None;
-        let mut host: Option<(u16, u16)> = None;
-        let mut command: Option<(u16, u16)> = None;
-        let mut params: SmallVec<[(u16, u16); 2]> = SmallVec::new();
-
-        enum State {
-            Initial,
-            PrefixNick { begin: u16 },
-            PrefixUser { begin: u16, begin_prefix: u16 },
-            PrefixHost { begin: u16, begin_prefix: u16 },
-            Command { begin: u16 },
-            Params,
-            ParamsMiddle { begin: u16 },
-            ParamsTrailing { begin: u16 },
-        }
-
-        let mut state = State::Initial;
-
-        for (i, b) in raw.bytes().enumerate() {
-            // if b == b'\r' || b == b'\n' {
-            //     break;
-            // }
-            match state {
-                State::Initial => match b {
-                    b':' => {
-                        state = State::PrefixNick {
-                            begin: i as u16 + 1,
-                        }
-                    }
-                    _ => {
-                        state = State::Command { begin: i as u16 };
-                    }
-                },
-                State::PrefixNick { begin } => match b {
-                    b'!' => {
-                        nick = Some((begin, i as u16));
-
-                        state = State::PrefixUser {
-                            begin: i as u16 + 1,
-                            begin_prefix: begin,
-                        };
-                    }
-                    b'@' => {
-                        nick = Some((begin, i as u16));
-
-                        state = State::PrefixHost {
-                            begin: i as u16 + 1,
-                            begin_prefix: begin,
-                        };
-                    }
-                    b' ' => {
-                        nick = Some((begin, i as u16));
-                        prefix = Some((begin, i as u16));
-
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                    }
-                    _ => {}
-                },
-                State::PrefixUser {
-                    begin,
-                    begin_prefix,
-                } => match b {
-                    b'@' => {
-                        user = Some((begin, i as u16));
-
-                        state = State::PrefixHost {
-                            begin: i as u16 + 1,
-                            begin_prefix,
-                        };
-                    }
-                    b' ' => {
-                        user = Some((begin, i as u16));
-                        prefix = Some((begin_prefix, i as u16));
-
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                    }
-                    _ => {}
-                },
-                State::PrefixHost {
-                    begin,
-                    begin_prefix,
-                } => match b {
-                    b' ' => {
-                        host = Some((begin, i as u16));
-                        prefix = Some((begin_prefix, i as u16));
-
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                    }
-                    _ => {}
-                },
-                State::Command { begin } => match b {
-                    b' ' => {
-                        command = Some((begin, i as u16));
-
-                        state = State::Params;
-                    }
-                    _ => {}
-                },
-                State::Params => match b {
-                    b' ' => {}
-                    b':' => {
-                        state = State::ParamsTrailing {
-                            begin: i as u16 + 1,
-                        };
-                        // break;
-                    }
-                    _ => {
-                        state = State::ParamsMiddle { begin: i as u16 };
-                    }
-                },
-                State::ParamsMiddle { begin } => match b {
-                    b' ' => {
-                        params.push((begin, i as u16));
-
-                        state = State::Params;
-                    }
-                    _ => {}
-                },
-                State::ParamsTrailing { begin: _ } => match b {
-                    _ => {}
-                },
-            }
-        }
-
-        match state {
-            State::Command { begin } => {
-                command = Some((begin, raw.len() as u16));
-            }
-            State::ParamsTrailing { begin } => {
-                params.push((begin, raw.len() as u16));
-            }
-            State::ParamsMiddle { begin } => {
-                params.push((begin, raw.len() as u16));
-            }
-            _ => {}
-        }
-
-        Self {
-            raw,
-            command: command.unwrap(),
-            params,
-            prefix,
-            nick,
-            user,
-            host,
-        }
+    /// Records when the message was read off the connection.
+    #[cfg(feature = "std")]
+    pub fn with_received(mut self, received: SystemTime) -> Self {
+        self.received = Some(received);
+        self
     }
 
-    pub fn parse_replace(raw: String) -> Self {
-        let mut prefix: Option<(u16, u16)> = None;
-        let mut nick: Option<(u16, u16)> = None;
-        let mut user: Option<(u16, u16)> = None;
-        let mut host: Option<(u16, u16)> = None;
-        let mut command: Option<(u16, u16)> = None;
-        let mut params: SmallVec<[(u16, u16); 2]> = SmallVec::new();
-
-        enum State {
-            Initial,
-            PrefixNick { begin: u16 },
-            PrefixUser { begin: u16, begin_prefix: u16 },
-            PrefixHost { begin: u16, begin_prefix: u16 },
-            Command { begin: u16 },
-            Params,
-            ParamsMiddle { begin: u16 },
-            ParamsTrailing { begin: u16 },
-        }
-
-        let mut state = State::Initial;
-
-        for (i, b) in raw.bytes().enumerate() {
-            // if b == b'\r' || b == b'\n' {
-            //     break;
-            // }
-            match state {
-                State::Initial => match b {
-                    b':' => {
-                        state = State::PrefixNick {
-                            begin: i as u16 + 1,
-                        }
-                    }
-                    _ => {
-                        state = State::Command { begin: i as u16 };
-                    }
-                },
-                State::PrefixNick { begin } => match b {
-                    b'!' => {
-                        nick.replace((begin, i as u16));
-
-                        state = State::PrefixUser {
-                            begin: i as u16 + 1,
-                            begin_prefix: begin,
-                        };
-                    }
-                    b'@' => {
-                        nick.replace((begin, i as u16));
-
-                        state = State::PrefixHost {
-                            begin: i as u16 + 1,
-                            begin_prefix: begin,
-                        };
-                    }
-                    b' ' => {
-                        nick.replace((begin, i as u16));
-                        prefix.replace((begin, i as u16));
-
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                    }
-                    _ => {}
-                },
-                State::PrefixUser {
-                    begin,
-                    begin_prefix,
-                } => match b {
-                    b'@' => {
-                        user.replace((begin, i as u16));
-
-                        state = State::PrefixHost {
-                            begin: i as u16 + 1,
-                            begin_prefix,
-                        };
-                    }
-                    b' ' => {
-                        user.replace((begin, i as u16));
-                        prefix.replace((begin_prefix, i as u16));
-
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                    }
-                    _ => {}
-                },
-                State::PrefixHost {
-                    begin,
-                    begin_prefix,
-                } => match b {
-                    b' ' => {
-                        host.replace((begin, i as u16));
-                        prefix.replace((begin_prefix, i as u16));
-
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                    }
-                    _ => {}
-                },
-                State::Command { begin } => match b {
-                    b' ' => {
-                        command.replace((begin, i as u16));
-
-                        state = State::Params;
-                    }
-                    _ => {}
-                },
-                State::Params => match b {
-                    b' ' => {}
-                    b':' => {
-                        state = State::ParamsTrailing {
-                            begin: i as u16 + 1,
-                        };
-                        // break;
-                    }
-                    _ => {
-                        state = State::ParamsMiddle { begin: i as u16 };
-                    }
-                },
-                State::ParamsMiddle { begin } => match b {
-                    b' ' => {
-                        params.push((begin, i as u16));
-
-                        state = State::Params;
-                    }
-                    _ => {}
-                },
-                State::ParamsTrailing { begin: _ } => match b {
-                    _ => {}
-                },
-            }
-        }
-
-        match state {
-            State::Command { begin } => {
-                command.replace((begin, raw.len() as u16));
-            }
-            State::ParamsTrailing { begin } => {
-                params.push((begin, raw.len() as u16));
-            }
-            State::ParamsMiddle { begin } => {
-                params.push((begin, raw.len() as u16));
-            }
-            _ => {}
-        }
-
-        Self {
-            raw,
-            command: command.unwrap(),
-            params,
-            prefix,
-            nick,
-            user,
-            host,
-        }
+    /// Records the grammar deviations the parser accepted for this message.
+    pub fn with_leniencies(mut self, leniencies: Leniencies) -> Self {
+        self.leniencies = leniencies;
+        self
     }
 
-    pub fn parse_iter(raw: String) -> Self {
-        // let mut rawSlice:Option<(u16,u16)> = None;
-        let mut prefix: Option<(u16, u16)> = None;
-        let mut nick: Option<(u16, u16)> = None;
-        let mut user: Option<(u16, u16)> = None;
-        let mut host: Option<(u16, u16)> = None;
-        let mut command: Option<(u16, u16)> = None;
-        let mut params: SmallVec<[(u16, u16); 2]> = SmallVec::new();
-
-        enum State {
-            PrefixNick { begin: u16 },
-            PrefixUser { begin: u16, begin_prefix: u16 },
-            PrefixHost { begin: u16, begin_prefix: u16 },
-            Command { begin: u16 },
-            Params,
-            ParamsMiddle { begin: u16 },
-            ParamsTrailing { begin: u16 },
-        }
-
-        let mut state = State::Command { begin: 0 };
-        let mut iter = raw.bytes().until(b'\n').until(b'\r').enumerate();
-        if let Some((_i, b)) = iter.next() {
-            if b == b':' {
-                state = State::PrefixNick { begin: 1 };
-            }
-        } else {
-            panic!();
-        }
-
-        match state {
-            State::PrefixNick { begin } => {
-                while let Some((i, b)) = iter.next() {
-                    if b == b'!' {
-                        nick.replace((begin, i as u16));
-                        state = State::PrefixUser {
-                            begin: i as u16 + 1,
-                            begin_prefix: begin,
-                        };
-                        break;
-                    } else if b == b' ' {
-                        nick.replace((begin, i as u16));
-                        prefix.replace((begin, i as u16));
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                        break;
-                    }
-                }
-            }
-            _ => {}
-        }
-
-        match state {
-            State::PrefixUser {
-                begin,
-                begin_prefix,
-            } => {
-                while let Some((i, b)) = iter.next() {
-                    if b == b'@' {
-                        user.replace((begin, i as u16));
-                        state = State::PrefixHost {
-                            begin: i as u16 + 1,
-                            begin_prefix,
-                        };
-                        break;
-                    } else if b == b' ' {
-                        user.replace((begin, i as u16));
-                        prefix.replace((begin_prefix, i as u16));
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                        break;
-                    }
-                }
-            }
-            _ => {}
-        }
-
-        match state {
-            State::PrefixHost {
-                begin,
-                begin_prefix,
-            } => {
-                while let Some((i, b)) = iter.next() {
-                    if b == b' ' {
-                        host.replace((begin, i as u16));
-                        prefix.replace((begin_prefix, i as u16));
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                        break;
-                    }
-                }
-            }
-            _ => {}
-        }
-
-        match state {
-            State::Command { begin } => {
-                while let Some((i, b)) = iter.next() {
-                    if b == b' ' {
-                        command.replace((begin, i as u16));
-                        state = State::Params;
-                        break;
-                    }
-                }
-            }
-            _ => {}
-        }
-
-        while let Some((i, b)) = iter.next() {
-            match state {
-                State::Params => {
-                    if b == b':' {
-                        state = State::ParamsTrailing {
-                            begin: i as u16 + 1,
-                        };
-                    } else {
-                        state = State::ParamsMiddle { begin: i as u16 };
-                    }
-                }
-                State::ParamsMiddle { begin } => {
-                    while let Some((i, b)) = iter.next() {
-                        if b == b' ' {
-                            params.push((begin, i as u16));
-                            state = State::Params;
-                            break;
-                        }
-                    }
-                    // params.push((begin, raw.len() as u16));
-                }
-                State::ParamsTrailing { begin } => {
-                    //while let Some(_) = iter.next() {}
-                    iter.for_each(drop);
-                    params.push((begin, raw.len() as u16));
-                    break;
-                }
-                _ => {}
-            }
-        }
-
-        match state {
-            State::Command { begin } => {
-                command.replace((begin, raw.len() as u16));
-            }
-            State::ParamsMiddle { begin } => {
-                params.push((begin, raw.len() as u16));
-            }
-            _ => {}
-        }
-
-        Self {
-            raw,
-            command: command.unwrap(),
-            params,
-            prefix,
-            nick,
-            user,
-            host,
-        }
+    pub fn leniencies(&self) -> Leniencies {
+        self.leniencies
     }
 
-    pub fn parse_for_iter(raw: String) -> Self {
-        let mut prefix: Option<(u16, u16)> = None;
-        let mut nick: Option<(u16, u16)> = None;
-        let mut user: Option<(u16, u16)> = None;
-        let mut host: Option<(u16, u16)> = None;
-        let mut command: Option<(u16, u16)> = None;
-        let mut params: SmallVec<[(u16, u16); 2]> = SmallVec::new();
-
-        #[derive(Debug)]
-        enum State {
-            PrefixNick { begin: u16 },
-            PrefixUser { begin: u16, begin_prefix: u16 },
-            PrefixHost { begin: u16, begin_prefix: u16 },
-            Command { begin: u16 },
-            Params,
-            ParamsMiddle { begin: u16 },
-            ParamsTrailing { begin: u16 },
-        }
-
-        let mut state = State::Command { begin: 0 };
-        let mut iter = raw.bytes().until(b'\n').until(b'\r').enumerate();
-
-        if let Some((_i, b)) = iter.next() {
-            if b == b':' {
-                state = State::PrefixNick { begin: 1 };
-            }
-        } else {
-            panic!();
-        }
-
-        match state {
-            State::PrefixNick { begin } => {
-                for (i, b) in iter.by_ref() {
-                    if b == b'!' {
-                        nick.replace((begin, i as u16));
-                        state = State::PrefixUser {
-                            begin: i as u16 + 1,
-                            begin_prefix: begin,
-                        };
-                        break;
-                    } else if b == b' ' {
-                        nick.replace((begin, i as u16));
-                        prefix.replace((begin, i as u16));
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                        break;
-                    }
-                }
-            }
-            _ => {}
-        }
-
-        match state {
-            State::PrefixUser {
-                begin,
-                begin_prefix,
-            } => {
-                for (i, b) in iter.by_ref() {
-                    if b == b'@' {
-                        user.replace((begin, i as u16));
-                        state = State::PrefixHost {
-                            begin: i as u16 + 1,
-                            begin_prefix,
-                        };
-                        break;
-                    } else if b == b' ' {
-                        user.replace((begin, i as u16));
-                        prefix.replace((begin_prefix, i as u16));
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                        break;
-                    }
-                }
-            }
-            _ => {}
-        }
-
-        match state {
-            State::PrefixHost {
-                begin,
-                begin_prefix,
-            } => {
-                for (i, b) in iter.by_ref() {
-                    if b == b' ' {
-                        host.replace((begin, i as u16));
-                        prefix.replace((begin_prefix, i as u16));
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                        break;
-                    }
-                }
-            }
-            _ => {}
-        }
-
-        match state {
-            State::Command { begin } => {
-                for (i, b) in iter.by_ref() {
-                    if b == b' ' {
-                        command.replace((begin, i as u16));
-                        state = State::Params;
-                        break;
-                    }
-                }
-            }
-            _ => {}
-        }
-
-        while let Some((i, b)) = iter.next() {
-            match state {
-                State::Params => {
-                    if b == b':' {
-                        state = State::ParamsTrailing {
-                            begin: i as u16 + 1,
-                        };
-                    } else {
-                        state = State::ParamsMiddle { begin: i as u16 };
-                    }
-                }
-                State::ParamsMiddle { begin } => {
-                    for (i, b) in iter.by_ref() {
-                        if b == b' ' {
-                            params.push((begin, i as u16));
-                            state = State::Params;
-                            break;
-                        }
-                    }
-                    // params.push((begin, raw.len() as u16));
-                }
-                State::ParamsTrailing { begin } => {
-                    //while let Some(_) = iter.next() {}
-                    iter.for_each(drop);
-                    params.push((begin, raw.len() as u16));
-                    break;
-                }
-                _ => {}
-            }
-        }
-
-        match state {
-            State::Command { begin } => {
-                command.replace((begin, raw.len() as u16));
-            }
-            State::ParamsMiddle { begin } => {
-                params.push((begin, raw.len() as u16));
-            }
-            _ => {}
-        }
-
-        Self {
-            raw,
-            command: command.unwrap(),
-            params,
-            prefix,
-            nick,
-            user,
-            host,
-        }
+    #[cfg(feature = "std")]
+    pub fn received(&self) -> Option<SystemTime> {
+        self.received
     }
 
-    pub fn parse_foreach(raw: String) -> Self {
-        let mut prefix: Option<(u16, u16)> = None;
-        let mut nick: Option<(u16, u16)> = None;
-        let mut user: Option<(u16, u16)> = None;
-        let mut host: Option<(u16, u16)> = None;
-        let mut command: Option<(u16, u16)> = None;
-        let mut params: SmallVec<[(u16, u16); 2]> = SmallVec::new();
-
-        enum State {
-            Initial,
-            PrefixNick { begin: u16 },
-            PrefixUser { begin: u16, begin_prefix: u16 },
-            PrefixHost { begin: u16, begin_prefix: u16 },
-            Command { begin: u16 },
-            Params,
-            ParamsMiddle { begin: u16 },
-            ParamsTrailing { begin: u16 },
-        }
-
-        let mut state = State::Initial;
-
-        raw.bytes().enumerate().for_each(|(i, b)| {
-            // if b == b'\r' || b == b'\n' {
-            //     break;
-            // }
-            match state {
-                State::Initial => match b {
-                    b':' => {
-                        state = State::PrefixNick {
-                            begin: i as u16 + 1,
-                        }
-                    }
-                    _ => {
-                        state = State::Command { begin: i as u16 };
-                    }
-                },
-                State::PrefixNick { begin } => match b {
-                    b'!' => {
-                        nick = Some((begin, i as u16));
-
-                        state = State::PrefixUser {
-                            begin: i as u16 + 1,
-                            begin_prefix: begin,
-                        };
-                    }
-                    b'@' => {
-                        nick = Some((begin, i as u16));
-
-                        state = State::PrefixHost {
-                            begin: i as u16 + 1,
-                            begin_prefix: begin,
-                        };
-                    }
-                    b' ' => {
-                        nick = Some((begin, i as u16));
-                        prefix = Some((begin, i as u16));
-
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                    }
-                    _ => {}
-                },
-                State::PrefixUser {
-                    begin,
-                    begin_prefix,
-                } => match b {
-                    b'@' => {
-                        user = Some((begin, i as u16));
-
-                        state = State::PrefixHost {
-                            begin: i as u16 + 1,
-                            begin_prefix,
-                        };
-                    }
-                    b' ' => {
-                        user = Some((begin, i as u16));
-                        prefix = Some((begin_prefix, i as u16));
-
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                    }
-                    _ => {}
-                },
-                State::PrefixHost {
-                    begin,
-                    begin_prefix,
-                } => match b {
-                    b' ' => {
-                        host = Some((begin, i as u16));
-                        prefix = Some((begin_prefix, i as u16));
-
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                    }
-                    _ => {}
-                },
-                State::Command { begin } => match b {
-                    b' ' => {
-                        command = Some((begin, i as u16));
-
-                        state = State::Params;
-                    }
-                    _ => {}
-                },
-                State::Params => match b {
-                    b' ' => {}
-                    b':' => {
-                        state = State::ParamsTrailing {
-                            begin: i as u16 + 1,
-                        };
-                        // break;
-                    }
-                    _ => {
-                        state = State::ParamsMiddle { begin: i as u16 };
-                    }
-                },
-                State::ParamsMiddle { begin } => match b {
-                    b' ' => {
-                        params.push((begin, i as u16));
-
-                        state = State::Params;
-                    }
-                    _ => {}
-                },
-                State::ParamsTrailing { begin: _ } => match b {
-                    _ => {}
-                },
-            }
-        });
-
-        match state {
-            State::Command { begin } => {
-                command = Some((begin, raw.len() as u16));
-            }
-            State::ParamsTrailing { begin } => {
-                params.push((begin, raw.len() as u16));
-            }
-            State::ParamsMiddle { begin } => {
-                params.push((begin, raw.len() as u16));
-            }
-            _ => {}
-        }
-
-        Self {
-            raw,
-            command: command.unwrap(),
-            params,
-            prefix,
-            nick,
-            user,
-            host,
-        }
+    /// The `server-time` of the message, falling back to when it was received.
+    #[cfg(feature = "std")]
+    pub fn time(&self) -> Option<SystemTime> {
+        self.server_time().or(self.received)
     }
 
-    pub fn parse_loop(raw: String) -> Self {
-        let mut prefix: Option<(u16, u16)> = None;
-        let mut nick: Option<(u16, u16)> = None;
-        let mut user: Option<(u16, u16)> = None;
-        let mut host: Option<(u16, u16)> = None;
-        let mut command: Option<(u16, u16)> = None;
-        let mut params: SmallVec<[(u16, u16); 2]> = SmallVec::new();
-
-        enum State {
-            Initial,
-            PrefixNick { begin: u16 },
-            PrefixUser { begin: u16, begin_prefix: u16 },
-            PrefixHost { begin: u16, begin_prefix: u16 },
-            Command { begin: u16 },
-            Params,
-            ParamsMiddle { begin: u16 },
-            ParamsTrailing { begin: u16 },
-        }
-
-        let mut state = State::Initial;
-
-        let mut i = 0;
-        let bytes = raw.as_bytes();
-        loop {
-            if i >= raw.len() {
-                break;
-            }
-            let b = bytes[i];
-            i += 1;
-            if b == b'\r' || b == b'\n' {
-                break;
-            }
-            match state {
-                State::Initial => match b {
-                    b':' => {
-                        state = State::PrefixNick {
-                            begin: i as u16 + 1,
-                        }
-                    }
-                    _ => {
-                        state = State::Command { begin: i as u16 };
-                    }
-                },
-                State::PrefixNick { begin } => match b {
-                    b'!' => {
-                        nick = Some((begin, i as u16));
-
-                        state = State::PrefixUser {
-                            begin: i as u16 + 1,
-                            begin_prefix: begin,
-                        };
-                    }
-                    b'@' => {
-                        nick = Some((begin, i as u16));
-
-                        state = State::PrefixHost {
-                            begin: i as u16 + 1,
-                            begin_prefix: begin,
-                        };
-                    }
-                    b' ' => {
-                        nick = Some((begin, i as u16));
-                        prefix = Some((begin, i as u16));
-
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                    }
-                    _ => {}
-                },
-                State::PrefixUser {
-                    begin,
-                    begin_prefix,
-                } => match b {
-                    b'@' => {
-                        user = Some((begin, i as u16));
-
-                        state = State::PrefixHost {
-                            begin: i as u16 + 1,
-                            begin_prefix,
-                        };
-                    }
-                    b' ' => {
-                        user = Some((begin, i as u16));
-                        prefix = Some((begin_prefix, i as u16));
-
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                    }
-                    _ => {}
-                },
-                State::PrefixHost {
-                    begin,
-                    begin_prefix,
-                } => match b {
-                    b' ' => {
-                        host = Some((begin, i as u16));
-                        prefix = Some((begin_prefix, i as u16));
-
-                        state = State::Command {
-                            begin: i as u16 + 1,
-                        };
-                    }
-                    _ => {}
-                },
-                State::Command { begin } => match b {
-                    b' ' => {
-                        command = Some((begin, i as u16));
-
-                        state = State::Params;
-                    }
-                    _ => {}
-                },
-                State::Params => match b {
-                    b' ' => {}
-                    b':' => {
-                        state = State::ParamsTrailing {
-                            begin: i as u16 + 1,
-                        };
-                        // break;
-                    }
-                    _ => {
-                        state = State::ParamsMiddle { begin: i as u16 };
-                    }
-                },
-                State::ParamsMiddle { begin } => match b {
-                    b' ' => {
-                        params.push((begin, i as u16));
-
-                        state = State::Params;
-                    }
-                    _ => {}
-                },
-                State::ParamsTrailing { begin: _ } => match b {
-                    _ => {}
-                },
-            }
+    /// Parse a message from a string, with or without its `\r\n`.
+    /// A line that isn't a valid message gives one without a command or params,
+    /// use [`ParsedMessage::try_parse`] to tell these apart.
+    /// Panics if `raw` is longer than [`MAX_LEN`].
+    /// Example:
+    /// ```
+    /// use tiny_irc::message::ParsedMessage;
+    /// let msg = ParsedMessage::parse(":irc.example.com 001 test :Welcome to the Internet Relay Network".to_string());
+    /// ```
+    pub fn parse(raw: String) -> Self {
+        assert!(raw.len() <= MAX_LEN, "message longer than MAX_LEN");
+        match parse_single(raw, ParserConfig::lenient()) {
+            Ok(msg) => msg,
+            Err(raw) => Self {
+                raw,
+                ..Self::default()
+            },
         }
+    }
 
-        match state {
-            State::Command { begin } => {
-                command = Some((begin, raw.len() as u16));
-            }
-            State::ParamsTrailing { begin } => {
-                params.push((begin, raw.len() as u16));
-            }
-            State::ParamsMiddle { begin } => {
-                params.push((begin, raw.len() as u16));
-            }
-            _ => {}
-        }
+    /// Parses a message leniently, `None` if it is malformed.
+    pub fn try_parse(raw: String) -> Option<Self> {
+        Self::parse_with(raw, ParserConfig::lenient())
+    }
 
-        Self {
-            raw,
-            command: command.unwrap(),
-            params,
-            prefix,
-            nick,
-            user,
-            host,
-        }
+    /// Parses a message with the same rules as a [`Parser`](crate::parser::Parser)
+    /// using `config`, `None` if it is malformed or longer than [`MAX_LEN`].
+    /// The config's length limits don't apply, as the line is already in memory.
+    pub fn parse_with(raw: String, config: ParserConfig) -> Option<Self> {
+        parse_single(raw, config).ok()
     }
 }
 
@@ -1274,11 +405,13 @@ impl ParsedMessage {
 #[cfg(test)]
 mod tests {
     use super::*;
+    #[cfg(feature = "std")]
+    use std::time::{Duration, UNIX_EPOCH};
 
     #[test]
     fn test_parse() {
         let msg = ":irc.example.com 001 test :Welcome to the Internet Relay Network".to_string();
-        let msg = ParsedMessage::parse_iter(msg);
+        let msg = ParsedMessage::parse(msg);
 
         assert_eq!(
             msg.raw,
@@ -1297,7 +430,7 @@ mod tests {
         let msg =
             ":<nick>!<user>@<user>.tmi.twitch.tv PRIVMSG #<channel> :This is a sample message"
                 .to_string();
-        let msg = ParsedMessage::parse_iter(msg);
+        let msg = ParsedMessage::parse(msg);
 
         assert_eq!(
             msg.raw,
@@ -1314,23 +447,91 @@ mod tests {
         assert_eq!(msg.params(), vec!["#<channel>", "This is a sample message"]);
     }
 
-    // #[test]
-    // fn test_parse_linebreak() {
-    //     let msg =
-    //         ":irc.example.com 001 test :Welcome to the Internet Relay Network\r\n".to_string();
-    //     let msg = ParsedMessage::parse_iter(msg);
-
-    //     assert_eq!(
-    //         msg.raw,
-    //         ":irc.example.com 001 test :Welcome to the Internet Relay Network"
-    //     );
-    //     assert_eq!(msg.command(), "001");
-    //     assert_eq!(msg.prefix(), Some("irc.example.com".to_string()));
-    //     assert_eq!(
-    //         msg.params(),
-    //         vec!["test", "Welcome to the Internet Relay Network"]
-    //     );
-    // }
+    // The tests below pin down where the shared engine differs from the
+    // `parse_*` variants it replaced.
+
+    #[test]
+    fn test_parse_incomplete_does_not_panic() {
+        for raw in ["", ":p", ":only.prefix", "@a=b"] {
+            let msg = ParsedMessage::parse(raw.to_string());
+            assert_eq!(msg.raw, raw);
+            assert_eq!(msg.command(), "");
+            assert!(msg.params().is_empty());
+            assert!(ParsedMessage::try_parse(raw.to_string()).is_none());
+        }
+    }
+
+    #[test]
+    fn test_parse_strips_line_ending() {
+        for raw in ["PING a\r\n", "PING :a\r\n", "PING a\n"] {
+            let msg = ParsedMessage::parse(raw.to_string());
+            assert_eq!(msg.raw, raw);
+            assert_eq!(msg.params(), vec!["a"]);
+        }
+    }
+
+    #[test]
+    fn test_parse_reads_tags() {
+        let msg = ParsedMessage::parse("@a=b PING x".to_string());
+        assert_eq!(msg.command(), "PING");
+        assert_eq!(msg.params(), vec!["x"]);
+        assert_eq!(msg.tags(), vec![("a".to_string(), "b".to_string())]);
+    }
+
+    #[test]
+    fn test_parse_repeated_spaces() {
+        let msg = ParsedMessage::parse("PING  a  :b c".to_string());
+        assert_eq!(msg.params(), vec!["a", "b c"]);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_tag_accessors() {
+        let msg = "@time=2011-10-19T16:40:51.620Z;msgid=63E1033A051D4B41B1AB1FA3CF4B243E;account=bob :bob!b@host PRIVMSG #channel :Hi"
+            .to_string();
+        let msg = ParsedMessage::parse(msg);
+
+        assert_eq!(
+            msg.server_time(),
+            Some(UNIX_EPOCH + Duration::from_millis(1319042451620))
+        );
+        assert_eq!(msg.time(), msg.server_time());
+        assert_eq!(
+            msg.msgid(),
+            Some("63E1033A051D4B41B1AB1FA3CF4B243E".to_string())
+        );
+        assert_eq!(msg.account(), Some("bob".to_string()));
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_tag_accessors_fallback() {
+        let received = UNIX_EPOCH + Duration::from_secs(1_000_000);
+        let msg = ParsedMessage::parse("@account=* :bob!b@host PRIVMSG #channel :Hi".to_string())
+            .with_received(received);
+
+        assert_eq!(msg.server_time(), None);
+        assert_eq!(msg.time(), Some(received));
+        assert_eq!(msg.msgid(), None);
+        assert_eq!(msg.account(), None);
+
+        let msg =
+            ParsedMessage::parse("@time=2021-01-01T01:00:00+01:00 :srv NOTICE * :Hi".to_string());
+        assert_eq!(
+            msg.server_time(),
+            Some(UNIX_EPOCH + Duration::from_secs(1609459200))
+        );
+    }
+
+    #[test]
+    fn test_parse_over_64k() {
+        let nick = "n".repeat(70_000);
+        let raw = format!(":{}!user@host PRIVMSG #channel :Hi", nick);
+        let msg = ParsedMessage::parse(raw);
+        assert_eq!(msg.nick(), Some(nick));
+        assert_eq!(msg.command(), "PRIVMSG");
+        assert_eq!(msg.params(), vec!["#channel", "Hi"]);
+    }
 
     // #[bench]
     // fn bench_parse(b: &mut test::Bencher) {
@@ -1375,95 +576,25 @@ mod tests {
     //     b.iter(|| ParsedMessage::parse(msg.clone()));
     // }
 
-    // #[bench]
-    // fn bench_parse_replace(b: &mut test::Bencher) {
-    //     let msg = ":irc.example.com 001 test :Welcome to the Internet Relay Network".to_string();
-    //     b.iter(|| ParsedMessage::parse_replace(msg.clone()));
-    // }
-
-    // #[bench]
-    // fn bench_parse_small_replace(b: &mut test::Bencher) {
-    //     let msg = "PING".to_string();
-    //     b.iter(|| ParsedMessage::parse_replace(msg.clone()));
-    // }
-
-    // #[bench]
-    // fn bench_parse_long_nick_replace(b: &mut test::Bencher) {
-    //     let front = ":".to_string();
-    //     let nick = "_".repeat(512 - 6);
-    //     let back = " PING".to_string();
-    //     let msg = format!("{}{}{}", front, nick, back);
-
-    //     assert_eq!(msg.len(), 512);
-
-    //     b.iter(|| ParsedMessage::parse_replace(msg.clone()));
-    // }
-
-    // #[bench]
-    // fn bench_parse_long_trailing_replace(b: &mut test::Bencher) {
-    //     let front = ":irc.example.com 001 test :Welcome to the Internet Relay Network".to_string();
-    //     let back = "_".repeat(448);
-    //     let msg = format!("{}{}", front, back);
-
-    //     assert_eq!(msg.len(), 512);
-
-    //     b.iter(|| ParsedMessage::parse_replace(msg.clone()));
-    // }
-
-    // #[bench]
-    // fn bench_parse_foreach(b: &mut test::Bencher) {
-    //     let msg = ":irc.example.com 001 test :Welcome to the Internet Relay Network".to_string();
-    //     b.iter(|| ParsedMessage::parse_foreach(msg.clone()));
-    // }
-
-    // #[bench]
-    // fn bench_parse_small_foreach(b: &mut test::Bencher) {
-    //     let msg = "PING".to_string();
-    //     b.iter(|| ParsedMessage::parse_foreach(msg.clone()));
-    // }
-
-    // #[bench]
-    // fn bench_parse_long_nick_foreach(b: &mut test::Bencher) {
-    //     let front = ":".to_string();
-    //     let nick = "_".repeat(512 - 6);
-    //     let back = " PING".to_string();
-    //     let msg = format!("{}{}{}", front, nick, back);
-
-    //     assert_eq!(msg.len(), 512);
-
-    //     b.iter(|| ParsedMessage::parse_foreach(msg.clone()));
-    // }
-
-    // #[bench]
-    // fn bench_parse_long_trailing_foreach(b: &mut test::Bencher) {
-    //     let front = ":irc.example.com 001 test :Welcome to the Internet Relay Network".to_string();
-    //     let back = "_".repeat(448);
-    //     let msg = format!("{}{}", front, back);
-
-    //     assert_eq!(msg.len(), 512);
-
-    //     b.iter(|| ParsedMessage::parse_foreach(msg.clone()));
-    // }
-
     mod bench {
         use super::*;
 
         extern crate test;
 
-        mod iter {
+        mod parse {
             use super::*;
 
             #[bench]
             fn bench_parse_usual(b: &mut test::Bencher) {
                 let msg = ":irc.example.com 001 test :Welcome to the Internet Relay Network\r\n"
                     .to_string();
-                b.iter(|| ParsedMessage::parse_iter(msg.clone()));
+                b.iter(|| ParsedMessage::parse(msg.clone()));
             }
 
             #[bench]
             fn bench_parse_small(b: &mut test::Bencher) {
                 let msg = "PING \r\n".to_string();
-                b.iter(|| ParsedMessage::parse_iter(msg.clone()));
+                b.iter(|| ParsedMessage::parse(msg.clone()));
             }
 
             #[bench]
@@ -1475,7 +606,7 @@ mod tests {
 
                 assert_eq!(msg.len(), 512);
 
-                b.iter(|| ParsedMessage::parse_iter(msg.clone()));
+                b.iter(|| ParsedMessage::parse(msg.clone()));
             }
 
             #[bench]
@@ -1487,7 +618,7 @@ mod tests {
 
                 assert_eq!(msg.len(), 512);
 
-                b.iter(|| ParsedMessage::parse_iter(msg.clone()));
+                b.iter(|| ParsedMessage::parse(msg.clone()));
             }
 
             #[bench]
@@ -1496,130 +627,116 @@ mod tests {
                     .to_string();
 
                 b.iter(|| {
-                    ParsedMessage::parse_iter(msg.clone());
-                    ParsedMessage::parse_iter(msg.clone())
+                    ParsedMessage::parse(msg.clone());
+                    ParsedMessage::parse(msg.clone())
                 });
             }
         }
 
-        // #[bench]
-        // fn bench_parse_for_iter(b: &mut test::Bencher) {
-        //     let msg = ":irc.example.com 001 test :Welcome to the Internet Relay Network".to_string();
-        //     b.iter(|| ParsedMessage::parse_for_iter(msg.clone()));
-        // }
-
-        // #[bench]
-        // fn bench_parse_small_for_iter(b: &mut test::Bencher) {
-        //     let msg = "PING".to_string();
-        //     b.iter(|| ParsedMessage::parse_for_iter(msg.clone()));
-        // }
-
-        // #[bench]
-        // fn bench_parse_long_nick_for_iter(b: &mut test::Bencher) {
-        //     let front = ":".to_string();
-        //     let nick = "_".repeat(512 - 6);
-        //     let back = " PING".to_string();
-        //     let msg = format!("{}{}{}", front, nick, back);
-
-        //     assert_eq!(msg.len(), 512);
-
-        //     b.iter(|| ParsedMessage::parse_for_iter(msg.clone()));
-        // }
-
-        // #[bench]
-        // fn bench_parse_long_trailing_for_iter(b: &mut test::Bencher) {
-        //     let front = ":irc.example.com 001 test :Welcome to the Internet Relay Network".to_string();
-        //     let back = "_".repeat(448);
-        //     let msg = format!("{}{}", front, back);
-
-        //     assert_eq!(msg.len(), 512);
-
-        //     b.iter(|| ParsedMessage::parse_for_iter(msg.clone()));
-        // }
-
-        #[test]
-        fn test_until() {
-            let vec = vec![1, 2, 3];
-            let mut iter = vec.into_iter();
-            let mut iter_ref = iter.by_ref().until(2);
-            assert_eq!(iter_ref.next(), Some(1));
-            assert_eq!(iter_ref.next(), None);
-            assert_eq!(iter.next(), Some(3));
-            assert_eq!(iter.next(), None);
-        }
+        /// The iterator adapter the byte-at-a-time parse variants stopped at
+        /// line endings with, kept to compare against a plain loop.
+        mod until {
+            use super::*;
 
-        #[test]
-        fn test_until_until() {
-            let vec = vec![1, 2, 3];
-            let mut iter = vec.into_iter();
-            let mut iter_ref = iter.by_ref().until(2).until(1);
-            assert_eq!(iter_ref.next(), None);
-            assert_eq!(iter.next(), Some(2));
-            assert_eq!(iter.next(), Some(3));
-            assert_eq!(iter.next(), None);
-        }
-        #[test]
-        fn test_until_until2() {
-            let vec = vec![1, 2, 3];
-            let mut iter = vec.into_iter();
-            let mut iter_ref = iter.by_ref().until(1).until(2);
-            assert_eq!(iter_ref.next(), None);
-            assert_eq!(iter.next(), Some(2));
-            assert_eq!(iter.next(), Some(3));
-            assert_eq!(iter.next(), None);
-        }
+            struct Until<I: Iterator> {
+                iter: I,
+                until: I::Item,
+            }
+
+            impl<I> Iterator for Until<I>
+            where
+                I: Iterator,
+                I::Item: PartialEq<I::Item>,
+            {
+                type Item = I::Item;
 
-        #[bench]
-        fn bench_until_mapped2(b: &mut test::Bencher) {
-            let mut msg = "_".repeat(512);
-            msg.push('\n');
-            msg.push_str(&"_".repeat(511));
-            b.iter(|| {
-                let mut i1 = msg.bytes();
-                let mut iter = i1.by_ref().until(b'\n').until(b'\r');
-                while let Some(_) = iter.next() {
-                    // if c == b'\n' {
-                    //     break;
-                    // }
+                #[inline(always)]
+                fn next(&mut self) -> Option<Self::Item> {
+                    self.iter.next().filter(|n| n != &self.until)
                 }
-                i1
-                // println!("{:?}", iter.collect::<Vec<_>>());
-            });
-        }
+            }
 
-        #[bench]
-        fn bench_until_mapped(b: &mut test::Bencher) {
-            let mut msg = "_".repeat(512);
-            msg.push('\n');
-            msg.push_str(&"_".repeat(511));
-            b.iter(|| {
-                let mut i1 = msg.bytes();
-                let mut iter = i1.by_ref().until(b'\n');
-                while let Some(_) = iter.next() {
-                    // if c == b'\n' {
-                    //     break;
-                    // }
+            trait UntilExt: Iterator + Sized {
+                fn until(self, until: Self::Item) -> Until<Self> {
+                    Until { iter: self, until }
                 }
-                i1
-                // println!("{:?}", iter.collect::<Vec<_>>());
-            });
-        }
+            }
+
+            impl<I: Iterator> UntilExt for I {}
+
+            #[test]
+            fn test_until() {
+                let vec = vec![1, 2, 3];
+                let mut iter = vec.into_iter();
+                let mut iter_ref = iter.by_ref().until(2);
+                assert_eq!(iter_ref.next(), Some(1));
+                assert_eq!(iter_ref.next(), None);
+                assert_eq!(iter.next(), Some(3));
+                assert_eq!(iter.next(), None);
+            }
+
+            #[test]
+            fn test_until_until() {
+                let vec = vec![1, 2, 3];
+                let mut iter = vec.into_iter();
+                let mut iter_ref = iter.by_ref().until(2).until(1);
+                assert_eq!(iter_ref.next(), None);
+                assert_eq!(iter.next(), Some(2));
+                assert_eq!(iter.next(), Some(3));
+                assert_eq!(iter.next(), None);
+            }
+
+            #[test]
+            fn test_until_until2() {
+                let vec = vec![1, 2, 3];
+                let mut iter = vec.into_iter();
+                let mut iter_ref = iter.by_ref().until(1).until(2);
+                assert_eq!(iter_ref.next(), None);
+                assert_eq!(iter.next(), Some(2));
+                assert_eq!(iter.next(), Some(3));
+                assert_eq!(iter.next(), None);
+            }
+
+            fn line() -> String {
+                let mut msg = "_".repeat(512);
+                msg.push('\n');
+                msg.push_str(&"_".repeat(511));
+                msg
+            }
+
+            #[bench]
+            fn bench_until_mapped2(b: &mut test::Bencher) {
+                let msg = line();
+                b.iter(|| {
+                    let mut i1 = msg.bytes();
+                    i1.by_ref().until(b'\n').until(b'\r').for_each(drop);
+                    i1
+                });
+            }
 
-        #[bench]
-        fn bench_until_raw(b: &mut test::Bencher) {
-            let mut msg = "_".repeat(512);
-            msg.push('\n');
-            msg.push_str(&"_".repeat(511));
-            b.iter(|| {
-                let mut iter = msg.bytes();
-                while let Some(c) = iter.next() {
-                    if c == b'\n' || c == b'\r' {
-                        break;
+            #[bench]
+            fn bench_until_mapped(b: &mut test::Bencher) {
+                let msg = line();
+                b.iter(|| {
+                    let mut i1 = msg.bytes();
+                    i1.by_ref().until(b'\n').for_each(drop);
+                    i1
+                });
+            }
+
+            #[bench]
+            fn bench_until_raw(b: &mut test::Bencher) {
+                let msg = line();
+                b.iter(|| {
+                    let mut iter = msg.bytes();
+                    for c in iter.by_ref() {
+                        if c == b'\n' || c == b'\r' {
+                            break;
+                        }
                     }
-                }
-                iter
-                // println!("{:?}", iter.collect::<Vec<_>>());
-            });
+                    iter
+                });
+            }
         }
     }
 }
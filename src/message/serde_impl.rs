@@ -0,0 +1,175 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt::{Formatter, Result as FResult};
+
+use serde::de::{Error, MapAccess, Visitor};
+use serde::ser::SerializeStruct;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::prelude::*;
+use super::{MessageBuilder, ParsedMessage};
+
+/// Tags keep their order, and with it the message's raw text on a round-trip.
+struct TagList(Vec<(String, String)>);
+
+impl Serialize for TagList {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(key, value)| (key, value)))
+    }
+}
+
+impl<'de> Deserialize<'de> for TagList {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TagVisitor;
+
+        impl<'de> Visitor<'de> for TagVisitor {
+            type Value = TagList;
+
+            fn expecting(&self, f: &mut Formatter<'_>) -> FResult {
+                f.write_str("a map of tags")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<TagList, A::Error> {
+                let mut tags = Vec::new();
+                while let Some(tag) = map.next_entry()? {
+                    tags.push(tag);
+                }
+                Ok(TagList(tags))
+            }
+        }
+
+        deserializer.deserialize_map(TagVisitor)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Prefix {
+    nick: Option<String>,
+    user: Option<String>,
+    host: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct Fields {
+    #[serde(default)]
+    tags: Option<TagList>,
+    #[serde(default)]
+    prefix: Option<Prefix>,
+    command: String,
+    #[serde(default)]
+    params: Vec<String>,
+}
+
+/// Serialized as `{tags, prefix: {nick, user, host}, command, params}`,
+/// with `null` for a message without tags or prefix.
+impl Serialize for ParsedMessage {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let tags = self.tags();
+        let mut state = serializer.serialize_struct("ParsedMessage", 4)?;
+        state.serialize_field("tags", &(!tags.is_empty()).then_some(TagList(tags)))?;
+        state.serialize_field(
+            "prefix",
+            &self.prefix().map(|_| Prefix {
+                nick: self.nick(),
+                user: self.user(),
+                host: self.host(),
+            }),
+        )?;
+        state.serialize_field("command", &self.command())?;
+        state.serialize_field("params", &self.params())?;
+        state.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for ParsedMessage {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let fields = Fields::deserialize(deserializer)?;
+        let mut builder = MessageBuilder::new(&fields.command);
+        for (key, value) in fields.tags.map(|tags| tags.0).unwrap_or_default() {
+            builder = builder.tag(&key, &value);
+        }
+        if let Some(prefix) = fields.prefix {
+            let mut text = prefix.nick.unwrap_or_default();
+            if let Some(user) = prefix.user {
+                text.push('!');
+                text.push_str(&user);
+            }
+            if let Some(host) = prefix.host {
+                text.push('@');
+                text.push_str(&host);
+            }
+            builder = builder.prefix(&text);
+        }
+        for param in &fields.params {
+            builder = builder.param(param);
+        }
+        builder.try_to_message().map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_round_trip() {
+        let msg = ParsedMessage::parse(
+            "@time=2011-10-19T16:40:51.620Z;msgid=a\\sb;+draft/typing :nick!user@host PRIVMSG #channel :Hello there"
+                .to_string(),
+        );
+        let json = serde_json::to_value(&msg).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "tags": {"time": "2011-10-19T16:40:51.620Z", "msgid": "a b", "+draft/typing": ""},
+                "prefix": {"nick": "nick", "user": "user", "host": "host"},
+                "command": "PRIVMSG",
+                "params": ["#channel", "Hello there"],
+            })
+        );
+        let back: ParsedMessage =
+            serde_json::from_str(&serde_json::to_string(&msg).unwrap()).unwrap();
+        assert_eq!(back.to_string(), msg.to_string());
+        assert_eq!(back.tag("msgid"), Some("a b".to_string()));
+    }
+
+    #[test]
+    fn test_without_tags_or_prefix() {
+        let msg = ParsedMessage::parse("PING :x y".to_string());
+        let json = serde_json::to_string(&msg).unwrap();
+        assert_eq!(
+            json,
+            r#"{"tags":null,"prefix":null,"command":"PING","params":["x y"]}"#
+        );
+        assert_eq!(serde_json::from_str::<ParsedMessage>(&json).unwrap(), msg);
+
+        let msg: ParsedMessage =
+            serde_json::from_str(r#"{"prefix":{"nick":"irc.example.com"},"command":"001"}"#)
+                .unwrap();
+        assert_eq!(msg.to_string(), ":irc.example.com 001");
+    }
+
+    #[test]
+    fn test_rejects_invalid() {
+        for json in [
+            r#"{"command":""}"#,
+            r#"{"command":"PRIV MSG"}"#,
+            r#"{"command":"PING","params":["","x"]}"#,
+            r#"{"command":"PING","params":["a b","x"]}"#,
+            r#"{"command":"PING","params":[":a","x"]}"#,
+            r#"{"command":"PING","params":["a\r\nQUIT"]}"#,
+            r#"{"command":"PING","params":["a\u0000"]}"#,
+            r#"{"prefix":{"nick":"a\nb"},"command":"PING"}"#,
+        ] {
+            assert!(
+                serde_json::from_str::<ParsedMessage>(json).is_err(),
+                "{}",
+                json
+            );
+        }
+        let err =
+            serde_json::from_str::<ParsedMessage>(r#"{"command":"PING","params":["a b","x"]}"#)
+                .unwrap_err();
+        assert!(err.to_string().starts_with("invalid param 0"), "{}", err);
+    }
+}
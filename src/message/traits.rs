@@ -1,3 +1,11 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use std::time::SystemTime;
+
+#[cfg(feature = "std")]
+use super::util::parse_rfc3339;
+
 pub trait Message {
     fn command(&self) -> String;
 }
@@ -13,6 +21,28 @@ pub trait Parameterized {
     fn params(&self) -> Vec<String>;
 }
 
-pub trait IRCMessage: Message + Prefixed + Parameterized {}
+pub trait Tagged {
+    fn tags(&self) -> Vec<(String, String)>;
+    fn tag(&self, key: &str) -> Option<String>;
+
+    /// The `time` tag set by the `server-time` capability.
+    #[cfg(feature = "std")]
+    fn server_time(&self) -> Option<SystemTime> {
+        self.tag("time").and_then(|time| parse_rfc3339(&time))
+    }
+
+    /// The `msgid` tag, a server-unique identifier of the message.
+    fn msgid(&self) -> Option<String> {
+        self.tag("msgid").filter(|id| !id.is_empty())
+    }
+
+    /// The `account` tag of the sender, `None` if they are not logged in.
+    fn account(&self) -> Option<String> {
+        self.tag("account")
+            .filter(|account| !account.is_empty() && account != "*")
+    }
+}
+
+pub trait IRCMessage: Message + Prefixed + Parameterized + Tagged {}
 
-impl<T> IRCMessage for T where T: Message + Prefixed + Parameterized {}
+impl<T> IRCMessage for T where T: Message + Prefixed + Parameterized + Tagged {}
@@ -1,54 +1,184 @@
-pub struct Until<I>
-where
-    I: Iterator,
-{
-    iter: I,
-    until: I::Item,
-}
+use alloc::string::String;
+#[cfg(feature = "std")]
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
-impl<I: Iterator> Until<I> {
-    #[inline(always)]
-    pub fn new(iter: I, until: I::Item) -> Self {
-        Self { iter, until }
+/// Reverses the IRCv3 message tag value escaping.
+/// `\:`, `\s`, `\\`, `\r` and `\n` map to `;`, space, `\`, CR and LF,
+/// any other escaped character stands for itself and a trailing lone `\` is dropped.
+pub fn unescape_tag_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(':') => out.push(';'),
+            Some('s') => out.push(' '),
+            Some('r') => out.push('\r'),
+            Some('n') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => {}
+        }
     }
+    out
 }
 
-impl<I> Iterator for Until<I>
-where
-    I: Iterator,
-    I::Item: PartialEq<I::Item>,
-{
-    type Item = I::Item;
-
-    #[inline(always)]
-    fn next(&mut self) -> Option<Self::Item> {
-        self.iter.next().filter(|n| n != &self.until)
-        // let n = self.iter.next();
-        // if n.contains(&self.until) {
-        //     None
-        // } else {
-        //     n
-        // }
-        // if let Some(b) = n {
-        //     if b == self.until {
-        //         return None;
-        //     }
-        // }
-        // n
+/// Escapes a tag value so it can be sent inside a message's tag section.
+pub fn escape_tag_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ';' => out.push_str("\\:"),
+            ' ' => out.push_str("\\s"),
+            '\\' => out.push_str("\\\\"),
+            '\r' => out.push_str("\\r"),
+            '\n' => out.push_str("\\n"),
+            c => out.push(c),
+        }
     }
+    out
 }
 
-pub trait UntilExt<I: Iterator> {
-    fn until(self, until: I::Item) -> Until<I>;
+/// Whether `host` is a dotted DNS hostname, each label made of letters,
+/// digits and hyphens that doesn't start or end with a hyphen.
+pub fn is_valid_hostname(host: &str) -> bool {
+    if host.len() > 253 || !host.contains('.') {
+        return false;
+    }
+    host.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+    })
 }
 
-impl<I> UntilExt<I> for I
-where
-    I: Iterator,
-    I::Item: PartialEq<I::Item>,
-{
-    #[inline(always)]
-    fn until(self, until: I::Item) -> Until<I> {
-        Until::new(self, until)
+/// Parses an RFC 3339 timestamp such as `2011-10-19T16:40:51.620Z`.
+#[cfg(feature = "std")]
+pub fn parse_rfc3339(s: &str) -> Option<SystemTime> {
+    let b = s.as_bytes();
+    if b.len() < 20 || b[4] != b'-' || b[7] != b'-' || b[13] != b':' || b[16] != b':' {
+        return None;
+    }
+    if !matches!(b[10], b'T' | b't' | b' ') {
+        return None;
+    }
+    let num = |range: std::ops::Range<usize>| -> Option<i64> {
+        let digits = s.get(range)?;
+        if !digits.bytes().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        digits.parse().ok()
+    };
+    let (year, month, day) = (num(0..4)?, num(5..7)?, num(8..10)?);
+    let (hour, minute, second) = (num(11..13)?, num(14..16)?, num(17..19)?);
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+
+    let mut rest = &s[19..];
+    let mut nanos = 0u32;
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let len = fraction.bytes().take_while(u8::is_ascii_digit).count();
+        if len == 0 {
+            return None;
+        }
+        for (i, c) in fraction[..len].bytes().take(9).enumerate() {
+            nanos += (c - b'0') as u32 * 10u32.pow(8 - i as u32);
+        }
+        rest = &fraction[len..];
+    }
+    let offset = match rest {
+        "Z" | "z" => 0,
+        _ => {
+            let sign = match rest.bytes().next()? {
+                b'+' => 1,
+                b'-' => -1,
+                _ => return None,
+            };
+            if rest.len() != 6 || rest.as_bytes()[3] != b':' {
+                return None;
+            }
+            sign * (num(s.len() - 5..s.len() - 3)? * 3600 + num(s.len() - 2..s.len())? * 60)
+        }
+    };
+
+    let days = days_from_civil(year, month, day);
+    let secs = days * 86400 + hour * 3600 + minute * 60 + second - offset;
+    if secs >= 0 {
+        Some(UNIX_EPOCH + Duration::new(secs as u64, nanos))
+    } else {
+        UNIX_EPOCH
+            .checked_sub(Duration::new((-secs) as u64, 0))?
+            .checked_add(Duration::new(0, nanos))
     }
 }
+
+/// Formats a timestamp as RFC 3339 in UTC with millisecond precision.
+#[cfg(feature = "std")]
+pub fn format_rfc3339(time: SystemTime) -> String {
+    let (secs, millis) = match time.duration_since(UNIX_EPOCH) {
+        Ok(since) => (since.as_secs() as i64, since.subsec_millis()),
+        Err(before) => {
+            let before = before.duration();
+            let mut secs = -(before.as_secs() as i64);
+            let mut millis = before.subsec_millis();
+            if millis > 0 {
+                secs -= 1;
+                millis = 1000 - millis;
+            }
+            (secs, millis)
+        }
+    };
+    let days = secs.div_euclid(86400);
+    let secs_of_day = secs.rem_euclid(86400);
+
+    let (year, month, day) = civil_from_days(days);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60,
+        millis
+    )
+}
+
+/// Days since the epoch of a date, from Howard Hinnant's `days_from_civil`.
+#[cfg(feature = "std")]
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// The `(year, month, day)` of a count of days since the epoch,
+/// from Howard Hinnant's `civil_from_days`.
+#[cfg(feature = "std")]
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
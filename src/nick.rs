@@ -0,0 +1,482 @@
+//! Picking another nick when ours is taken, and taking it back later.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::message::{prelude::*, MessageBuilder, ParsedMessage};
+
+/// Attempts after which registration gives up on finding a nick.
+const MAX_ATTEMPTS: usize = 16;
+
+/// How to derive more nicks from the primary one once the alternates are used up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Suffix {
+    /// `nick_`, `nick__`, ...
+    Underscore,
+    /// `nick1`, `nick2`, ...
+    Number,
+}
+
+/// How to get the primary nick back after registering with another.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Regain {
+    /// Sends NICK once MONITOR reports the nick offline, or ISON every
+    /// `interval` when the server has no MONITOR.
+    Watch { interval: Duration },
+    /// Asks NickServ to disconnect whoever holds the nick, then watches it.
+    Ghost { password: String },
+    /// Asks NickServ to change our nick, for services that support REGAIN.
+    Regain { password: String },
+}
+
+/// Changes to our nick.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NickEvent {
+    /// The server refused a nick with a numeric such as 433.
+    Rejected { nick: String, code: String },
+    /// Our effective nick is now `nick`, on registration or a change.
+    Changed { nick: String, primary: bool },
+    /// Every nick was refused during registration.
+    Exhausted,
+}
+
+fn fold(nick: &str) -> String {
+    nick.to_ascii_lowercase()
+}
+
+/// Whether `nick` can be sent as a single NICK param.
+fn is_valid(nick: &str) -> bool {
+    !nick.is_empty() && !nick.starts_with(':') && !nick.contains([' ', ',', '\r', '\n', '\0'])
+}
+
+/// The line, or `None` if a nick or password in it can't be sent.
+fn line(msg: MessageBuilder) -> Option<String> {
+    msg.try_build().ok()
+}
+
+/// Tracks our nick, answering 433, 432, 436 and 437 during registration with
+/// the next candidate and regaining the primary nick afterwards.
+///
+/// Register with [`NickManager::primary`], then pass every received message
+/// to [`NickManager::handle`] and call [`NickManager::poll`] now and then,
+/// sending the lines they return. Candidates that are empty or contain a space,
+/// `,` or line break are skipped, and an invalid primary nick is never regained.
+#[derive(Debug, Clone)]
+pub struct NickManager {
+    primary: String,
+    alternates: Vec<String>,
+    suffix: Option<Suffix>,
+    max_len: Option<usize>,
+    regain: Option<Regain>,
+    attempt: usize,
+    current: Option<String>,
+    registered: bool,
+    monitor: bool,
+    watching: bool,
+    last_ison: Option<Instant>,
+    events: VecDeque<NickEvent>,
+}
+
+impl NickManager {
+    /// Falls back to underscores and watches for the primary nick every minute.
+    pub fn new(primary: &str) -> Self {
+        Self {
+            primary: primary.to_string(),
+            alternates: Vec::new(),
+            suffix: Some(Suffix::Underscore),
+            max_len: None,
+            regain: Some(Regain::Watch {
+                interval: Duration::from_secs(60),
+            }),
+            attempt: 0,
+            current: None,
+            registered: false,
+            monitor: false,
+            watching: false,
+            last_ison: None,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Nicks to try, in order, before suffixing the primary one.
+    pub fn alternates(mut self, alternates: &[&str]) -> Self {
+        self.alternates = alternates.iter().map(|nick| nick.to_string()).collect();
+        self
+    }
+
+    /// `None` stops after the alternates.
+    pub fn suffix(mut self, suffix: Option<Suffix>) -> Self {
+        self.suffix = suffix;
+        self
+    }
+
+    /// Shortens the primary nick so suffixed ones fit the server's NICKLEN.
+    pub fn max_len(mut self, max_len: usize) -> Self {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    /// `None` keeps whichever nick registration ended with.
+    pub fn regain(mut self, regain: Option<Regain>) -> Self {
+        self.regain = regain;
+        self
+    }
+
+    pub fn primary(&self) -> &str {
+        &self.primary
+    }
+
+    /// Our nick once registered.
+    pub fn current(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    pub fn has_primary(&self) -> bool {
+        self.current
+            .as_deref()
+            .is_some_and(|nick| fold(nick) == fold(&self.primary))
+    }
+
+    pub fn next_event(&mut self) -> Option<NickEvent> {
+        self.events.pop_front()
+    }
+
+    /// The nick for a registration attempt.
+    fn candidate(&self, attempt: usize) -> Option<String> {
+        if attempt == 0 {
+            return Some(self.primary.clone());
+        }
+        if let Some(alternate) = self.alternates.get(attempt - 1) {
+            return Some(alternate.clone());
+        }
+        let n = attempt - self.alternates.len();
+        let suffix = match self.suffix? {
+            Suffix::Underscore => "_".repeat(n),
+            Suffix::Number => n.to_string(),
+        };
+        let mut base = self.primary.as_str();
+        if let Some(max_len) = self.max_len {
+            let len = max_len.checked_sub(suffix.len()).filter(|&len| len > 0)?;
+            base = &base[..base.char_indices().nth(len).map_or(base.len(), |(i, _)| i)];
+        }
+        Some(format!("{}{}", base, suffix))
+    }
+
+    fn set_current(&mut self, nick: &str) {
+        self.current = Some(nick.to_string());
+        let primary = self.has_primary();
+        self.events.push_back(NickEvent::Changed {
+            nick: nick.to_string(),
+            primary,
+        });
+    }
+
+    fn nick_line(&self) -> Vec<String> {
+        line(MessageBuilder::new("NICK").param(&self.primary))
+            .into_iter()
+            .collect()
+    }
+
+    /// Starts regaining the primary nick, once the server told its ISUPPORT.
+    fn start_regain(&mut self) -> Vec<String> {
+        if self.has_primary() || self.watching || !is_valid(&self.primary) {
+            return Vec::new();
+        }
+        let mut lines = Vec::new();
+        match &self.regain {
+            None => return lines,
+            Some(Regain::Regain { password }) => {
+                let text = format!("REGAIN {} {}", self.primary, password);
+                lines.extend(line(
+                    MessageBuilder::new("PRIVMSG").params(&["NickServ", &text]),
+                ));
+                return lines;
+            }
+            Some(Regain::Ghost { password }) => {
+                let text = format!("GHOST {} {}", self.primary, password);
+                lines.extend(line(
+                    MessageBuilder::new("PRIVMSG").params(&["NickServ", &text]),
+                ));
+            }
+            Some(Regain::Watch { .. }) => {}
+        }
+        self.watching = true;
+        if self.monitor {
+            lines.extend(line(
+                MessageBuilder::new("MONITOR").params(&["+", &self.primary]),
+            ));
+        }
+        lines
+    }
+
+    fn stop_watching(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if self.watching && self.monitor {
+            lines.extend(line(
+                MessageBuilder::new("MONITOR").params(&["-", &self.primary]),
+            ));
+        }
+        self.watching = false;
+        lines
+    }
+
+    /// Handles a received message, returning lines to send.
+    pub fn handle(&mut self, msg: &ParsedMessage) -> Vec<String> {
+        let params = msg.params();
+        let param = |i: usize| params.get(i).map(String::as_str).unwrap_or_default();
+        let is_primary = |nick: &str| fold(nick) == fold(&self.primary);
+
+        match msg.command().as_str() {
+            code @ ("432" | "433" | "436" | "437") => {
+                self.events.push_back(NickEvent::Rejected {
+                    nick: param(1).to_string(),
+                    code: code.to_string(),
+                });
+                if self.registered {
+                    return Vec::new();
+                }
+                loop {
+                    self.attempt += 1;
+                    match self.candidate(self.attempt) {
+                        Some(nick) if self.attempt < MAX_ATTEMPTS => {
+                            // Skip candidates that turn out the same once shortened.
+                            if !is_valid(&nick) || fold(&nick) == fold(param(1)) {
+                                continue;
+                            }
+                            return line(MessageBuilder::new("NICK").param(&nick))
+                                .into_iter()
+                                .collect();
+                        }
+                        _ => {
+                            self.events.push_back(NickEvent::Exhausted);
+                            return Vec::new();
+                        }
+                    }
+                }
+            }
+            "001" => {
+                self.registered = true;
+                self.set_current(param(0));
+                Vec::new()
+            }
+            "005" => {
+                let tokens = params
+                    .get(1..params.len().saturating_sub(1))
+                    .unwrap_or_default();
+                if tokens
+                    .iter()
+                    .any(|token| token == "MONITOR" || token.starts_with("MONITOR="))
+                {
+                    self.monitor = true;
+                }
+                Vec::new()
+            }
+            // The end of the MOTD, or its absence, ends the welcome burst.
+            "376" | "422" => self.start_regain(),
+            "NICK" => {
+                let nick = msg.nick().unwrap_or_default();
+                let new = param(0);
+                let mine = self
+                    .current
+                    .as_deref()
+                    .is_some_and(|me| fold(me) == fold(&nick));
+                if mine {
+                    self.set_current(new);
+                    if self.has_primary() {
+                        return self.stop_watching();
+                    }
+                } else if self.watching && is_primary(&nick) {
+                    return self.nick_line();
+                }
+                Vec::new()
+            }
+            "QUIT" if self.watching && is_primary(&msg.nick().unwrap_or_default()) => {
+                self.nick_line()
+            }
+            // RPL_MONOFFLINE
+            "731"
+                if self.watching
+                    && param(1)
+                        .split(',')
+                        .any(|target| is_primary(target.split('!').next().unwrap_or(target))) =>
+            {
+                self.nick_line()
+            }
+            // RPL_ISON
+            "303" if self.watching && !param(1).split(' ').any(is_primary) => self.nick_line(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Returns an ISON query when one is due, for servers without MONITOR.
+    pub fn poll(&mut self, now: Instant) -> Option<String> {
+        if !self.watching || self.monitor {
+            return None;
+        }
+        let interval = match &self.regain {
+            Some(Regain::Watch { interval }) => *interval,
+            _ => Duration::from_secs(60),
+        };
+        if self
+            .last_ison
+            .is_some_and(|last| now.duration_since(last) < interval)
+        {
+            return None;
+        }
+        self.last_ison = Some(now);
+        line(MessageBuilder::new("ISON").param(&self.primary))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn handle(manager: &mut NickManager, line: &str) -> Vec<String> {
+        manager.handle(&ParsedMessage::parse(line.to_string()))
+    }
+
+    #[test]
+    fn test_registration() {
+        let mut manager = NickManager::new("robot").alternates(&["robot2"]).max_len(6);
+        assert_eq!(
+            handle(
+                &mut manager,
+                ":irc.test 433 * robot :Nickname is already in use"
+            ),
+            ["NICK robot2"]
+        );
+        assert_eq!(
+            handle(
+                &mut manager,
+                ":irc.test 437 * robot2 :Nick/channel is temporarily unavailable"
+            ),
+            ["NICK robot_"]
+        );
+        assert_eq!(
+            handle(&mut manager, ":irc.test 432 * robot_ :Erroneous nickname"),
+            ["NICK robo__"]
+        );
+        handle(&mut manager, ":irc.test 001 robo__ :Welcome");
+        assert_eq!(manager.current(), Some("robo__"));
+        let events: Vec<NickEvent> = std::iter::from_fn(|| manager.next_event()).collect();
+        assert_eq!(events.len(), 4);
+        assert_eq!(
+            events[3],
+            NickEvent::Changed {
+                nick: "robo__".to_string(),
+                primary: false
+            }
+        );
+
+        let mut manager = NickManager::new("bot").suffix(None);
+        handle(
+            &mut manager,
+            ":irc.test 433 * bot :Nickname is already in use",
+        );
+        assert_eq!(
+            manager.next_event().unwrap(),
+            NickEvent::Rejected {
+                nick: "bot".to_string(),
+                code: "433".to_string()
+            }
+        );
+        assert_eq!(manager.next_event(), Some(NickEvent::Exhausted));
+    }
+
+    #[test]
+    fn test_regain_monitor() {
+        let mut manager = NickManager::new("bot").suffix(Some(Suffix::Number));
+        assert_eq!(
+            handle(
+                &mut manager,
+                ":irc.test 433 * bot :Nickname is already in use"
+            ),
+            ["NICK bot1"]
+        );
+        handle(&mut manager, ":irc.test 001 bot1 :Welcome");
+        handle(
+            &mut manager,
+            ":irc.test 005 bot1 MONITOR=100 :are supported by this server",
+        );
+        assert_eq!(
+            handle(&mut manager, ":irc.test 376 bot1 :End of MOTD"),
+            ["MONITOR + bot"]
+        );
+        assert_eq!(manager.poll(Instant::now()), None);
+        assert_eq!(
+            handle(&mut manager, ":irc.test 731 bot1 :bot!u@h"),
+            ["NICK bot"]
+        );
+        assert_eq!(
+            handle(&mut manager, ":bot1!u@h NICK bot"),
+            ["MONITOR - bot"]
+        );
+        assert!(manager.has_primary());
+    }
+
+    #[test]
+    fn test_short_isupport() {
+        let mut manager = NickManager::new("bot");
+        handle(&mut manager, ":irc.test 001 bot :Welcome");
+        assert!(handle(&mut manager, ":irc.test 005").is_empty());
+        assert!(handle(&mut manager, ":irc.test 005 bot").is_empty());
+        assert!(handle(&mut manager, ":irc.test 005 bot :MONITOR").is_empty());
+        assert!(!manager.monitor);
+    }
+
+    #[test]
+    fn test_regain_ison() {
+        let interval = Duration::from_secs(30);
+        let mut manager = NickManager::new("bot").regain(Some(Regain::Ghost {
+            password: "hunter2".to_string(),
+        }));
+        handle(
+            &mut manager,
+            ":irc.test 433 * bot :Nickname is already in use",
+        );
+        handle(&mut manager, ":irc.test 001 bot_ :Welcome");
+        assert_eq!(
+            handle(&mut manager, ":irc.test 422 bot_ :MOTD File is missing"),
+            ["PRIVMSG NickServ :GHOST bot hunter2"]
+        );
+        let now = Instant::now();
+        assert_eq!(manager.poll(now).as_deref(), Some("ISON bot"));
+        assert_eq!(manager.poll(now + interval), None);
+        assert!(handle(&mut manager, ":irc.test 303 bot_ :bot").is_empty());
+        assert_eq!(
+            manager.poll(now + interval * 2).as_deref(),
+            Some("ISON bot")
+        );
+        assert_eq!(handle(&mut manager, ":irc.test 303 bot_ :"), ["NICK bot"]);
+
+        let mut manager = NickManager::new("bot").regain(Some(Regain::Regain {
+            password: "hunter2".to_string(),
+        }));
+        handle(&mut manager, ":irc.test 001 bot_ :Welcome");
+        assert_eq!(
+            handle(&mut manager, ":irc.test 376 bot_ :End of MOTD"),
+            ["PRIVMSG NickServ :REGAIN bot hunter2"]
+        );
+        assert_eq!(manager.poll(now), None);
+    }
+
+    #[test]
+    fn test_invalid_nicks() {
+        let mut manager = NickManager::new("ro bot").alternates(&["", "a\r\nQUIT", "robot"]);
+        assert_eq!(
+            handle(&mut manager, ":irc.test 432 * ro :Erroneous nickname"),
+            ["NICK robot"]
+        );
+        handle(&mut manager, ":irc.test 001 robot :Welcome");
+        assert!(handle(&mut manager, ":irc.test 376 robot :End of MOTD").is_empty());
+        assert_eq!(manager.poll(Instant::now()), None);
+
+        let mut manager = NickManager::new("bot").regain(Some(Regain::Ghost {
+            password: "a\r\nQUIT".to_string(),
+        }));
+        handle(&mut manager, ":irc.test 001 bot_ :Welcome");
+        assert!(handle(&mut manager, ":irc.test 376 bot_ :End of MOTD").is_empty());
+        assert_eq!(manager.poll(Instant::now()).as_deref(), Some("ISON bot"));
+    }
+}
@@ -1,410 +1,507 @@
+use memchr::{memchr, memchr2, memrchr};
 use smallvec::SmallVec;
 
-use crate::message::{prelude::*, ParsedMessage};
-// use std::cell::{Cell, RefCell, RefMut};
-// use std::collections::VecDeque;
-use std::mem::{replace, take};
-
-#[derive(Debug)]
-enum State {
-    Start,
-    PrefixNick { begin: u16 },
-    PrefixUser { begin: u16, begin_prefix: u16 },
-    PrefixHost { begin: u16, begin_prefix: u16 },
-    Command { begin: u16 },
-    Params,
-    ParamsMiddle { begin: u16 },
-    ParamsTrailing { begin: u16 },
-    End,
-    Stop,
-}
+use crate::message::{ParsedMessage, MAX_LEN};
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::error::Error;
+use core::fmt::{Display, Formatter, Result as FResult};
+use core::mem::take;
+#[cfg(feature = "std")]
+use std::time::SystemTime;
 
 pub struct Parser {
     buffer: Vec<u8>,
+    /// Start of the bytes not yet handed out as messages.
+    start: usize,
+    /// How far `buffer` has been searched for a line ending.
+    scanned: usize,
+    /// Whether input is dropped until the next line ending.
+    discarding: bool,
+    /// Dropped input, reported once the buffer has been read up to the offset.
+    errors: VecDeque<(usize, ParseError)>,
+    config: ParserConfig,
 }
 
-struct BufferIter<T>
-where
-    T: Iterator,
-{
-    inner: T,
-    pos: u16,
+/// How closely lines have to follow the RFC 1459/2812 grammar.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParserConfig {
+    /// Accept the deviations listed in [`Leniency`] instead of dropping the line.
+    pub lenient: bool,
+    /// Longest line kept, including its line ending. Longer lines are dropped.
+    pub max_line_len: usize,
+    /// Most bytes held that haven't been handed out as messages yet.
+    pub max_buffer_len: usize,
 }
 
-impl<T> BufferIter<T>
-where
-    T: Iterator,
-{
-    #[inline(always)]
-    fn new(inner: T) -> Self {
-        BufferIter { inner, pos: 0 }
+impl Default for ParserConfig {
+    fn default() -> Self {
+        Self {
+            lenient: true,
+            // 8191 bytes of tags on top of the 512 bytes of RFC 1459.
+            max_line_len: 8191 + 512,
+            max_buffer_len: 1 << 20,
+        }
+    }
+}
+
+impl ParserConfig {
+    /// Only lines ending in `\r\n` with single spaces between their parts.
+    pub fn strict() -> Self {
+        Self {
+            lenient: false,
+            ..Self::default()
+        }
+    }
+
+    pub fn lenient() -> Self {
+        Self::default()
+    }
+}
+
+/// Input the parser dropped instead of buffering.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// A line longer than `max_line_len`, of which `len` bytes had arrived.
+    /// The rest of it is discarded up to the next line ending.
+    OverLongLine { len: usize },
+    /// `len` bytes that didn't fit in `max_buffer_len`, including the start
+    /// of the line they belong to. Input is discarded up to the next line ending.
+    BufferFull { len: usize },
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        match self {
+            ParseError::OverLongLine { len } => write!(f, "dropped a line of over {} bytes", len),
+            ParseError::BufferFull { len } => {
+                write!(f, "parser buffer full, dropped {} bytes", len)
+            }
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// A deviation from the grammar accepted in lenient mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Leniency {
+    /// The line ended in `\n` without `\r`.
+    BareLineFeed,
+    /// Spaces or tabs before the tags, prefix or command.
+    LeadingWhitespace,
+    /// More than one space between two parts.
+    RepeatedSpaces,
+    /// Spaces after the last parameter.
+    TrailingSpaces,
+}
+
+const LENIENCIES: [Leniency; 4] = [
+    Leniency::BareLineFeed,
+    Leniency::LeadingWhitespace,
+    Leniency::RepeatedSpaces,
+    Leniency::TrailingSpaces,
+];
+
+/// The leniencies applied while parsing a message.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Leniencies(u8);
+
+impl Leniencies {
+    pub fn insert(&mut self, leniency: Leniency) {
+        self.0 |= 1 << leniency as u8;
+    }
+
+    pub fn contains(&self, leniency: Leniency) -> bool {
+        self.0 & (1 << leniency as u8) != 0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Leniency> {
+        let set = *self;
+        LENIENCIES
+            .into_iter()
+            .filter(move |&leniency| set.contains(leniency))
     }
 }
 
-impl<T> Iterator for BufferIter<T>
-where
-    T: Iterator,
-{
-    type Item = (T::Item, u16);
+struct Scan {
+    lenient: bool,
+    applied: Leniencies,
+}
 
+impl Scan {
+    /// Whether the deviation may be accepted, recording it if so.
     #[inline(always)]
-    fn next(&mut self) -> Option<Self::Item> {
-        self.inner.next().map(|c| {
-            self.pos += 1;
-            (c, self.pos - 1)
-        })
+    fn allow(&mut self, leniency: Leniency) -> bool {
+        if self.lenient {
+            self.applied.insert(leniency);
+        }
+        self.lenient
     }
 }
 
-impl<T> ExactSizeIterator for BufferIter<T>
-where
-    T: ExactSizeIterator,
-{
-    fn len(&self) -> usize {
-        self.inner.len()
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
-#[inline(always)]
-fn parse_start(iter: &mut impl Iterator<Item = (u8, u16)>) -> State {
-    for (c, pos) in iter {
-        debug_assert_eq!(pos, 0);
-        if c == b':' {
-            return State::PrefixNick { begin: 1 };
+impl Parser {
+    pub fn new() -> Self {
+        Self::with_config(ParserConfig::default())
+    }
+
+    pub fn with_config(config: ParserConfig) -> Self {
+        Self {
+            buffer: Vec::new(),
+            start: 0,
+            scanned: 0,
+            discarding: false,
+            errors: VecDeque::new(),
+            config,
+        }
+    }
+
+    pub fn config(&self) -> ParserConfig {
+        self.config
+    }
+
+    pub fn push(&mut self, buf_in: String) {
+        if self.is_empty() && !self.discarding && buf_in.len() <= self.config.max_buffer_len {
+            self.rebase();
+            self.buffer = buf_in.into_bytes();
         } else {
-            return State::Command { begin: 0 };
+            self.push_buf(buf_in.as_bytes());
         }
     }
-    State::Stop
-}
 
-#[inline(always)]
-fn parse_prefix_nick(
-    iter: &mut impl Iterator<Item = (u8, u16)>,
-    begin: u16,
-    prefix: &mut Option<(u16, u16)>,
-    nick: &mut Option<(u16, u16)>,
-) -> State {
-    for (c, pos) in iter {
-        if c == b'!' {
-            nick.replace((begin, pos));
-            return State::PrefixUser {
-                begin: pos + 1,
-                begin_prefix: begin,
-            };
-        } else if c == b'@' {
-            nick.replace((begin, pos));
-            return State::PrefixHost {
-                begin: pos + 1,
-                begin_prefix: begin,
+    pub fn push_buf(&mut self, mut buf_in: &[u8]) {
+        loop {
+            if self.discarding {
+                match memchr(b'\n', buf_in) {
+                    Some(end) => {
+                        self.discarding = false;
+                        buf_in = &buf_in[end + 1..];
+                    }
+                    None => return,
+                }
+            }
+            let pending = self.buffer.len() - self.start;
+            if pending + buf_in.len() <= self.config.max_buffer_len {
+                self.append(buf_in);
+                return;
+            }
+
+            // Keep the complete lines that still fit.
+            let room = self.config.max_buffer_len.saturating_sub(pending);
+            if let Some(end) = memrchr(b'\n', &buf_in[..room]) {
+                self.append(&buf_in[..end + 1]);
+                buf_in = &buf_in[end + 1..];
+                continue;
+            }
+
+            // The partial line at the end of the buffer is continued by `buf_in`.
+            let complete = memrchr(b'\n', &self.buffer[self.start..]).map_or(0, |end| end + 1);
+            let partial = pending - complete;
+            self.buffer.truncate(self.start + complete);
+            self.scanned = self.scanned.min(self.buffer.len());
+            let dropped = memchr(b'\n', buf_in).map_or(buf_in.len(), |end| end + 1);
+            self.discarding = buf_in[..dropped].last() != Some(&b'\n');
+            let err = ParseError::BufferFull {
+                len: partial + dropped,
             };
-        } else if c == b' ' {
-            nick.replace((begin, pos));
-            prefix.replace((begin, pos));
-            return State::Command { begin: pos + 1 };
+            self.errors.push_back((self.buffer.len(), err));
+            buf_in = &buf_in[dropped..];
         }
     }
-    State::Stop
-}
 
-#[inline(always)]
-fn parse_prefix_user(
-    iter: &mut impl Iterator<Item = (u8, u16)>,
-    begin: u16,
-    begin_prefix: u16,
-    prefix: &mut Option<(u16, u16)>,
-    user: &mut Option<(u16, u16)>,
-) -> State {
-    for (c, pos) in iter {
-        if c == b'@' {
-            user.replace((begin, pos));
-            return State::PrefixHost {
-                begin: pos + 1,
-                begin_prefix,
-            };
-        } else if c == b' ' {
-            user.replace((begin, pos));
-            prefix.replace((begin_prefix, pos));
-            return State::Command { begin: pos + 1 };
+    fn append(&mut self, buf_in: &[u8]) {
+        // Only move the unparsed tail once more has been consumed than is left,
+        // so every byte is moved at most once on average.
+        if self.start >= self.buffer.len() - self.start {
+            self.rebase();
         }
+        self.buffer.extend_from_slice(buf_in);
     }
-    State::Stop
-}
 
-#[inline(always)]
-fn parse_prefix_host(
-    iter: &mut impl Iterator<Item = (u8, u16)>,
-    begin: u16,
-    begin_prefix: u16,
-    prefix: &mut Option<(u16, u16)>,
-    host: &mut Option<(u16, u16)>,
-) -> State {
-    for (c, pos) in iter {
-        if c == b' ' {
-            host.replace((begin, pos));
-            prefix.replace((begin_prefix, pos));
-            return State::Command { begin: pos + 1 };
+    /// Moves the bytes not yet handed out to the front of the buffer.
+    fn rebase(&mut self) {
+        let start = self.start;
+        self.buffer.drain(..start);
+        self.scanned -= start;
+        self.start = 0;
+        for (at, _) in &mut self.errors {
+            *at = at.saturating_sub(start);
         }
     }
-    State::Stop
-}
 
-#[inline(always)]
-fn parse_command(
-    iter: &mut impl Iterator<Item = (u8, u16)>,
-    begin: u16,
-    command: &mut Option<(u16, u16)>,
-) -> State {
-    for (c, pos) in iter {
-        if c == b' ' {
-            command.replace((begin, pos));
-            return State::Params;
+    fn is_empty(&self) -> bool {
+        self.start == self.buffer.len()
+    }
+
+    /// Takes the line ending at `end` off the front of the buffer.
+    fn take_line(&mut self, end: usize) -> Vec<u8> {
+        if self.start == 0 && end == self.buffer.len() {
+            self.scanned = 0;
+            for (at, _) in &mut self.errors {
+                *at = 0;
+            }
+            return take(&mut self.buffer);
         }
-        debug_assert_ne!(c as char, '\r');
-        debug_assert_ne!(c as char, '\n');
+        let line = self.buffer[self.start..end].to_vec();
+        self.start = end;
+        self.scanned = end;
+        line
     }
-    State::Stop
 }
 
+/// Where the separator at `pos` is followed by the next part, `None` if the line
+/// ends there. Repeated spaces are skipped if allowed.
 #[inline(always)]
-fn parse_params(iter: &mut impl Iterator<Item = (u8, u16)>) -> State {
-    for (c, pos) in iter {
-        if c == b':' {
-            return State::ParamsTrailing { begin: pos + 1 };
-        } else if c == b'\r' {
-            return State::End;
-        } else {
-            return State::ParamsMiddle { begin: pos };
-        }
+fn after_space(body: &[u8], pos: usize, scan: &mut Scan) -> Option<usize> {
+    let first = pos + 1;
+    let mut pos = first;
+    while body.get(pos) == Some(&b' ') {
+        pos += 1;
+    }
+    if pos == body.len() || (pos > first && !scan.allow(Leniency::RepeatedSpaces)) {
+        return None;
     }
-    State::Stop
+    Some(pos)
 }
 
 #[inline(always)]
-fn parse_params_middle(
-    iter: &mut impl Iterator<Item = (u8, u16)>,
-    begin: u16,
-    params: &mut SmallVec<[(u16, u16); 2]>,
-) -> State {
-    for (c, pos) in iter {
-        if c == b' ' {
-            params.push((begin, pos));
-            return State::Params;
-        } else if c == b'\r' {
-            params.push((begin, pos));
-            return State::End;
+fn find_space(body: &[u8], from: usize) -> usize {
+    memchr(b' ', &body[from..]).map_or(body.len(), |offset| from + offset)
+}
+
+type Span = (u32, u32);
+
+/// Where the parts of a message are in its line.
+struct Spans {
+    prefix: Option<Span>,
+    nick: Option<Span>,
+    user: Option<Span>,
+    host: Option<Span>,
+    command: Span,
+    params: SmallVec<[Span; 2]>,
+    tags: Option<Span>,
+}
+
+/// Runs the parser over one complete line, `body_end` being the offset of its
+/// line ending. A malformed line is handed back as it was.
+fn parse_line(raw: String, body_end: usize, scan: &mut Scan) -> Result<ParsedMessage, String> {
+    if raw.len() > MAX_LEN {
+        return Err(raw);
+    }
+    match locate(&raw.as_bytes()[..body_end], scan) {
+        Some(spans) => {
+            let msg = ParsedMessage::new(
+                raw,
+                spans.prefix,
+                spans.nick,
+                spans.user,
+                spans.host,
+                spans.command,
+                spans.params,
+                spans.tags,
+            );
+            Ok(msg.with_leniencies(scan.applied))
         }
+        None => Err(raw),
     }
-    State::Stop
 }
 
-#[inline(always)]
-fn parse_params_trailing(
-    iter: &mut impl Iterator<Item = (u8, u16)>,
-    begin: u16,
-    params: &mut SmallVec<[(u16, u16); 2]>,
-) -> State {
-    for (c, pos) in iter {
-        if c == b'\r' {
-            params.push((begin, pos));
-            return State::End;
+/// Finds the parts of a line body. They are located with `memchr`, which uses
+/// SIMD where the target supports it and scalar code otherwise.
+fn locate(body: &[u8], scan: &mut Scan) -> Option<Spans> {
+    let span = |begin: usize, end: usize| (begin as u32, end as u32);
+
+    let mut tags = None;
+    let mut prefix = None;
+    let mut nick = None;
+    let mut user = None;
+    let mut host = None;
+    let mut params: SmallVec<[Span; 2]> = SmallVec::new();
+
+    let mut pos = 0;
+    while matches!(body.get(pos), Some(b' ' | b'\t')) {
+        if !scan.allow(Leniency::LeadingWhitespace) {
+            return None;
         }
+        pos += 1;
     }
-    State::Stop
-}
 
-impl Parser {
-    pub fn new() -> Self {
-        Self { buffer: Vec::new() }
+    if body.get(pos) == Some(&b'@') {
+        let end = memchr(b' ', &body[pos..])? + pos;
+        tags = Some(span(pos + 1, end));
+        pos = after_space(body, end, scan)?;
     }
 
-    pub fn push(&mut self, buf_in: String) {
-        if self.buffer.is_empty() {
-            self.buffer = buf_in.into_bytes();
-        } else {
-            self.buffer.append(&mut buf_in.into_bytes());
+    if body.get(pos) == Some(&b':') {
+        let begin = pos + 1;
+        let end = memchr(b' ', &body[begin..])? + begin;
+        let source = &body[begin..end];
+        match memchr2(b'!', b'@', source).map(|offset| begin + offset) {
+            Some(nick_end) if body[nick_end] == b'!' => {
+                nick = Some(span(begin, nick_end));
+                match memchr(b'@', &body[nick_end + 1..end]) {
+                    Some(offset) => {
+                        let user_end = nick_end + 1 + offset;
+                        user = Some(span(nick_end + 1, user_end));
+                        host = Some(span(user_end + 1, end));
+                    }
+                    None => user = Some(span(nick_end + 1, end)),
+                }
+            }
+            Some(nick_end) => {
+                nick = Some(span(begin, nick_end));
+                host = Some(span(nick_end + 1, end));
+            }
+            None => nick = Some(span(begin, end)),
         }
+        prefix = Some(span(begin, end));
+        pos = after_space(body, end, scan)?;
     }
 
-    pub fn push_buf(&mut self, buf_in: &[u8]) {
-        if self.buffer.is_empty() {
-            self.buffer = buf_in.to_vec();
-        } else {
-            self.buffer.extend_from_slice(buf_in);
-        }
+    // A stray `\r` can't be told apart from a line ending from here on.
+    if pos == body.len() || memchr(b'\r', &body[pos..]).is_some() {
+        return None;
     }
-}
 
-impl Iterator for Parser {
-    type Item = ParsedMessage;
+    let command_end = find_space(body, pos);
+    let command = span(pos, command_end);
+    pos = command_end;
 
-    fn next(&mut self) -> Option<ParsedMessage> {
-        let mut state = State::Start;
-        // let buffer = &self.buffer;
-        // let mut iter = BufferIter::new(buffer.iter().cloned());
-        let mut pos: i32 = -1;
-        let mut iter = self.buffer.iter().map(|c| {
+    while pos < body.len() {
+        // `pos` is on the space before the next param.
+        let begin = pos + 1;
+        pos = begin;
+        while body.get(pos) == Some(&b' ') {
             pos += 1;
-            (*c, pos as u16)
-        });
-
-        // println!("pos: {:?}, len: {:?}", iter.pos, iter.len());
-        // while let Some((c, pos)) = iter.next() {
-        //     println!("{:?}", (c as char, pos));
-        // }
-        // println!("pos: {:?}, len: {:?}", iter.pos, iter.len());
-
-        // return None;
-
-        let mut prefix: Option<(u16, u16)> = None;
-        let mut nick: Option<(u16, u16)> = None;
-        let mut user: Option<(u16, u16)> = None;
-        let mut host: Option<(u16, u16)> = None;
-        let mut command: Option<(u16, u16)> = None;
-        let mut params: SmallVec<[(u16, u16); 2]> = SmallVec::new();
-
-        // println!("Start: {:?}", self.state);
-        debug_assert!(matches!(state, State::Start));
-        state = parse_start(&mut iter);
-        // match self.state {
-        //     State::Start => self.parse_start(&mut iter),
-        //     _ => {}
-        // }
-
-        // println!("Nick?: {:?}", self.state);
-        match state {
-            State::PrefixNick { begin } => {
-                state = parse_prefix_nick(&mut iter, begin, &mut prefix, &mut nick);
-                //TODO: move PrefixUser and PrefixHost in here
+        }
+        if pos == body.len() {
+            if !scan.allow(Leniency::TrailingSpaces) {
+                return None;
             }
-            State::Stop => return None,
-            _ => {}
+            break;
         }
-
-        // println!("User?: {:?}", state);
-        match state {
-            State::PrefixUser {
-                begin,
-                begin_prefix,
-            } => state = parse_prefix_user(&mut iter, begin, begin_prefix, &mut prefix, &mut user),
-            State::Stop => return None,
-            _ => {}
+        if pos > begin && !scan.allow(Leniency::RepeatedSpaces) {
+            return None;
         }
-
-        // println!("Host?: {:?}", self.state);
-        match state {
-            State::PrefixHost {
-                begin,
-                begin_prefix,
-            } => state = parse_prefix_host(&mut iter, begin, begin_prefix, &mut prefix, &mut host),
-            State::Stop => return None,
-            _ => {}
+        if body[pos] == b':' {
+            params.push(span(pos + 1, body.len()));
+            break;
         }
+        let end = find_space(body, pos);
+        params.push(span(pos, end));
+        pos = end;
+    }
+
+    Some(Spans {
+        prefix,
+        nick,
+        user,
+        host,
+        command,
+        params,
+        tags,
+    })
+}
 
-        // debug_assert!(matches!(state, State::Command { begin }));
-        // println!("Command?: {:?}", state);
-        match state {
-            State::Command { begin } => state = parse_command(&mut iter, begin, &mut command),
-            State::Stop => return None,
-            _ => {}
+/// Parses a single line that may or may not end in `\r\n`, handing `raw`
+/// back if it is malformed.
+pub(crate) fn parse_single(raw: String, config: ParserConfig) -> Result<ParsedMessage, String> {
+    let mut scan = Scan {
+        lenient: config.lenient,
+        applied: Leniencies::default(),
+    };
+    let body_end = if let Some(body) = raw.strip_suffix("\r\n") {
+        body.len()
+    } else if let Some(body) = raw.strip_suffix('\n') {
+        if !scan.allow(Leniency::BareLineFeed) {
+            return Err(raw);
         }
+        body.len()
+    } else {
+        raw.len()
+    };
+    parse_line(raw, body_end, &mut scan)
+}
 
-        // println!("Params?: {:?}", self.state);
+impl Parser {
+    /// Returns the next complete message, or what was dropped since the last call.
+    /// Bytes already searched for a line ending are not scanned again, and
+    /// taking a line off the front of the buffer doesn't move the bytes
+    /// behind it. Malformed lines are skipped.
+    pub fn next_event(&mut self) -> Option<Result<ParsedMessage, ParseError>> {
         loop {
-            // println!("End?: {:?}", self.state);
-            match state {
-                State::End => {
-                    if let Some((c, pos)) = iter.next() {
-                        if c == b'\n' {
-                            let raw_vec = if pos as usize == self.buffer.len() - 1 {
-                                take(&mut self.buffer)
-                            } else {
-                                let rest = self.buffer.split_off(pos as usize + 1);
-                                replace(&mut self.buffer, rest)
-                            };
-
-                            let raw = unsafe { String::from_utf8_unchecked(raw_vec) };
-                            return Some(ParsedMessage::new(
-                                raw,
-                                prefix,
-                                nick,
-                                user,
-                                host,
-                                command.unwrap(),
-                                params,
-                            ));
-                        }
+            if matches!(self.errors.front(), Some(&(at, _)) if self.start >= at) {
+                return self.errors.pop_front().map(|(_, err)| Err(err));
+            }
+            let end = match memchr(b'\n', &self.buffer[self.scanned..]) {
+                Some(offset) => self.scanned + offset + 1,
+                None => {
+                    self.scanned = self.buffer.len();
+                    let len = self.buffer.len() - self.start;
+                    if len > self.config.max_line_len {
+                        self.buffer.truncate(self.start);
+                        self.scanned = self.start;
+                        self.discarding = true;
+                        return Some(Err(ParseError::OverLongLine { len }));
                     }
                     return None;
                 }
-                State::Stop => return None,
-                _ => {}
-            }
+            };
 
-            // debug_assert!(matches!(state, State::Params));
-            state = parse_params(&mut iter);
-            // match self.state {
-            //     State::Params => self.parse_params(&mut iter),
-            //     _ => {}
-            // }
-
-            // println!("Params2?: {:?}", self.state);
-            match state {
-                State::ParamsMiddle { begin } => {
-                    state = parse_params_middle(&mut iter, begin, &mut params);
-                }
-                State::ParamsTrailing { begin } => {
-                    state = parse_params_trailing(&mut iter, begin, &mut params);
-                }
-                State::Stop => return None,
-                _ => {}
+            let line = self.take_line(end);
+            if line.len() > self.config.max_line_len {
+                return Some(Err(ParseError::OverLongLine { len: line.len() }));
+            }
+            let raw = match String::from_utf8(line) {
+                Ok(raw) => raw,
+                Err(err) => String::from_utf8_lossy(err.as_bytes()).into_owned(),
+            };
+            let mut scan = Scan {
+                lenient: self.config.lenient,
+                applied: Leniencies::default(),
+            };
+            let body_end = match raw.strip_suffix("\r\n") {
+                Some(body) => body.len(),
+                None if scan.allow(Leniency::BareLineFeed) => raw.len() - 1,
+                None => continue,
+            };
+            if let Ok(msg) = parse_line(raw, body_end, &mut scan) {
+                #[cfg(feature = "std")]
+                let msg = msg.with_received(SystemTime::now());
+                return Some(Ok(msg));
             }
         }
+    }
+}
 
-        // while iter.len() > 0 {
-        //     // println!("{:?}", self.state);
-        //     match self.state {
-        //         State::Start => self.parse_start(&mut iter),
-        //         State::PrefixNick { begin } => self.parse_prefix_nick(&mut iter, begin),
-        //         State::PrefixUser {
-        //             begin,
-        //             begin_prefix,
-        //         } => {
-        //             self.parse_prefix_user(&mut iter, begin, begin_prefix);
-        //         }
-        //         State::PrefixHost {
-        //             begin,
-        //             begin_prefix,
-        //         } => {
-        //             self.parse_prefix_host(&mut iter, begin, begin_prefix);
-        //         }
-        //         State::Command { begin } => self.parse_command(&mut iter, begin),
-        //         State::Params => {
-        //             self.parse_params(&mut iter);
-        //         }
-        //         State::ParamsMiddle { begin } => {
-        //             self.parse_params_middle(&mut iter, begin);
-        //         }
-        //         State::ParamsTrailing { begin } => {
-        //             self.parse_params_trailing(&mut iter, begin);
-        //         }
-        //         State::End => {
-        //             if let Some((c, pos)) = iter.next() {
-        //                 if c == b'\n' {
-        //                     self.state = State::Start;
-        //                     return Some(self.build(pos + 1));
-        //                 }
-        //             }
-        //         }
-        //     }
-        // }
-        // None
+impl Iterator for Parser {
+    type Item = ParsedMessage;
+
+    /// Returns the next complete message, skipping over dropped input.
+    fn next(&mut self) -> Option<ParsedMessage> {
+        loop {
+            if let Ok(msg) = self.next_event()? {
+                return Some(msg);
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::message::prelude::*;
     extern crate test;
 
     #[test]
@@ -447,6 +544,23 @@ mod tests {
         assert_eq!(msg.params(), vec!["#<channel>", "This is a sample message"]);
     }
 
+    #[test]
+    fn test_parse_with_tags() {
+        let msg = "@label=1;msgid=abc\\sdef;+draft/typing :nick!user@host TAGMSG #channel\r\n"
+            .to_string();
+        let mut parser = Parser::new();
+        parser.push(msg);
+        let msg = parser.next().unwrap();
+
+        assert_eq!(msg.command(), "TAGMSG");
+        assert_eq!(msg.nick(), Some("nick".to_string()));
+        assert_eq!(msg.params(), vec!["#channel"]);
+        assert_eq!(msg.tag("label"), Some("1".to_string()));
+        assert_eq!(msg.tag("msgid"), Some("abc def".to_string()));
+        assert_eq!(msg.tag("+draft/typing"), Some(String::new()));
+        assert_eq!(msg.tag("time"), None);
+    }
+
     #[test]
     fn test_parse_two_messages() {
         let msg1 = ":irc.example.com 001 test :Message1\r\n".to_string();
@@ -478,6 +592,144 @@ mod tests {
         // assert_eq!(msg.params().len(), 0);
     }
 
+    #[test]
+    fn test_parse_chunked() {
+        let msg =
+            "@time=2022-01-01T00:00:00.000Z :nick!user@host PRIVMSG #channel :hello\r\nPING :x\r\n";
+        let mut parser = Parser::new();
+        let mut parsed = Vec::new();
+        for chunk in msg.as_bytes().chunks(3) {
+            parser.push_buf(chunk);
+            parsed.extend(&mut parser);
+        }
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].params(), vec!["#channel", "hello"]);
+        assert_eq!(parsed[1].command(), "PING");
+        assert!(parser.is_empty());
+    }
+
+    #[test]
+    fn test_parse_skips_malformed() {
+        let mut parser = Parser::with_config(ParserConfig::strict());
+        parser.push_buf(b"PING\n:only.prefix\r\n\r\nPONG :x\r\n\xffINFO \xfe\r\n");
+
+        assert_eq!(parser.next().unwrap().command(), "PONG");
+        let msg = parser.next().unwrap();
+        assert_eq!(msg.params(), vec!["\u{fffd}"]);
+        assert_eq!(parser.next(), None);
+    }
+
+    #[test]
+    fn test_parse_lenient() {
+        let lines = "  PING\n@a=b  :nick  PRIVMSG  #channel   :hi \r\nMODE #c +o  nick  \n";
+        let mut strict = Parser::with_config(ParserConfig::strict());
+        strict.push(lines.to_string());
+        assert_eq!(strict.next(), None);
+
+        let mut parser = Parser::new();
+        parser.push(lines.to_string());
+
+        let ping = parser.next().unwrap();
+        assert_eq!(ping.command(), "PING");
+        assert_eq!(
+            ping.leniencies().iter().collect::<Vec<_>>(),
+            vec![Leniency::BareLineFeed, Leniency::LeadingWhitespace]
+        );
+
+        let privmsg = parser.next().unwrap();
+        assert_eq!(privmsg.tag("a"), Some("b".to_string()));
+        assert_eq!(privmsg.nick(), Some("nick".to_string()));
+        assert_eq!(privmsg.params(), vec!["#channel", "hi "]);
+        assert_eq!(
+            privmsg.leniencies().iter().collect::<Vec<_>>(),
+            vec![Leniency::RepeatedSpaces]
+        );
+
+        let mode = parser.next().unwrap();
+        assert_eq!(mode.params(), vec!["#c", "+o", "nick"]);
+        assert!(mode.leniencies().contains(Leniency::TrailingSpaces));
+        assert!(mode.leniencies().contains(Leniency::RepeatedSpaces));
+
+        parser.push(":srv 001 me :\r\n".to_string());
+        let welcome = parser.next().unwrap();
+        assert_eq!(welcome.params(), vec!["me", ""]);
+        assert!(welcome.leniencies().is_empty());
+    }
+
+    #[test]
+    fn test_parse_over_64k() {
+        let text = "x".repeat(70_000);
+        let mut parser = Parser::with_config(ParserConfig {
+            max_line_len: 1 << 17,
+            ..ParserConfig::default()
+        });
+        parser.push(format!(
+            ":nick!user@host PRIVMSG #channel :{}\r\nPING :after\r\n",
+            text
+        ));
+
+        let msg = parser.next().unwrap();
+        assert_eq!(msg.params(), vec!["#channel".to_string(), text]);
+        assert_eq!(parser.next().unwrap().params(), vec!["after"]);
+    }
+
+    #[test]
+    fn test_over_long_line() {
+        let mut parser = Parser::with_config(ParserConfig {
+            max_line_len: 16,
+            ..ParserConfig::default()
+        });
+        parser.push_buf(b"PING :0123456789\r\nPING :a\r\nPING :01234");
+        assert_eq!(
+            parser.next_event(),
+            Some(Err(ParseError::OverLongLine { len: 18 }))
+        );
+        assert_eq!(parser.next().unwrap().params(), vec!["a"]);
+        assert_eq!(parser.next_event(), None);
+
+        parser.push_buf(b"567890");
+        assert_eq!(
+            parser.next_event(),
+            Some(Err(ParseError::OverLongLine { len: 17 }))
+        );
+        parser.push_buf(b"123456789");
+        parser.push_buf(b"\r\nPING :b\r\n");
+        assert_eq!(parser.next_event().unwrap().unwrap().params(), vec!["b"]);
+        assert_eq!(parser.next_event(), None);
+    }
+
+    #[test]
+    fn test_buffer_full() {
+        let mut parser = Parser::with_config(ParserConfig {
+            max_buffer_len: 24,
+            ..ParserConfig::default()
+        });
+        parser.push_buf(b"PING :a\r\nPING :b");
+        parser.push_buf(b"cdefghijklmnop\r\nPING");
+        parser.push_buf(b" :c\r\nPING :d\r\n");
+
+        // Complete lines in a chunk are kept as long as they fit.
+        assert_eq!(parser.next().unwrap().params(), vec!["a"]);
+        assert_eq!(
+            parser.next_event(),
+            Some(Err(ParseError::BufferFull { len: 23 }))
+        );
+        assert_eq!(parser.next().unwrap().params(), vec!["c"]);
+        assert_eq!(
+            parser.next_event(),
+            Some(Err(ParseError::BufferFull { len: 9 }))
+        );
+
+        parser.push_buf(b"PING :e\r\n");
+        parser.push_buf(b"PING :fghijklmnopqrstuvwxyz\r\n");
+        parser.push_buf(b"PING :g\r\n");
+        assert_eq!(parser.next().unwrap().params(), vec!["e"]);
+        assert!(parser.next_event().unwrap().is_err());
+        assert_eq!(parser.next().unwrap().params(), vec!["g"]);
+        assert_eq!(parser.next_event(), None);
+    }
+
     #[bench]
     fn bench_parse_usual(b: &mut test::Bencher) {
         let msg =
@@ -556,4 +808,47 @@ mod tests {
             parser.next()
         });
     }
+
+    #[bench]
+    fn bench_parse_chunked(b: &mut test::Bencher) {
+        let front = ":irc.example.com 001 test :Welcome to the Internet Relay Network".to_string();
+        let back = "_".repeat(446);
+        let msg = format!("{}{}\r\n", front, back);
+
+        let mut parser = Parser::new();
+        b.iter(|| {
+            for chunk in msg.as_bytes().chunks(16) {
+                parser.push_buf(chunk);
+            }
+            parser.next()
+        });
+    }
+
+    #[bench]
+    fn bench_parse_chunked_next(b: &mut test::Bencher) {
+        let front = ":irc.example.com 001 test :Welcome to the Internet Relay Network".to_string();
+        let back = "_".repeat(446);
+        let msg = format!("{}{}\r\n", front, back);
+
+        let mut parser = Parser::new();
+        b.iter(|| {
+            let mut parsed = None;
+            for chunk in msg.as_bytes().chunks(16) {
+                parser.push_buf(chunk);
+                parsed = parser.next().or(parsed);
+            }
+            parsed
+        });
+    }
+
+    #[bench]
+    fn bench_parse_backlog(b: &mut test::Bencher) {
+        let msg = ":irc.example.com 001 test :Welcome to the Internet Relay Network\r\n".repeat(64);
+
+        let mut parser = Parser::new();
+        b.iter(|| {
+            parser.push_buf(msg.as_bytes());
+            (&mut parser).count()
+        });
+    }
 }
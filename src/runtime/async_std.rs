@@ -0,0 +1,50 @@
+use std::io::Result;
+
+use ::async_std::net::{TcpStream, ToSocketAddrs};
+
+use crate::client::Client;
+
+/// A client on an async-std TCP connection.
+/// async-std streams implement the `futures::io` traits directly.
+pub type AsyncStdClient = Client<TcpStream, TcpStream>;
+
+/// Connects to an IRC server over TCP.
+pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<AsyncStdClient> {
+    let stream = TcpStream::connect(addr).await?;
+    Ok(Client::new(stream.clone(), stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::message::prelude::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn test_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(stream.try_clone().unwrap())
+                .read_line(&mut line)
+                .unwrap();
+            stream
+                .write_all(b":irc.example.com 001 test :Welcome\r\n")
+                .unwrap();
+            line
+        });
+
+        ::async_std::task::block_on(async {
+            let mut client = connect(addr).await.unwrap();
+            client.send("NICK test").await.unwrap();
+            let msg = client.recv().await.unwrap().unwrap();
+            assert_eq!(msg.command(), "001");
+            assert_eq!(client.recv().await.unwrap(), None);
+        });
+        assert_eq!(server.join().unwrap(), "NICK test\r\n");
+    }
+}
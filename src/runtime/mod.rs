@@ -0,0 +1,9 @@
+//! Adapters running [`Client`](crate::client::Client) on an async runtime.
+//!
+//! The client itself only needs `futures::io` streams; these modules connect
+//! it to the runtimes enabled through cargo features.
+
+#[cfg(feature = "async-std")]
+pub mod async_std;
+#[cfg(feature = "tokio")]
+pub mod tokio;
@@ -0,0 +1,74 @@
+use std::io::Result;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use ::tokio::io::ReadBuf;
+use ::tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use ::tokio::net::{TcpStream, ToSocketAddrs};
+
+use crate::client::Client;
+
+/// A client on a tokio TCP connection.
+pub type TokioClient = Client<Compat<OwnedReadHalf>, Compat<OwnedWriteHalf>>;
+
+/// Connects to an IRC server over TCP.
+pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<TokioClient> {
+    let (reader, writer) = TcpStream::connect(addr).await?.into_split();
+    Ok(client(reader, writer))
+}
+
+/// Creates a client from tokio reader and writer halves.
+pub fn client<R, W>(reader: R, writer: W) -> Client<Compat<R>, Compat<W>>
+where
+    R: ::tokio::io::AsyncRead + Unpin,
+    W: ::tokio::io::AsyncWrite + Unpin,
+{
+    Client::new(Compat(reader), Compat(writer))
+}
+
+/// Implements the `futures::io` traits for a tokio stream.
+#[derive(Debug)]
+pub struct Compat<T>(pub T);
+
+impl<T> Compat<T> {
+    pub fn get_ref(&self) -> &T {
+        &self.0
+    }
+
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T: ::tokio::io::AsyncRead + Unpin> futures::io::AsyncRead for Compat<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        let mut buf = ReadBuf::new(buf);
+        match Pin::new(&mut self.0).poll_read(cx, &mut buf) {
+            Poll::Ready(Ok(())) => Poll::Ready(Ok(buf.filled().len())),
+            Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<T: ::tokio::io::AsyncWrite + Unpin> futures::io::AsyncWrite for Compat<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, HashSet};
+use std::time::SystemTime;
+
+use super::ClientId;
+
+/// The channel modes the server supports, `+iklmnst`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChannelModes {
+    /// `+n`, only members may send to the channel.
+    pub no_external: bool,
+    /// `+t`, only operators may change the topic.
+    pub topic_ops: bool,
+    /// `+i`, joining needs an invite.
+    pub invite_only: bool,
+    /// `+m`, only voiced members and operators may send.
+    pub moderated: bool,
+    /// `+s`, hidden from NAMES and WHO of non-members.
+    pub secret: bool,
+    /// `+k`, the key needed to join.
+    pub key: Option<String>,
+    /// `+l`, the most members that can join.
+    pub limit: Option<usize>,
+}
+
+impl ChannelModes {
+    /// The modes as sent in RPL_CHANNELMODEIS, e.g. `["+klnt", "key", "10"]`.
+    pub fn to_params(&self) -> Vec<String> {
+        let mut modes = String::from("+");
+        let mut params = Vec::new();
+        if self.invite_only {
+            modes.push('i');
+        }
+        if let Some(key) = &self.key {
+            modes.push('k');
+            params.push(key.clone());
+        }
+        if let Some(limit) = self.limit {
+            modes.push('l');
+            params.push(limit.to_string());
+        }
+        for (set, mode) in [
+            (self.moderated, 'm'),
+            (self.no_external, 'n'),
+            (self.secret, 's'),
+            (self.topic_ops, 't'),
+        ] {
+            if set {
+                modes.push(mode);
+            }
+        }
+        params.insert(0, modes);
+        params
+    }
+
+    /// Sets a flag mode, returning whether it changed.
+    pub(crate) fn set_flag(&mut self, mode: char, set: bool) -> bool {
+        let flag = match mode {
+            'n' => &mut self.no_external,
+            't' => &mut self.topic_ops,
+            'i' => &mut self.invite_only,
+            'm' => &mut self.moderated,
+            's' => &mut self.secret,
+            _ => return false,
+        };
+        let changed = *flag != set;
+        *flag = set;
+        changed
+    }
+}
+
+/// A member's status in a channel, `+o` and `+v`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Membership {
+    pub op: bool,
+    pub voice: bool,
+}
+
+impl Membership {
+    /// The highest status prefix, as shown in NAMES and WHO.
+    pub fn prefix(&self) -> &'static str {
+        if self.op {
+            "@"
+        } else if self.voice {
+            "+"
+        } else {
+            ""
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Topic {
+    pub text: String,
+    pub setter: String,
+    pub time: SystemTime,
+}
+
+#[derive(Debug, Clone)]
+pub struct Channel {
+    pub(super) name: String,
+    pub(super) topic: Option<Topic>,
+    pub(super) modes: ChannelModes,
+    pub(super) members: BTreeMap<ClientId, Membership>,
+    pub(super) invited: HashSet<ClientId>,
+    pub(super) created: SystemTime,
+}
+
+impl Channel {
+    /// A new channel, `+nt` like on most networks.
+    pub(super) fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            topic: None,
+            modes: ChannelModes {
+                no_external: true,
+                topic_ops: true,
+                ..ChannelModes::default()
+            },
+            members: BTreeMap::new(),
+            invited: HashSet::new(),
+            created: SystemTime::now(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn topic(&self) -> Option<&Topic> {
+        self.topic.as_ref()
+    }
+
+    pub fn modes(&self) -> &ChannelModes {
+        &self.modes
+    }
+
+    pub fn membership(&self, id: ClientId) -> Option<Membership> {
+        self.members.get(&id).copied()
+    }
+
+    pub fn members(&self) -> impl Iterator<Item = (ClientId, Membership)> + '_ {
+        self.members
+            .iter()
+            .map(|(&id, &membership)| (id, membership))
+    }
+
+    pub(super) fn is_op(&self, id: ClientId) -> bool {
+        self.members.get(&id).is_some_and(|member| member.op)
+    }
+
+    /// Whether a client may send messages to the channel under `+n` and `+m`.
+    pub(super) fn can_send(&self, id: ClientId) -> bool {
+        match self.members.get(&id) {
+            Some(member) => !self.modes.moderated || member.op || member.voice,
+            None => !self.modes.no_external && !self.modes.moderated,
+        }
+    }
+}
+
+/// A mode change as applied, to be announced to the channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ModeChange {
+    pub set: bool,
+    pub mode: char,
+    pub arg: Option<String>,
+}
+
+/// Formats applied changes as MODE params, e.g. `["+o-k", "nick", "*"]`.
+pub(crate) fn format_changes(changes: &[ModeChange]) -> Vec<String> {
+    let mut modes = String::new();
+    let mut args = Vec::new();
+    let mut sign = None;
+    for change in changes {
+        if sign != Some(change.set) {
+            modes.push(if change.set { '+' } else { '-' });
+            sign = Some(change.set);
+        }
+        modes.push(change.mode);
+        args.extend(change.arg.clone());
+    }
+    args.insert(0, modes);
+    args
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode_params() {
+        let mut modes = ChannelModes::default();
+        assert_eq!(modes.to_params(), vec!["+"]);
+        assert!(modes.set_flag('t', true));
+        assert!(!modes.set_flag('t', true));
+        modes.key = Some("secret".to_string());
+        modes.limit = Some(10);
+        modes.secret = true;
+        assert_eq!(modes.to_params(), vec!["+klst", "secret", "10"]);
+    }
+
+    #[test]
+    fn test_format_changes() {
+        let change = |set, mode, arg: Option<&str>| ModeChange {
+            set,
+            mode,
+            arg: arg.map(str::to_string),
+        };
+        let changes = [
+            change(true, 'o', Some("nick")),
+            change(true, 'm', None),
+            change(false, 'k', Some("*")),
+            change(false, 'l', None),
+        ];
+        assert_eq!(format_changes(&changes), vec!["+om-kl", "nick", "*"]);
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::io::{Read, Result, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use super::{ClientId, Output, Server};
+use crate::parser::Parser;
+
+struct State {
+    server: Server,
+    /// Lines for each connection's writer thread, so a slow client doesn't
+    /// hold up the others while the lock is held.
+    writers: HashMap<ClientId, Sender<String>>,
+}
+
+impl State {
+    /// Queues everything the server output for the writer threads.
+    fn flush(&mut self) {
+        while let Some(output) = self.server.next_output() {
+            match output {
+                Output::Send(id, line) => {
+                    if let Some(writer) = self.writers.get(&id) {
+                        let _ = writer.send(line);
+                    }
+                }
+                // The writer closes the connection once it sent what was queued.
+                Output::Close(id) => {
+                    self.writers.remove(&id);
+                }
+            }
+        }
+    }
+}
+
+/// A [`Server`] listening on TCP, with a thread per connection.
+///
+/// It runs until the process exits, which suits tests and local development.
+pub struct Ircd {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+}
+
+impl Ircd {
+    /// Binds to `addr`, port 0 picks a free one, and starts accepting clients.
+    pub fn bind<A: ToSocketAddrs>(addr: A, name: &str) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State {
+            server: Server::new(name),
+            writers: HashMap::new(),
+        }));
+        let accept_state = state.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let state = accept_state.clone();
+                thread::spawn(move || serve(state, stream));
+            }
+        });
+        Ok(Self { addr, state })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Runs `f` with the server, e.g. to check channel state from a test.
+    pub fn with_server<T>(&self, f: impl FnOnce(&Server) -> T) -> T {
+        f(&self.state.lock().unwrap().server)
+    }
+}
+
+fn serve(state: Arc<Mutex<State>>, mut stream: TcpStream) {
+    let host = match stream.peer_addr() {
+        Ok(addr) => addr.ip().to_string(),
+        Err(_) => return,
+    };
+    let id = {
+        let mut state = state.lock().unwrap();
+        let writer = match stream.try_clone() {
+            Ok(writer) => writer,
+            Err(_) => return,
+        };
+        let (sender, lines) = channel();
+        thread::spawn(move || write_lines(writer, lines));
+        let id = state.server.connect(&host);
+        state.writers.insert(id, sender);
+        id
+    };
+
+    let mut parser = Parser::new();
+    let mut buf = [0u8; 4096];
+    while let Ok(n @ 1..) = stream.read(&mut buf) {
+        parser.push_buf(&buf[..n]);
+        while let Some(event) = parser.next_event() {
+            if let Ok(msg) = event {
+                let mut state = state.lock().unwrap();
+                state.server.handle(id, &msg);
+                state.flush();
+            }
+        }
+    }
+
+    let mut state = state.lock().unwrap();
+    state.server.disconnect(id, "Connection closed");
+    state.flush();
+    state.writers.remove(&id);
+}
+
+/// Writes queued lines until the sender is dropped, then closes the connection.
+fn write_lines(mut stream: TcpStream, lines: Receiver<String>) {
+    for line in lines {
+        if stream
+            .write_all(format!("{}\r\n", line).as_bytes())
+            .is_err()
+        {
+            break;
+        }
+    }
+    let _ = stream.shutdown(Shutdown::Both);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocking::SyncClient;
+    use crate::message::prelude::*;
+
+    #[test]
+    fn test_tcp() -> Result<()> {
+        let ircd = Ircd::bind("127.0.0.1:0", "irc.test")?;
+        let mut alice = SyncClient::connect(ircd.addr())?;
+        let mut bob = SyncClient::connect(ircd.addr())?;
+        alice.register("alice", "alice", "Alice")?;
+        bob.register("bob", "bob", "Bob")?;
+
+        alice.send("JOIN #chan")?;
+        while alice.recv()?.command() != "366" {}
+        bob.send("JOIN #chan")?;
+        while bob.recv()?.command() != "366" {}
+        assert_eq!(
+            alice.recv()?.to_string(),
+            ":bob!bob@127.0.0.1 JOIN #chan\r\n"
+        );
+
+        bob.send("PRIVMSG #chan :hello there")?;
+        assert_eq!(
+            alice.recv()?.to_string(),
+            ":bob!bob@127.0.0.1 PRIVMSG #chan :hello there\r\n"
+        );
+        bob.send("QUIT")?;
+        assert_eq!(
+            alice.recv()?.to_string(),
+            ":bob!bob@127.0.0.1 QUIT :Quit: Client Quit\r\n"
+        );
+        assert_eq!(
+            ircd.with_server(|server| server.channel("#chan").unwrap().members().count()),
+            1
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,1118 @@
+//! A small single-server ircd for local development and integration tests.
+//!
+//! [`Server`] holds the network state and turns client messages into
+//! [`Output`] without doing any I/O, [`Ircd`] serves it over TCP.
+
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::message::{format_rfc3339, prelude::*, MessageBuilder, ParsedMessage};
+
+mod channel;
+mod listener;
+use channel::{format_changes, ModeChange};
+pub use channel::{Channel, ChannelModes, Membership, Topic};
+pub use listener::Ircd;
+
+pub type ClientId = u64;
+
+const NICKLEN: usize = 30;
+const CHANNELLEN: usize = 50;
+
+/// What the server asks of the connections.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    /// Send a line, without its line ending.
+    Send(ClientId, String),
+    /// Close the connection after sending what was queued before.
+    Close(ClientId),
+}
+
+struct User {
+    nick: Option<String>,
+    user: Option<String>,
+    realname: String,
+    host: String,
+    registered: bool,
+    invisible: bool,
+}
+
+impl User {
+    fn prefix(&self) -> String {
+        format!(
+            "{}!{}@{}",
+            self.nick.as_deref().unwrap_or("*"),
+            self.user.as_deref().unwrap_or("*"),
+            self.host
+        )
+    }
+}
+
+fn fold(name: &str) -> String {
+    name.to_ascii_lowercase()
+}
+
+fn is_valid_nick(nick: &str) -> bool {
+    let special = |c: char| "[]\\`_^{|}".contains(c);
+    let mut chars = nick.chars();
+    nick.len() <= NICKLEN
+        && chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || special(c))
+        && chars.all(|c| c.is_ascii_alphanumeric() || special(c) || c == '-')
+}
+
+fn is_valid_channel(name: &str) -> bool {
+    name.len() > 1
+        && name.len() <= CHANNELLEN
+        && name.starts_with('#')
+        && !name.contains([' ', ',', '\x07', ':'])
+}
+
+fn unix_secs(time: SystemTime) -> String {
+    time.duration_since(UNIX_EPOCH)
+        .map(|since| since.as_secs())
+        .unwrap_or(0)
+        .to_string()
+}
+
+/// The state of the network: its users and channels.
+pub struct Server {
+    name: String,
+    created: SystemTime,
+    next_id: ClientId,
+    users: HashMap<ClientId, User>,
+    /// Folded nick to client.
+    nicks: HashMap<String, ClientId>,
+    /// Folded channel name to channel.
+    channels: HashMap<String, Channel>,
+    output: VecDeque<Output>,
+}
+
+impl Server {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            created: SystemTime::now(),
+            next_id: 0,
+            users: HashMap::new(),
+            nicks: HashMap::new(),
+            channels: HashMap::new(),
+            output: VecDeque::new(),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Adds a connection from `host`, which has yet to register.
+    pub fn connect(&mut self, host: &str) -> ClientId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.users.insert(
+            id,
+            User {
+                nick: None,
+                user: None,
+                realname: String::new(),
+                host: host.to_string(),
+                registered: false,
+                invisible: false,
+            },
+        );
+        id
+    }
+
+    /// Removes a connection that was closed, telling its channels.
+    pub fn disconnect(&mut self, id: ClientId, reason: &str) {
+        self.quit(id, reason, false);
+    }
+
+    /// The next line to send or connection to close.
+    pub fn next_output(&mut self) -> Option<Output> {
+        self.output.pop_front()
+    }
+
+    pub fn nick(&self, id: ClientId) -> Option<&str> {
+        self.users.get(&id)?.nick.as_deref()
+    }
+
+    pub fn channel(&self, name: &str) -> Option<&Channel> {
+        self.channels.get(&fold(name))
+    }
+
+    /// Handles a message from a client.
+    pub fn handle(&mut self, id: ClientId, msg: &ParsedMessage) {
+        let registered = match self.users.get(&id) {
+            Some(user) => user.registered,
+            None => return,
+        };
+        let command = msg.command().to_ascii_uppercase();
+        let params = msg.params();
+        // A NUL can't be relayed to other clients.
+        if params.iter().any(|param| param.contains('\0')) {
+            return;
+        }
+        let params: Vec<&str> = params.iter().map(String::as_str).collect();
+        match command.as_str() {
+            "PING" => self.ping(id, &params),
+            "PONG" => {}
+            "QUIT" => {
+                let reason = format!("Quit: {}", params.first().unwrap_or(&"Client Quit"));
+                self.quit(id, &reason, true);
+            }
+            "CAP" => self.cap(id, &params),
+            "NICK" => self.nick_command(id, &params),
+            "USER" => self.user_command(id, &params),
+            _ if !registered => self.numeric(id, "451", &["You have not registered"]),
+            "JOIN" => self.join_command(id, &params),
+            "PART" => self.part_command(id, &params),
+            "PRIVMSG" | "NOTICE" => self.message(id, &command, &params),
+            "TOPIC" => self.topic(id, &params),
+            "MODE" => self.mode(id, &params),
+            "NAMES" => self.names_command(id, &params),
+            "WHO" => self.who(id, &params),
+            "INVITE" => self.invite(id, &params),
+            _ => self.numeric(id, "421", &[&command, "Unknown command"]),
+        }
+    }
+
+    /// Messages that can't be serialized are dropped rather than sent malformed.
+    fn send(&mut self, id: ClientId, msg: MessageBuilder) {
+        if let Ok(line) = msg.try_build() {
+            self.output.push_back(Output::Send(id, line));
+        }
+    }
+
+    fn send_all<I: IntoIterator<Item = ClientId>>(&mut self, ids: I, msg: MessageBuilder) {
+        let line = match msg.try_build() {
+            Ok(line) => line,
+            Err(_) => return,
+        };
+        for id in ids {
+            self.output.push_back(Output::Send(id, line.clone()));
+        }
+    }
+
+    /// Sends a numeric reply, addressed to the client's nick. Echoed input
+    /// that can't be a middle param, such as `#a b` from `JOIN :#a b`, becomes `*`.
+    fn numeric(&mut self, id: ClientId, code: &str, params: &[&str]) {
+        let target = self.nick(id).unwrap_or("*").to_string();
+        let mut msg = MessageBuilder::new(code).prefix(&self.name).param(&target);
+        if let Some((text, echoed)) = params.split_last() {
+            for param in echoed {
+                let valid = !param.is_empty() && !param.contains(' ') && !param.starts_with(':');
+                msg = msg.param(if valid { param } else { "*" });
+            }
+            msg = msg.param(text);
+        }
+        self.send(id, msg);
+    }
+
+    fn prefix(&self, id: ClientId) -> String {
+        self.users.get(&id).map(User::prefix).unwrap_or_default()
+    }
+
+    fn member_ids(&self, key: &str) -> Vec<ClientId> {
+        self.channels
+            .get(key)
+            .map(|channel| channel.members.keys().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// The client and everyone sharing a channel with them.
+    fn peers(&self, id: ClientId) -> BTreeSet<ClientId> {
+        let mut peers: BTreeSet<ClientId> = self
+            .channels
+            .values()
+            .filter(|channel| channel.members.contains_key(&id))
+            .flat_map(|channel| channel.members.keys().copied())
+            .collect();
+        peers.insert(id);
+        peers
+    }
+
+    /// Sends ERR_NEEDMOREPARAMS unless there are at least `count` params.
+    fn need(&mut self, id: ClientId, command: &str, params: &[&str], count: usize) -> bool {
+        if params.len() < count || params[..count].iter().any(|param| param.is_empty()) {
+            self.numeric(id, "461", &[command, "Not enough parameters"]);
+            return false;
+        }
+        true
+    }
+
+    fn ping(&mut self, id: ClientId, params: &[&str]) {
+        match params.last() {
+            Some(token) => {
+                let msg = MessageBuilder::new("PONG")
+                    .prefix(&self.name)
+                    .param(&self.name)
+                    .param(token);
+                self.send(id, msg);
+            }
+            None => self.numeric(id, "409", &["No origin specified"]),
+        }
+    }
+
+    /// No capabilities are offered, so clients negotiating them can go on registering.
+    fn cap(&mut self, id: ClientId, params: &[&str]) {
+        let target = self.nick(id).unwrap_or("*").to_string();
+        let reply = match params.first().map(|sub| sub.to_ascii_uppercase()) {
+            Some(sub) if sub == "LS" || sub == "LIST" => Some((sub, String::new())),
+            Some(sub) if sub == "REQ" => {
+                Some(("NAK".to_string(), params.get(1).unwrap_or(&"").to_string()))
+            }
+            Some(sub) if sub == "END" => None,
+            _ => {
+                self.numeric(
+                    id,
+                    "410",
+                    &[params.first().unwrap_or(&""), "Invalid CAP command"],
+                );
+                None
+            }
+        };
+        if let Some((sub, arg)) = reply {
+            let msg = MessageBuilder::new("CAP")
+                .prefix(&self.name)
+                .params(&[&target, &sub, &arg]);
+            self.send(id, msg);
+        }
+    }
+
+    fn nick_command(&mut self, id: ClientId, params: &[&str]) {
+        let nick = match params.first() {
+            Some(nick) if !nick.is_empty() => *nick,
+            _ => return self.numeric(id, "431", &["No nickname given"]),
+        };
+        if !is_valid_nick(nick) {
+            return self.numeric(id, "432", &[nick, "Erroneous nickname"]);
+        }
+        match self.nicks.get(&fold(nick)) {
+            Some(&owner) if owner != id => {
+                return self.numeric(id, "433", &[nick, "Nickname is already in use"])
+            }
+            _ => {}
+        }
+
+        let prefix = self.prefix(id);
+        let user = self.users.get_mut(&id).unwrap();
+        if user.nick.as_deref() == Some(nick) {
+            return;
+        }
+        if let Some(old) = user.nick.replace(nick.to_string()) {
+            self.nicks.remove(&fold(&old));
+        }
+        self.nicks.insert(fold(nick), id);
+        if self.users[&id].registered {
+            let msg = MessageBuilder::new("NICK").prefix(&prefix).param(nick);
+            self.send_all(self.peers(id), msg);
+        } else {
+            self.try_register(id);
+        }
+    }
+
+    fn user_command(&mut self, id: ClientId, params: &[&str]) {
+        if !self.need(id, "USER", params, 4) {
+            return;
+        }
+        let user = self.users.get_mut(&id).unwrap();
+        if user.registered {
+            return self.numeric(id, "462", &["You may not reregister"]);
+        }
+        user.user = Some(params[0].to_string());
+        user.realname = params[3].to_string();
+        self.try_register(id);
+    }
+
+    fn try_register(&mut self, id: ClientId) {
+        let user = self.users.get_mut(&id).unwrap();
+        if user.registered || user.nick.is_none() || user.user.is_none() {
+            return;
+        }
+        user.registered = true;
+        let prefix = user.prefix();
+        let name = self.name.clone();
+        self.numeric(
+            id,
+            "001",
+            &[&format!("Welcome to the Internet Relay Network {}", prefix)],
+        );
+        self.numeric(
+            id,
+            "002",
+            &[&format!("Your host is {}, running tiny-irc", name)],
+        );
+        let created = format!("This server was created {}", format_rfc3339(self.created));
+        self.numeric(id, "003", &[&created]);
+        self.numeric(id, "004", &[&name, "tiny-irc", "i", "iklmnostv"]);
+        self.numeric(
+            id,
+            "005",
+            &[
+                "CASEMAPPING=ascii",
+                "CHANTYPES=#",
+                "CHANMODES=,k,l,imnst",
+                "PREFIX=(ov)@+",
+                &format!("NICKLEN={}", NICKLEN),
+                &format!("CHANNELLEN={}", CHANNELLEN),
+                "are supported by this server",
+            ],
+        );
+        self.numeric(id, "422", &["MOTD File is missing"]);
+    }
+
+    fn quit(&mut self, id: ClientId, reason: &str, notify: bool) {
+        let user = match self.users.get(&id) {
+            Some(user) => user,
+            None => return,
+        };
+        let host = user.host.clone();
+        if user.registered {
+            let mut peers = self.peers(id);
+            peers.remove(&id);
+            let msg = MessageBuilder::new("QUIT")
+                .prefix(&user.prefix())
+                .param(reason);
+            self.send_all(peers, msg);
+        }
+        self.channels.retain(|_, channel| {
+            channel.members.remove(&id);
+            channel.invited.remove(&id);
+            !channel.members.is_empty()
+        });
+        if notify {
+            let msg =
+                MessageBuilder::new("ERROR").param(&format!("Closing Link: {} ({})", host, reason));
+            self.send(id, msg);
+        }
+        self.output.push_back(Output::Close(id));
+        if let Some(nick) = self.users.remove(&id).and_then(|user| user.nick) {
+            self.nicks.remove(&fold(&nick));
+        }
+    }
+
+    fn join_command(&mut self, id: ClientId, params: &[&str]) {
+        if !self.need(id, "JOIN", params, 1) {
+            return;
+        }
+        if params[0] == "0" {
+            let joined: Vec<String> = self
+                .channels
+                .values()
+                .filter(|channel| channel.members.contains_key(&id))
+                .map(|channel| channel.name.clone())
+                .collect();
+            for name in joined {
+                self.part(id, &name, None);
+            }
+            return;
+        }
+        let mut keys = params
+            .get(1)
+            .map(|keys| keys.split(','))
+            .into_iter()
+            .flatten();
+        for name in params[0].split(',') {
+            self.join(id, name, keys.next());
+        }
+    }
+
+    fn join(&mut self, id: ClientId, name: &str, key: Option<&str>) {
+        if !is_valid_channel(name) {
+            return self.numeric(id, "403", &[name, "No such channel"]);
+        }
+        let folded = fold(name);
+        let channel = self
+            .channels
+            .entry(folded.clone())
+            .or_insert_with(|| Channel::new(name));
+        if channel.members.contains_key(&id) {
+            return;
+        }
+        let name = channel.name.clone();
+        let error = if channel.members.is_empty() {
+            None
+        } else if channel.modes.invite_only && !channel.invited.contains(&id) {
+            Some(("473", "Cannot join channel (+i)"))
+        } else if channel.modes.key.is_some() && channel.modes.key.as_deref() != key {
+            Some(("475", "Cannot join channel (+k)"))
+        } else if channel
+            .modes
+            .limit
+            .is_some_and(|limit| channel.members.len() >= limit)
+        {
+            Some(("471", "Cannot join channel (+l)"))
+        } else {
+            None
+        };
+        if let Some((code, text)) = error {
+            return self.numeric(id, code, &[&name, text]);
+        }
+        let membership = Membership {
+            op: channel.members.is_empty(),
+            voice: false,
+        };
+        channel.members.insert(id, membership);
+        channel.invited.remove(&id);
+        let topic = channel.topic.clone();
+
+        let msg = MessageBuilder::new("JOIN")
+            .prefix(&self.prefix(id))
+            .param(&name);
+        self.send_all(self.member_ids(&folded), msg);
+        if let Some(topic) = topic {
+            self.numeric(id, "332", &[&name, &topic.text]);
+            self.numeric(id, "333", &[&name, &topic.setter, &unix_secs(topic.time)]);
+        }
+        self.names(id, &folded);
+    }
+
+    fn part_command(&mut self, id: ClientId, params: &[&str]) {
+        if !self.need(id, "PART", params, 1) {
+            return;
+        }
+        for name in params[0].split(',') {
+            self.part(id, name, params.get(1).copied());
+        }
+    }
+
+    fn part(&mut self, id: ClientId, name: &str, reason: Option<&str>) {
+        let folded = fold(name);
+        let channel = match self.channels.get(&folded) {
+            Some(channel) => channel,
+            None => return self.numeric(id, "403", &[name, "No such channel"]),
+        };
+        if !channel.members.contains_key(&id) {
+            return self.numeric(id, "442", &[name, "You're not on that channel"]);
+        }
+        let mut msg = MessageBuilder::new("PART")
+            .prefix(&self.prefix(id))
+            .param(&channel.name);
+        if let Some(reason) = reason {
+            msg = msg.param(reason);
+        }
+        self.send_all(self.member_ids(&folded), msg);
+        let channel = self.channels.get_mut(&folded).unwrap();
+        channel.members.remove(&id);
+        if channel.members.is_empty() {
+            self.channels.remove(&folded);
+        }
+    }
+
+    /// PRIVMSG and NOTICE, which never gets an error reply.
+    fn message(&mut self, id: ClientId, command: &str, params: &[&str]) {
+        let notice = command == "NOTICE";
+        if params.first().is_none_or(|target| target.is_empty()) {
+            if !notice {
+                let text = format!("No recipient given ({})", command);
+                self.numeric(id, "411", &[&text]);
+            }
+            return;
+        }
+        let text = match params.get(1) {
+            Some(text) if !text.is_empty() => *text,
+            _ => {
+                if !notice {
+                    self.numeric(id, "412", &["No text to send"]);
+                }
+                return;
+            }
+        };
+        let prefix = self.prefix(id);
+        for target in params[0].split(',') {
+            let recipients: Vec<ClientId> = if target.starts_with('#') {
+                match self.channels.get(&fold(target)) {
+                    Some(channel) if channel.can_send(id) => channel
+                        .members
+                        .keys()
+                        .copied()
+                        .filter(|&member| member != id)
+                        .collect(),
+                    Some(channel) => {
+                        if !notice {
+                            let name = channel.name.clone();
+                            self.numeric(id, "404", &[&name, "Cannot send to channel"]);
+                        }
+                        continue;
+                    }
+                    None => {
+                        if !notice {
+                            self.numeric(id, "403", &[target, "No such channel"]);
+                        }
+                        continue;
+                    }
+                }
+            } else {
+                match self.nicks.get(&fold(target)) {
+                    Some(&recipient) => vec![recipient],
+                    None => {
+                        if !notice {
+                            self.numeric(id, "401", &[target, "No such nick/channel"]);
+                        }
+                        continue;
+                    }
+                }
+            };
+            let msg = MessageBuilder::new(command)
+                .prefix(&prefix)
+                .param(target)
+                .param(text);
+            self.send_all(recipients, msg);
+        }
+    }
+
+    fn topic(&mut self, id: ClientId, params: &[&str]) {
+        if !self.need(id, "TOPIC", params, 1) {
+            return;
+        }
+        let folded = fold(params[0]);
+        let channel = match self.channels.get(&folded) {
+            Some(channel) => channel,
+            None => return self.numeric(id, "403", &[params[0], "No such channel"]),
+        };
+        let name = channel.name.clone();
+        let text = match params.get(1) {
+            Some(text) => *text,
+            None => {
+                return match channel.topic.clone() {
+                    Some(topic) => {
+                        self.numeric(id, "332", &[&name, &topic.text]);
+                        self.numeric(id, "333", &[&name, &topic.setter, &unix_secs(topic.time)]);
+                    }
+                    None => self.numeric(id, "331", &[&name, "No topic is set"]),
+                };
+            }
+        };
+        if !channel.members.contains_key(&id) {
+            return self.numeric(id, "442", &[&name, "You're not on that channel"]);
+        }
+        if channel.modes.topic_ops && !channel.is_op(id) {
+            return self.numeric(id, "482", &[&name, "You're not channel operator"]);
+        }
+        let setter = self.nick(id).unwrap_or_default().to_string();
+        let channel = self.channels.get_mut(&folded).unwrap();
+        channel.topic = (!text.is_empty()).then(|| Topic {
+            text: text.to_string(),
+            setter,
+            time: SystemTime::now(),
+        });
+        let msg = MessageBuilder::new("TOPIC")
+            .prefix(&self.prefix(id))
+            .param(&name)
+            .param(text);
+        self.send_all(self.member_ids(&folded), msg);
+    }
+
+    fn mode(&mut self, id: ClientId, params: &[&str]) {
+        if !self.need(id, "MODE", params, 1) {
+            return;
+        }
+        if params[0].starts_with('#') {
+            self.channel_mode(id, params);
+        } else {
+            self.user_mode(id, params);
+        }
+    }
+
+    fn user_mode(&mut self, id: ClientId, params: &[&str]) {
+        let nick = self.nick(id).unwrap_or_default().to_string();
+        if fold(params[0]) != fold(&nick) {
+            return match self.nicks.contains_key(&fold(params[0])) {
+                true => self.numeric(id, "502", &["Cannot change mode for other users"]),
+                false => self.numeric(id, "401", &[params[0], "No such nick/channel"]),
+            };
+        }
+        let user = self.users.get_mut(&id).unwrap();
+        let modes = match params.get(1) {
+            Some(modes) => *modes,
+            None => {
+                let modes = if user.invisible { "+i" } else { "+" };
+                return self.numeric(id, "221", &[modes]);
+            }
+        };
+        let mut set = true;
+        let mut changes = Vec::new();
+        let mut unknown = false;
+        for mode in modes.chars() {
+            match mode {
+                '+' | '-' => set = mode == '+',
+                'i' if user.invisible != set => {
+                    user.invisible = set;
+                    changes.push(ModeChange {
+                        set,
+                        mode,
+                        arg: None,
+                    });
+                }
+                'i' => {}
+                _ => unknown = true,
+            }
+        }
+        if unknown {
+            self.numeric(id, "501", &["Unknown MODE flag"]);
+        }
+        if !changes.is_empty() {
+            let msg = MessageBuilder::new("MODE")
+                .prefix(&nick)
+                .param(&nick)
+                .param(&format_changes(&changes)[0]);
+            self.send(id, msg);
+        }
+    }
+
+    fn channel_mode(&mut self, id: ClientId, params: &[&str]) {
+        let folded = fold(params[0]);
+        let channel = match self.channels.get(&folded) {
+            Some(channel) => channel,
+            None => return self.numeric(id, "403", &[params[0], "No such channel"]),
+        };
+        let name = channel.name.clone();
+        let modes = match params.get(1) {
+            Some(modes) => *modes,
+            None => {
+                let mut reply = vec![name.clone()];
+                reply.extend(channel.modes.to_params());
+                let created = unix_secs(channel.created);
+                let reply: Vec<&str> = reply.iter().map(String::as_str).collect();
+                self.numeric(id, "324", &reply);
+                return self.numeric(id, "329", &[&name, &created]);
+            }
+        };
+        if !channel.is_op(id) {
+            return self.numeric(id, "482", &[&name, "You're not channel operator"]);
+        }
+
+        let mut args = params[2..].iter();
+        let mut set = true;
+        let mut changes = Vec::new();
+        let mut errors = Vec::new();
+        let nicks = &self.nicks;
+        let channel = self.channels.get_mut(&folded).unwrap();
+        for mode in modes.chars() {
+            let change = |arg: Option<&str>| ModeChange {
+                set,
+                mode,
+                arg: arg.map(str::to_string),
+            };
+            match mode {
+                '+' | '-' => set = mode == '+',
+                'n' | 't' | 'i' | 'm' | 's' => {
+                    if channel.modes.set_flag(mode, set) {
+                        changes.push(change(None));
+                    }
+                }
+                'k' if set => {
+                    if let Some(key) = args.next().filter(|key| {
+                        !key.is_empty() && !key.contains(' ') && !key.starts_with(':')
+                    }) {
+                        channel.modes.key = Some(key.to_string());
+                        changes.push(change(Some(key)));
+                    }
+                }
+                'k' => {
+                    args.next();
+                    if channel.modes.key.take().is_some() {
+                        changes.push(change(Some("*")));
+                    }
+                }
+                'l' if set => {
+                    let limit = args.next().and_then(|limit| limit.parse::<usize>().ok());
+                    if let Some(limit) = limit.filter(|&limit| limit > 0) {
+                        channel.modes.limit = Some(limit);
+                        changes.push(change(Some(&limit.to_string())));
+                    }
+                }
+                'l' => {
+                    if channel.modes.limit.take().is_some() {
+                        changes.push(change(None));
+                    }
+                }
+                'o' | 'v' => {
+                    let nick = match args.next() {
+                        Some(nick) => *nick,
+                        None => continue,
+                    };
+                    let target = match nicks.get(&fold(nick)) {
+                        Some(&target) => target,
+                        None => {
+                            errors.push(vec![
+                                "401".to_string(),
+                                nick.to_string(),
+                                "No such nick/channel".to_string(),
+                            ]);
+                            continue;
+                        }
+                    };
+                    let member = match channel.members.get_mut(&target) {
+                        Some(member) => member,
+                        None => {
+                            errors.push(vec![
+                                "441".to_string(),
+                                nick.to_string(),
+                                name.clone(),
+                                "They aren't on that channel".to_string(),
+                            ]);
+                            continue;
+                        }
+                    };
+                    let flag = if mode == 'o' {
+                        &mut member.op
+                    } else {
+                        &mut member.voice
+                    };
+                    if *flag != set {
+                        *flag = set;
+                        changes.push(change(Some(nick)));
+                    }
+                }
+                _ => errors.push(vec![
+                    "472".to_string(),
+                    mode.to_string(),
+                    "is unknown mode char to me".to_string(),
+                ]),
+            }
+        }
+
+        for error in errors {
+            let params: Vec<&str> = error[1..].iter().map(String::as_str).collect();
+            self.numeric(id, &error[0], &params);
+        }
+        if !changes.is_empty() {
+            let changes = format_changes(&changes);
+            let changes: Vec<&str> = changes.iter().map(String::as_str).collect();
+            let msg = MessageBuilder::new("MODE")
+                .prefix(&self.prefix(id))
+                .param(&name)
+                .params(&changes);
+            self.send_all(self.member_ids(&folded), msg);
+        }
+    }
+
+    fn visible(&self, id: ClientId, channel: &Channel) -> bool {
+        !channel.modes.secret || channel.members.contains_key(&id)
+    }
+
+    fn names_command(&mut self, id: ClientId, params: &[&str]) {
+        match params.first().filter(|names| !names.is_empty()) {
+            Some(names) => {
+                for name in names.split(',') {
+                    let folded = fold(name);
+                    match self.channels.get(&folded) {
+                        Some(channel) if self.visible(id, channel) => self.names(id, &folded),
+                        _ => self.numeric(id, "366", &[name, "End of /NAMES list"]),
+                    }
+                }
+            }
+            None => {
+                let mut visible: Vec<String> = self
+                    .channels
+                    .iter()
+                    .filter(|(_, channel)| self.visible(id, channel))
+                    .map(|(folded, _)| folded.clone())
+                    .collect();
+                visible.sort();
+                for folded in visible {
+                    self.names(id, &folded);
+                }
+            }
+        }
+    }
+
+    /// Sends RPL_NAMREPLY and RPL_ENDOFNAMES for a channel.
+    fn names(&mut self, id: ClientId, folded: &str) {
+        let channel = match self.channels.get(folded) {
+            Some(channel) => channel,
+            None => return,
+        };
+        let symbol = if channel.modes.secret { "@" } else { "=" };
+        let names: Vec<String> = channel
+            .members()
+            .filter_map(|(member, membership)| {
+                let nick = self.users.get(&member)?.nick.as_deref()?;
+                Some(format!("{}{}", membership.prefix(), nick))
+            })
+            .collect();
+        let name = channel.name.clone();
+        self.numeric(id, "353", &[symbol, &name, &names.join(" ")]);
+        self.numeric(id, "366", &[&name, "End of /NAMES list"]);
+    }
+
+    fn who(&mut self, id: ClientId, params: &[&str]) {
+        let mask = params.first().copied().unwrap_or("*");
+        let mut replies = Vec::new();
+        let who_reply = |channel: &str, user: &User, status: &str| {
+            vec![
+                channel.to_string(),
+                user.user.clone().unwrap_or_default(),
+                user.host.clone(),
+                self.name.clone(),
+                user.nick.clone().unwrap_or_default(),
+                format!("H{}", status),
+                format!("0 {}", user.realname),
+            ]
+        };
+        if mask.starts_with('#') {
+            if let Some(channel) = self.channels.get(&fold(mask)) {
+                if self.visible(id, channel) {
+                    for (member, membership) in channel.members() {
+                        if let Some(user) = self.users.get(&member) {
+                            replies.push(who_reply(&channel.name, user, membership.prefix()));
+                        }
+                    }
+                }
+            }
+        } else if let Some(user) = self
+            .nicks
+            .get(&fold(mask))
+            .and_then(|target| self.users.get(target))
+        {
+            replies.push(who_reply("*", user, ""));
+        }
+        for reply in replies {
+            let reply: Vec<&str> = reply.iter().map(String::as_str).collect();
+            self.numeric(id, "352", &reply);
+        }
+        self.numeric(id, "315", &[mask, "End of WHO list"]);
+    }
+
+    fn invite(&mut self, id: ClientId, params: &[&str]) {
+        if !self.need(id, "INVITE", params, 2) {
+            return;
+        }
+        let (nick, name) = (params[0], params[1]);
+        let target = match self.nicks.get(&fold(nick)) {
+            Some(&target) => target,
+            None => return self.numeric(id, "401", &[nick, "No such nick/channel"]),
+        };
+        let channel = match self.channels.get_mut(&fold(name)) {
+            Some(channel) => channel,
+            None => return self.numeric(id, "403", &[name, "No such channel"]),
+        };
+        let name = channel.name.clone();
+        let error = if !channel.members.contains_key(&id) {
+            Some(("442", vec![name.as_str(), "You're not on that channel"]))
+        } else if channel.members.contains_key(&target) {
+            Some(("443", vec![nick, name.as_str(), "is already on channel"]))
+        } else if channel.modes.invite_only && !channel.is_op(id) {
+            Some(("482", vec![name.as_str(), "You're not channel operator"]))
+        } else {
+            channel.invited.insert(target);
+            None
+        };
+        if let Some((code, params)) = error {
+            return self.numeric(id, code, &params);
+        }
+        self.numeric(id, "341", &[nick, &name]);
+        let msg = MessageBuilder::new("INVITE")
+            .prefix(&self.prefix(id))
+            .param(nick)
+            .param(&name);
+        self.send(target, msg);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send(server: &mut Server, id: ClientId, line: &str) {
+        server.handle(id, &ParsedMessage::parse(line.to_string()));
+    }
+
+    /// The lines sent to `id`, dropping those for other clients.
+    fn received(server: &mut Server, id: ClientId) -> Vec<String> {
+        let mut lines = Vec::new();
+        while let Some(output) = server.next_output() {
+            if let Output::Send(to, line) = output {
+                if to == id {
+                    lines.push(line);
+                }
+            }
+        }
+        lines
+    }
+
+    fn register(server: &mut Server, nick: &str) -> ClientId {
+        let id = server.connect("127.0.0.1");
+        send(server, id, &format!("NICK {}", nick));
+        send(server, id, &format!("USER {} 0 * :Real {}", nick, nick));
+        while server.next_output().is_some() {}
+        id
+    }
+
+    #[test]
+    fn test_registration() {
+        let mut server = Server::new("irc.test");
+        let id = server.connect("10.0.0.1");
+        send(&mut server, id, "JOIN #chan");
+        send(&mut server, id, "NICK 1bad");
+        send(&mut server, id, "NICK alice");
+        send(&mut server, id, "USER alice 0 * :Alice A");
+        let lines = received(&mut server, id);
+        assert_eq!(lines[0], ":irc.test 451 * :You have not registered");
+        assert_eq!(lines[1], ":irc.test 432 * 1bad :Erroneous nickname");
+        assert_eq!(
+            lines[2],
+            ":irc.test 001 alice :Welcome to the Internet Relay Network alice!alice@10.0.0.1"
+        );
+        assert!(lines[6].starts_with(":irc.test 005 alice CASEMAPPING=ascii"));
+
+        let bob = server.connect("10.0.0.2");
+        send(&mut server, bob, "NICK ALICE");
+        assert_eq!(
+            received(&mut server, bob),
+            vec![":irc.test 433 * ALICE :Nickname is already in use"]
+        );
+
+        send(&mut server, id, "PING :abc");
+        send(&mut server, id, "QUIT :bye");
+        assert_eq!(
+            received(&mut server, id),
+            vec![
+                ":irc.test PONG irc.test abc",
+                "ERROR :Closing Link: 10.0.0.1 (Quit: bye)"
+            ]
+        );
+        send(&mut server, bob, "NICK alice");
+        assert!(received(&mut server, bob).is_empty());
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        let mut server = Server::new("irc.test");
+        let id = register(&mut server, "alice");
+        send(&mut server, id, "JOIN :#a b");
+        send(&mut server, id, "WHO :foo bar");
+        send(&mut server, id, "NICK ::x");
+        send(&mut server, id, "CAP");
+        assert_eq!(
+            received(&mut server, id),
+            vec![
+                ":irc.test 403 alice * :No such channel",
+                ":irc.test 315 alice * :End of WHO list",
+                ":irc.test 432 alice * :Erroneous nickname",
+                ":irc.test 410 alice * :Invalid CAP command",
+            ]
+        );
+
+        send(&mut server, id, "JOIN #chan");
+        while server.next_output().is_some() {}
+        send(&mut server, id, "PRIVMSG #chan :a\0b");
+        send(&mut server, id, "MODE #chan +k ::x");
+        assert!(received(&mut server, id).is_empty());
+    }
+
+    #[test]
+    fn test_channel() {
+        let mut server = Server::new("irc.test");
+        let alice = register(&mut server, "alice");
+        let bob = register(&mut server, "bob");
+
+        send(&mut server, alice, "JOIN #chan");
+        assert_eq!(
+            received(&mut server, alice),
+            vec![
+                ":alice!alice@127.0.0.1 JOIN #chan",
+                ":irc.test 353 alice = #chan @alice",
+                ":irc.test 366 alice #chan :End of /NAMES list",
+            ]
+        );
+        send(&mut server, alice, "TOPIC #chan :Hello world");
+        send(&mut server, bob, "JOIN #Chan");
+        let lines = received(&mut server, bob);
+        assert_eq!(lines[0], ":bob!bob@127.0.0.1 JOIN #chan");
+        assert_eq!(lines[1], ":irc.test 332 bob #chan :Hello world");
+        assert_eq!(lines[3], ":irc.test 353 bob = #chan :@alice bob");
+
+        send(&mut server, bob, "PRIVMSG #chan :hi all");
+        assert_eq!(
+            received(&mut server, alice),
+            vec![":bob!bob@127.0.0.1 PRIVMSG #chan :hi all"]
+        );
+        send(&mut server, bob, "TOPIC #chan :mine");
+        send(&mut server, bob, "PRIVMSG carol :hi");
+        assert_eq!(
+            received(&mut server, bob),
+            vec![
+                ":irc.test 482 bob #chan :You're not channel operator",
+                ":irc.test 401 bob carol :No such nick/channel",
+            ]
+        );
+
+        send(&mut server, alice, "WHO #chan");
+        let lines = received(&mut server, alice);
+        assert_eq!(
+            lines[0],
+            ":irc.test 352 alice #chan alice 127.0.0.1 irc.test alice H@ :0 Real alice"
+        );
+        assert_eq!(lines[2], ":irc.test 315 alice #chan :End of WHO list");
+
+        send(&mut server, bob, "PART #chan :later");
+        send(&mut server, alice, "PART #chan");
+        assert!(server.channel("#chan").is_none());
+    }
+
+    #[test]
+    fn test_modes() {
+        let mut server = Server::new("irc.test");
+        let alice = register(&mut server, "alice");
+        let bob = register(&mut server, "bob");
+        let carol = register(&mut server, "carol");
+        send(&mut server, alice, "JOIN #chan");
+        send(&mut server, bob, "JOIN #chan");
+        received(&mut server, alice);
+
+        send(&mut server, alice, "MODE #chan +mvkl-t bob secret 2 x");
+        assert_eq!(
+            received(&mut server, alice),
+            vec![":alice!alice@127.0.0.1 MODE #chan +mvkl-t bob secret 2"]
+        );
+        send(&mut server, alice, "MODE #chan");
+        assert_eq!(
+            received(&mut server, alice)[0],
+            ":irc.test 324 alice #chan +klmn secret 2"
+        );
+
+        send(&mut server, carol, "JOIN #chan");
+        send(&mut server, carol, "JOIN #chan secret");
+        send(&mut server, carol, "PRIVMSG #chan :hi");
+        assert_eq!(
+            received(&mut server, carol),
+            vec![
+                ":irc.test 475 carol #chan :Cannot join channel (+k)",
+                ":irc.test 471 carol #chan :Cannot join channel (+l)",
+                ":irc.test 404 carol #chan :Cannot send to channel",
+            ]
+        );
+
+        send(&mut server, alice, "MODE #chan -l+i");
+        send(&mut server, carol, "JOIN #chan secret");
+        send(&mut server, alice, "INVITE carol #chan");
+        send(&mut server, carol, "JOIN #chan secret");
+        let lines = received(&mut server, carol);
+        assert_eq!(
+            lines[0],
+            ":irc.test 473 carol #chan :Cannot join channel (+i)"
+        );
+        assert_eq!(lines[1], ":alice!alice@127.0.0.1 INVITE carol #chan");
+        assert_eq!(lines[2], ":carol!carol@127.0.0.1 JOIN #chan");
+
+        send(&mut server, carol, "PRIVMSG #chan :muted");
+        send(&mut server, bob, "PRIVMSG #chan :voiced");
+        assert_eq!(
+            received(&mut server, alice),
+            vec![":bob!bob@127.0.0.1 PRIVMSG #chan voiced"]
+        );
+        send(&mut server, alice, "MODE #chan +o dave");
+        send(&mut server, alice, "MODE #chan +z");
+        assert_eq!(
+            received(&mut server, alice),
+            vec![
+                ":irc.test 401 alice dave :No such nick/channel",
+                ":irc.test 472 alice z :is unknown mode char to me",
+            ]
+        );
+    }
+}
@@ -0,0 +1,444 @@
+//! A scripted mock server for testing clients without a network.
+//!
+//! ```
+//! use tiny_irc::testing::{MockStream, Script};
+//!
+//! let script: Script = "expect NICK foo\nsend :srv 001 foo :hi".parse().unwrap();
+//! let stream = MockStream::new(script);
+//! ```
+
+use std::collections::VecDeque;
+use std::error::Error as StdError;
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::thread::{self, JoinHandle};
+
+use futures::io::{AsyncRead, AsyncWrite};
+
+use crate::duplex::{Duplex, Next, Peer, ReadStep};
+use crate::message::{MessageBuilder, ParsedMessage};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Step {
+    /// Wait for the client to send a line equal to this one.
+    /// Lines are compared as messages, so `PRIVMSG #c :hi` matches `PRIVMSG #c hi`.
+    Expect(String),
+    /// Send a line to the client.
+    Send(String),
+}
+
+/// The steps the mock server goes through, written one per line as
+/// `expect <line>` or `send <line>`. Blank lines and `#` comments are skipped.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn expect(mut self, line: &str) -> Self {
+        self.steps.push(Step::Expect(line.to_string()));
+        self
+    }
+
+    pub fn send(mut self, line: &str) -> Self {
+        self.steps.push(Step::Send(line.to_string()));
+        self
+    }
+
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+}
+
+impl FromStr for Script {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut script = Script::new();
+        for (number, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            script = match line.split_once(' ') {
+                Some(("expect", line)) => script.expect(line),
+                Some(("send", line)) => script.send(line),
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!("script line {}: expected `expect` or `send`", number + 1),
+                    ))
+                }
+            };
+        }
+        Ok(script)
+    }
+}
+
+/// A client line that didn't match the script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The step of the script, counting from 1.
+    pub step: usize,
+    /// `None` if the client sent a line after the last expected one.
+    pub expected: Option<String>,
+    /// `None` if the client closed the connection or stopped sending.
+    pub received: Option<String>,
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        match (&self.expected, &self.received) {
+            (Some(expected), Some(received)) => {
+                let common = expected
+                    .chars()
+                    .zip(received.chars())
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                write!(
+                    f,
+                    "step {}: the client sent a different line\n- {}\n+ {}\n  {}^",
+                    self.step,
+                    expected,
+                    received,
+                    " ".repeat(common)
+                )
+            }
+            (Some(expected), None) => {
+                write!(
+                    f,
+                    "step {}: the client never sent\n- {}",
+                    self.step, expected
+                )
+            }
+            (None, Some(received)) => write!(
+                f,
+                "step {}: the client sent a line after the end of the script\n+ {}",
+                self.step, received
+            ),
+            (None, None) => write!(f, "step {}: mismatch", self.step),
+        }
+    }
+}
+
+impl StdError for Mismatch {}
+
+fn canonical(line: &str) -> String {
+    ParsedMessage::try_parse(line.to_string())
+        .and_then(|msg| MessageBuilder::from(&msg).try_build().ok())
+        .unwrap_or_else(|| line.to_string())
+}
+
+/// Plays a script, the server side of a [`Duplex`].
+struct ScriptPeer {
+    /// The remaining steps with their number.
+    steps: VecDeque<(usize, Step)>,
+    count: usize,
+    mismatch: Option<Mismatch>,
+}
+
+impl ScriptPeer {
+    fn new(script: Script) -> Self {
+        Self {
+            count: script.steps.len(),
+            steps: script
+                .steps
+                .into_iter()
+                .zip(1..)
+                .map(|(s, n)| (n, s))
+                .collect(),
+            mismatch: None,
+        }
+    }
+
+    /// Matches a line against the next expectation. Lines to send before it stay queued,
+    /// as a server doesn't wait for the client to read them.
+    fn check_line(&mut self, line: &str) -> std::result::Result<(), Mismatch> {
+        let index = self
+            .steps
+            .iter()
+            .position(|(_, step)| matches!(step, Step::Expect(_)));
+        let (number, expected) = match index.map(|index| &self.steps[index]) {
+            Some((number, Step::Expect(expected))) => (*number, expected.clone()),
+            _ => {
+                return Err(Mismatch {
+                    step: self.count + 1,
+                    expected: None,
+                    received: Some(line.to_string()),
+                })
+            }
+        };
+        if canonical(&expected) != canonical(line) {
+            return Err(Mismatch {
+                step: number,
+                expected: Some(expected),
+                received: Some(line.to_string()),
+            });
+        }
+        self.steps.remove(index.unwrap());
+        Ok(())
+    }
+
+    fn finish(&self) -> std::result::Result<(), Mismatch> {
+        if let Some(mismatch) = &self.mismatch {
+            return Err(mismatch.clone());
+        }
+        match self
+            .steps
+            .iter()
+            .find(|(_, step)| matches!(step, Step::Expect(_)))
+        {
+            Some((number, Step::Expect(expected))) => Err(Mismatch {
+                step: *number,
+                expected: Some(expected.clone()),
+                received: None,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Peer for ScriptPeer {
+    /// Lines up to the next expectation are readable, then the end of the stream.
+    fn next(&mut self) -> Next {
+        match self.steps.front() {
+            Some((_, Step::Send(_))) => match self.steps.pop_front() {
+                Some((_, Step::Send(line))) => Next::Line(line),
+                _ => Next::End,
+            },
+            Some((_, Step::Expect(line))) if self.mismatch.is_none() => {
+                Next::WaitForWrite(line.clone())
+            }
+            _ => Next::End,
+        }
+    }
+
+    fn receive(&mut self, line: &str) -> Result<()> {
+        self.check_line(line).map_err(|mismatch| {
+            self.mismatch = Some(mismatch.clone());
+            Error::new(ErrorKind::InvalidData, mismatch)
+        })
+    }
+
+    fn check(&self) -> Result<()> {
+        match &self.mismatch {
+            Some(mismatch) => Err(Error::new(ErrorKind::InvalidData, mismatch.clone())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// An in-memory connection to a scripted server. Reading gives the lines the
+/// script sends up to its next expectation, which writing must then satisfy.
+/// The stream ends after the last step.
+///
+/// Clones share the connection, so one can be used for reading, another for
+/// writing and a third to check [`MockStream::finish`].
+#[derive(Clone)]
+pub struct MockStream {
+    state: Arc<Mutex<Duplex<ScriptPeer>>>,
+}
+
+impl MockStream {
+    pub fn new(script: Script) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(Duplex::new(ScriptPeer::new(script)))),
+        }
+    }
+
+    /// Fails with the first mismatch or the first expectation not met.
+    pub fn finish(&self) -> std::result::Result<(), Mismatch> {
+        self.state.lock().unwrap().peer.finish()
+    }
+}
+
+impl Read for MockStream {
+    /// Fails if the script expects the client to send first,
+    /// as it can't while blocked here.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self.state.lock().unwrap().read(buf) {
+            ReadStep::Read(n) => Ok(n),
+            ReadStep::WaitForWrite(_) | ReadStep::WaitUntil(_) => Err(Error::new(
+                ErrorKind::WouldBlock,
+                "the script expects the client to send a line first",
+            )),
+        }
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.state.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for MockStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        let mut state = self.state.lock().unwrap();
+        match state.read(buf) {
+            ReadStep::Read(n) => Poll::Ready(Ok(n)),
+            ReadStep::WaitForWrite(_) | ReadStep::WaitUntil(_) => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl AsyncWrite for MockStream {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Poll::Ready(self.state.lock().unwrap().write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// A scripted server on a local TCP port, serving a single connection
+/// from a background thread. It closes the connection after the last step.
+pub struct MockServer {
+    addr: SocketAddr,
+    handle: JoinHandle<std::result::Result<(), Mismatch>>,
+}
+
+impl MockServer {
+    pub fn bind(script: Script) -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let handle = thread::spawn(move || {
+            let mut state = Duplex::new(ScriptPeer::new(script));
+            if let Ok((stream, _)) = listener.accept() {
+                serve(&mut state, stream);
+            }
+            state.peer.finish()
+        });
+        Ok(Self { addr, handle })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Waits for the script to run and returns the first mismatch.
+    /// Blocks until a client connected and the connection ended.
+    pub fn finish(self) -> std::result::Result<(), Mismatch> {
+        match self.handle.join() {
+            Ok(result) => result,
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    }
+}
+
+fn serve<S: Read + Write>(state: &mut Duplex<ScriptPeer>, mut stream: S) {
+    let mut buf = [0u8; 4096];
+    loop {
+        match state.read(&mut buf) {
+            ReadStep::Read(0) => return,
+            ReadStep::Read(n) => {
+                if stream.write_all(&buf[..n]).is_err() {
+                    return;
+                }
+            }
+            ReadStep::WaitForWrite(_) | ReadStep::WaitUntil(_) => match stream.read(&mut buf) {
+                Ok(0) | Err(_) => return,
+                Ok(n) => {
+                    if state.write(&buf[..n]).is_err() {
+                        return;
+                    }
+                }
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocking::SyncClient;
+    use crate::client::Client;
+    use crate::message::prelude::*;
+    use futures::executor::block_on;
+
+    const SCRIPT: &str = "
+        # registration
+        expect NICK foo
+        expect USER foo 0 * :Foo
+        send :srv 001 foo :hi
+        expect JOIN #chan
+        send :foo!foo@host JOIN #chan
+    ";
+
+    #[test]
+    fn test_in_memory() {
+        let stream = MockStream::new(SCRIPT.parse().unwrap());
+        let mut client = Client::new(stream.clone(), stream.clone());
+        block_on(async {
+            client.send("NICK foo").await.unwrap();
+            client.send("USER foo 0 * Foo").await.unwrap();
+            assert_eq!(client.recv().await.unwrap().unwrap().command(), "001");
+            client.send("JOIN :#chan").await.unwrap();
+            assert_eq!(client.recv().await.unwrap().unwrap().command(), "JOIN");
+            assert_eq!(client.recv().await.unwrap(), None);
+        });
+        stream.finish().unwrap();
+    }
+
+    #[test]
+    fn test_tcp() {
+        let server = MockServer::bind(SCRIPT.parse().unwrap()).unwrap();
+        let mut client = SyncClient::connect(server.addr()).unwrap();
+        let welcome = client.register("foo", "foo", "Foo").unwrap();
+        assert_eq!(welcome.params(), vec!["foo", "hi"]);
+        client.send("JOIN #chat").unwrap();
+        let err = server.finish().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "step 4: the client sent a different line\n- JOIN #chan\n+ JOIN #chat\n           ^"
+        );
+    }
+
+    #[test]
+    fn test_unmet() {
+        let stream = MockStream::new(Script::new().expect("QUIT"));
+        let err = stream.finish().unwrap_err();
+        assert_eq!(err.to_string(), "step 1: the client never sent\n- QUIT");
+
+        let mut stream = MockStream::new(Script::new().send("PING x"));
+        let err = stream.write(b"PONG x\r\n").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert_eq!(
+            stream.finish().unwrap_err().to_string(),
+            "step 2: the client sent a line after the end of the script\n+ PONG x"
+        );
+        assert!("bogus line".parse::<Script>().is_err());
+    }
+
+    #[test]
+    fn test_unserializable_line() {
+        let mut stream = MockStream::new(Script::new().expect("PRIVMSG #c :a\0b"));
+        stream.write_all(b"PRIVMSG #c :a\0b\r\n").unwrap();
+        stream.finish().unwrap();
+    }
+}
@@ -0,0 +1,532 @@
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter, Result as FResult};
+use std::fs::File;
+use std::io::{BufRead, BufWriter, Error, ErrorKind, Read, Result, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use futures::io::{AsyncRead, AsyncWrite};
+
+use crate::duplex::{Duplex, Next, Peer, ReadStep};
+
+/// Whether a line was received from or sent to the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    In,
+    Out,
+}
+
+/// A line of a session, at its offset from the start of the recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub offset: Duration,
+    pub direction: Direction,
+    /// The line without its line ending.
+    pub line: String,
+}
+
+/// Written as `<seconds> <direction> <line>`, with `<` for received
+/// and `>` for sent lines, e.g. `1.250000 > PONG :irc.example.com`.
+impl Display for Entry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> FResult {
+        let direction = match self.direction {
+            Direction::In => '<',
+            Direction::Out => '>',
+        };
+        write!(
+            f,
+            "{}.{:06} {} {}",
+            self.offset.as_secs(),
+            self.offset.subsec_micros(),
+            direction,
+            self.line
+        )
+    }
+}
+
+impl FromStr for Entry {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::new(ErrorKind::InvalidData, format!("invalid entry {:?}", s));
+        let (offset, rest) = s.split_once(' ').ok_or_else(invalid)?;
+        let (secs, micros) = offset.split_once('.').ok_or_else(invalid)?;
+        let secs = secs.parse::<u64>().map_err(|_| invalid())?;
+        if micros.is_empty() || micros.len() > 6 || !micros.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        let micros = format!("{:0<6}", micros)
+            .parse::<u32>()
+            .map_err(|_| invalid())?;
+        let direction = match rest.get(..2) {
+            Some("< ") => Direction::In,
+            Some("> ") => Direction::Out,
+            _ => return Err(invalid()),
+        };
+        Ok(Self {
+            offset: Duration::from_secs(secs) + Duration::from_micros(micros.into()),
+            direction,
+            line: rest[2..].to_string(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Transcript {
+    pub entries: Vec<Entry>,
+}
+
+impl Transcript {
+    /// Reads a transcript, one entry per line.
+    pub fn read<R: BufRead>(reader: R) -> Result<Self> {
+        let mut entries = Vec::new();
+        for line in reader.lines() {
+            let line = line?;
+            if !line.is_empty() {
+                entries.push(line.parse()?);
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::read(std::io::BufReader::new(File::open(path)?))
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<()> {
+        for entry in &self.entries {
+            writeln!(writer, "{}", entry)?;
+        }
+        writer.flush()
+    }
+}
+
+struct Sink {
+    start: Instant,
+    out: Box<dyn Write + Send>,
+    partial: [Vec<u8>; 2],
+}
+
+impl Sink {
+    fn record(&mut self, direction: Direction, bytes: &[u8]) -> Result<()> {
+        let partial = &mut self.partial[direction as usize];
+        partial.extend_from_slice(bytes);
+        let mut written = false;
+        while let Some(end) = partial.iter().position(|&b| b == b'\n') {
+            let rest = partial.split_off(end + 1);
+            let line = std::mem::replace(partial, rest);
+            let line = String::from_utf8_lossy(&line);
+            let entry = Entry {
+                offset: self.start.elapsed(),
+                direction,
+                line: line.trim_end_matches(['\r', '\n']).to_string(),
+            };
+            writeln!(self.out, "{}", entry)?;
+            written = true;
+        }
+        if written {
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+/// Records the lines passing through wrapped streams into a transcript,
+/// timestamped from when the recorder was created.
+#[derive(Clone)]
+pub struct Recorder {
+    sink: Arc<Mutex<Sink>>,
+}
+
+impl Recorder {
+    pub fn new<W: Write + Send + 'static>(out: W) -> Self {
+        Self {
+            sink: Arc::new(Mutex::new(Sink {
+                start: Instant::now(),
+                out: Box::new(out),
+                partial: [Vec::new(), Vec::new()],
+            })),
+        }
+    }
+
+    /// Records into a new transcript file.
+    pub fn create<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Wraps a stream, recording what is read from it as received
+    /// and what is written to it as sent.
+    pub fn wrap<T>(&self, inner: T) -> Recorded<T> {
+        Recorded {
+            inner,
+            sink: self.sink.clone(),
+        }
+    }
+}
+
+/// A stream whose lines are recorded by a [`Recorder`].
+pub struct Recorded<T> {
+    inner: T,
+    sink: Arc<Mutex<Sink>>,
+}
+
+impl<T> Recorded<T> {
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    fn record(&self, direction: Direction, bytes: &[u8]) -> Result<()> {
+        match self.sink.lock() {
+            Ok(mut sink) => sink.record(direction, bytes),
+            Err(_) => Err(Error::other("recorder poisoned")),
+        }
+    }
+}
+
+impl<T: Read> Read for Recorded<T> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.record(Direction::In, &buf[..n])?;
+        Ok(n)
+    }
+}
+
+impl<T: Write> Write for Recorded<T> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.record(Direction::Out, &buf[..n])?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Recorded<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        let n = match Pin::new(&mut self.inner).poll_read(cx, buf) {
+            Poll::Ready(Ok(n)) => n,
+            other => return other,
+        };
+        Poll::Ready(self.record(Direction::In, &buf[..n]).map(|_| n))
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Recorded<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize>> {
+        let n = match Pin::new(&mut self.inner).poll_write(cx, buf) {
+            Poll::Ready(Ok(n)) => n,
+            other => return other,
+        };
+        Poll::Ready(self.record(Direction::Out, &buf[..n]).map(|_| n))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Pin::new(&mut self.inner).poll_close(cx)
+    }
+}
+
+/// How fast a [`Replay`] delivers received lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pacing {
+    /// At their recorded offsets from the first read.
+    RealTime,
+    /// As soon as the client reads.
+    Fast,
+}
+
+/// Plays a transcript, the server side of a [`Duplex`].
+struct Player {
+    entries: VecDeque<Entry>,
+    pacing: Pacing,
+    start: Option<Instant>,
+    /// When the thread waking an async reader is due.
+    timer: Option<Instant>,
+}
+
+impl Peer for Player {
+    fn next(&mut self) -> Next {
+        let entry = match self.entries.front() {
+            Some(entry) => entry,
+            None => return Next::End,
+        };
+        if entry.direction == Direction::Out {
+            return Next::WaitForWrite(entry.line.clone());
+        }
+        if self.pacing == Pacing::RealTime {
+            let due = *self.start.get_or_insert_with(Instant::now) + entry.offset;
+            if due > Instant::now() {
+                return Next::WaitUntil(due);
+            }
+        }
+        Next::Line(self.entries.pop_front().unwrap().line)
+    }
+
+    fn receive(&mut self, line: &str) -> Result<()> {
+        match self.entries.front() {
+            Some(entry) if entry.direction == Direction::Out && entry.line == line => {
+                self.entries.pop_front();
+                Ok(())
+            }
+            Some(entry) if entry.direction == Direction::Out => Err(mismatch(format!(
+                "expected the client to send {:?}, it sent {:?}",
+                entry.line, line
+            ))),
+            Some(entry) => Err(mismatch(format!(
+                "expected the client to receive {:?} before it sent {:?}",
+                entry.line, line
+            ))),
+            None => Err(mismatch(format!(
+                "the client sent {:?} after the end of the transcript",
+                line
+            ))),
+        }
+    }
+}
+
+fn mismatch(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("replay: {}", message))
+}
+
+/// Plays the server side of a transcript back to a client, checking that
+/// the client sends the recorded lines. A received line is only delivered
+/// once the client sent every line recorded before it.
+///
+/// Clones share the same position, so one can be used for reading,
+/// another for writing and a third to check [`Replay::finish`].
+#[derive(Clone)]
+pub struct Replay {
+    state: Arc<Mutex<Duplex<Player>>>,
+}
+
+impl Replay {
+    pub fn new(transcript: Transcript, pacing: Pacing) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(Duplex::new(Player {
+                entries: transcript.entries.into(),
+                pacing,
+                start: None,
+                timer: None,
+            }))),
+        }
+    }
+
+    /// Fails unless the whole transcript was played back.
+    pub fn finish(&self) -> Result<()> {
+        let state = self.state.lock().unwrap();
+        match state.peer.entries.front() {
+            Some(entry) => Err(mismatch(format!(
+                "{} lines left, next {}",
+                state.peer.entries.len(),
+                entry
+            ))),
+            None if !state.unterminated().is_empty() => Err(mismatch(format!(
+                "the client sent an unterminated line {:?}",
+                String::from_utf8_lossy(state.unterminated())
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Read for Replay {
+    /// Sleeps until the next line is due in real time. Fails if the
+    /// client is expected to send a line first, as it can't while blocked here.
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            let step = self.state.lock().unwrap().read(buf);
+            match step {
+                ReadStep::Read(n) => return Ok(n),
+                ReadStep::WaitUntil(due) => {
+                    thread::sleep(due.saturating_duration_since(Instant::now()))
+                }
+                ReadStep::WaitForWrite(line) => {
+                    return Err(mismatch(format!(
+                        "expected the client to send {:?} before reading",
+                        line
+                    )))
+                }
+            }
+        }
+    }
+}
+
+impl Write for Replay {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.state.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for Replay {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<Result<usize>> {
+        let mut state = self.state.lock().unwrap();
+        match state.read(buf) {
+            ReadStep::Read(n) => Poll::Ready(Ok(n)),
+            ReadStep::WaitForWrite(_) => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+            ReadStep::WaitUntil(due) => {
+                // Without a runtime timer, a thread wakes the reader once the line is due.
+                if state.peer.timer != Some(due) {
+                    state.peer.timer = Some(due);
+                    let waker = cx.waker().clone();
+                    thread::spawn(move || {
+                        thread::sleep(due.saturating_duration_since(Instant::now()));
+                        waker.wake();
+                    });
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl AsyncWrite for Replay {
+    fn poll_write(self: Pin<&mut Self>, _cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        Poll::Ready(self.state.lock().unwrap().write(buf))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blocking::SyncClient;
+    use crate::client::Client;
+    use crate::ctcp::CtcpResponder;
+    use crate::message::{prelude::*, MessageBuilder};
+    use futures::executor::block_on;
+    use futures::io::Cursor;
+
+    /// A writer whose contents can be read while the recorder owns it.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn transcript(text: &str) -> Transcript {
+        Transcript::read(text.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_record() {
+        let out = Shared::default();
+        let recorder = Recorder::new(out.clone());
+        let input = Cursor::new(b":nick!u@h PRIVMSG test :\x01PING 1\x01\r\n:srv PI".to_vec());
+        let mut client = Client::new(recorder.wrap(input), recorder.wrap(Vec::new()));
+        client.set_ctcp_responder(Some(CtcpResponder::new()));
+        block_on(async {
+            client.send("NICK test").await.unwrap();
+            while client.recv().await.unwrap().is_some() {}
+        });
+
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let recorded = transcript(&out);
+        let lines: Vec<_> = recorded
+            .entries
+            .iter()
+            .map(|entry| (entry.direction, entry.line.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (Direction::Out, "NICK test"),
+                (Direction::In, ":nick!u@h PRIVMSG test :\x01PING 1\x01"),
+                (Direction::Out, "NOTICE nick :\x01PING 1\x01"),
+            ]
+        );
+        assert!(recorded
+            .entries
+            .windows(2)
+            .all(|w| w[0].offset <= w[1].offset));
+        let mut written = Vec::new();
+        recorded.write(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), out);
+    }
+
+    #[test]
+    fn test_replay() {
+        let replay = Replay::new(
+            transcript(concat!(
+                "0.000100 > NICK test\n",
+                "0.001000 < :nick!u@h PRIVMSG test :\x01PING 1\x01\n",
+                "0.001200 > NOTICE nick :\x01PING 1\x01\n",
+                "0.002000 < :srv NOTICE test :bye\n",
+            )),
+            Pacing::Fast,
+        );
+        let mut client = Client::new(replay.clone(), replay.clone());
+        client.set_ctcp_responder(Some(CtcpResponder::new()));
+        block_on(async {
+            client.send("NICK test").await.unwrap();
+            assert_eq!(client.recv().await.unwrap().unwrap().command(), "PRIVMSG");
+            assert_eq!(client.recv().await.unwrap().unwrap().command(), "NOTICE");
+            assert_eq!(client.recv().await.unwrap(), None);
+        });
+        replay.finish().unwrap();
+
+        let replay = Replay::new(transcript("0.0 > NICK test\n"), Pacing::Fast);
+        let mut client = Client::new(replay.clone(), replay.clone());
+        let err = block_on(client.send("NICK other")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("\"NICK test\""));
+        assert!(replay.finish().is_err());
+    }
+
+    #[test]
+    fn test_replay_real_time() {
+        let replay = Replay::new(
+            transcript("0.000000 < PING :a\n0.050000 > PONG a\n0.060000 < PING :b\n"),
+            Pacing::RealTime,
+        );
+        let mut client = SyncClient::new(replay.clone());
+        let started = Instant::now();
+        assert_eq!(client.recv().unwrap().params(), vec!["a"]);
+        client.send(MessageBuilder::new("PONG").param("a")).unwrap();
+        assert_eq!(client.recv().unwrap().params(), vec!["b"]);
+        assert!(started.elapsed() >= Duration::from_millis(60));
+        replay.finish().unwrap();
+    }
+}
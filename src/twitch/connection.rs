@@ -0,0 +1,289 @@
+use std::collections::VecDeque;
+use std::io::Result;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::time::{timeout, Instant};
+
+use crate::message::{prelude::*, ParsedMessage};
+use crate::runtime::tokio::{connect, TokioClient};
+
+pub const CAPABILITIES: [&str; 3] = [
+    "twitch.tv/tags",
+    "twitch.tv/commands",
+    "twitch.tv/membership",
+];
+
+/// Settings for connecting to Twitch chat.
+#[derive(Debug, Clone)]
+pub struct TwitchConfig {
+    pub host: String,
+    pub port: u16,
+    /// Login name and OAuth token, `None` to read chat anonymously.
+    pub credentials: Option<(String, String)>,
+    pub channels: Vec<String>,
+    /// At most `joins_per_window` channels are joined per `join_window`.
+    pub joins_per_window: usize,
+    pub join_window: Duration,
+}
+
+impl Default for TwitchConfig {
+    fn default() -> Self {
+        Self {
+            host: "irc.chat.twitch.tv".to_string(),
+            port: 6667,
+            credentials: None,
+            channels: Vec::new(),
+            joins_per_window: 20,
+            join_window: Duration::from_secs(10),
+        }
+    }
+}
+
+impl TwitchConfig {
+    pub fn new(login: &str, token: &str) -> Self {
+        Self {
+            credentials: Some((login.to_string(), token.to_string())),
+            ..Self::default()
+        }
+    }
+
+    pub fn anonymous() -> Self {
+        Self::default()
+    }
+
+    /// The lines sent to log in, in order.
+    pub fn registration(&self) -> Vec<String> {
+        let mut lines = vec![format!("CAP REQ :{}", CAPABILITIES.join(" "))];
+        match &self.credentials {
+            Some((login, token)) => {
+                let token = token.strip_prefix("oauth:").unwrap_or(token);
+                lines.push(format!("PASS oauth:{}", token));
+                lines.push(format!("NICK {}", login.to_lowercase()));
+            }
+            None => {
+                let suffix = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|since| since.subsec_nanos() % 100_000)
+                    .unwrap_or(0);
+                lines.push(format!("NICK justinfan{}", suffix));
+            }
+        }
+        lines
+    }
+}
+
+fn channel_name(channel: &str) -> String {
+    format!("#{}", channel.trim_start_matches('#').to_lowercase())
+}
+
+/// A connection to Twitch chat that logs in, answers PINGs, rate-limits JOINs
+/// and transparently reconnects when the server sends RECONNECT.
+pub struct TwitchClient {
+    config: TwitchConfig,
+    client: TokioClient,
+    channels: Vec<String>,
+    pending_joins: VecDeque<String>,
+    joins: VecDeque<Instant>,
+}
+
+impl TwitchClient {
+    pub async fn connect(config: TwitchConfig) -> Result<Self> {
+        let client = Self::login(&config).await?;
+        let mut twitch = Self {
+            config,
+            client,
+            channels: Vec::new(),
+            pending_joins: VecDeque::new(),
+            joins: VecDeque::new(),
+        };
+        for channel in twitch.config.channels.clone() {
+            twitch.join(&channel).await?;
+        }
+        Ok(twitch)
+    }
+
+    async fn login(config: &TwitchConfig) -> Result<TokioClient> {
+        let mut client = connect((config.host.as_str(), config.port)).await?;
+        for line in config.registration() {
+            client.send(&line).await?;
+        }
+        Ok(client)
+    }
+
+    /// The channels joined or waiting to be joined.
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+
+    /// Joins a channel once the JOIN rate limit allows it.
+    pub async fn join(&mut self, channel: &str) -> Result<()> {
+        let channel = channel_name(channel);
+        if !self.channels.contains(&channel) {
+            self.channels.push(channel.clone());
+            self.pending_joins.push_back(channel);
+        }
+        self.send_joins().await.map(|_| ())
+    }
+
+    pub async fn part(&mut self, channel: &str) -> Result<()> {
+        let channel = channel_name(channel);
+        self.channels.retain(|joined| *joined != channel);
+        self.pending_joins.retain(|pending| *pending != channel);
+        self.client.send(&format!("PART {}", channel)).await
+    }
+
+    pub async fn privmsg(&mut self, channel: &str, text: &str) -> Result<()> {
+        self.client
+            .send(&format!("PRIVMSG {} :{}", channel_name(channel), text))
+            .await
+    }
+
+    pub async fn send(&mut self, line: &str) -> Result<()> {
+        self.client.send(line).await
+    }
+
+    /// Sends queued JOINs as long as the rate limit allows,
+    /// returning when the next one may be sent.
+    async fn send_joins(&mut self) -> Result<Option<Instant>> {
+        loop {
+            let now = Instant::now();
+            while let Some(&sent) = self.joins.front() {
+                if now.duration_since(sent) < self.config.join_window {
+                    break;
+                }
+                self.joins.pop_front();
+            }
+            if self.pending_joins.is_empty() {
+                return Ok(None);
+            }
+            if self.joins.len() >= self.config.joins_per_window {
+                return Ok(self
+                    .joins
+                    .front()
+                    .map(|&sent| sent + self.config.join_window));
+            }
+            let channel = self.pending_joins.pop_front().unwrap();
+            self.joins.push_back(now);
+            self.client.send(&format!("JOIN {}", channel)).await?;
+        }
+    }
+
+    /// Receives the next message, returning `None` once the connection is closed.
+    /// PING and RECONNECT are handled here and not passed on.
+    /// Waiting for queued JOINs times out [`Client::recv`](crate::client::Client::recv),
+    /// which is cancel-safe, so no message is lost.
+    pub async fn recv(&mut self) -> Result<Option<ParsedMessage>> {
+        loop {
+            let msg = match self.send_joins().await? {
+                Some(next_join) => {
+                    let wait = next_join.saturating_duration_since(Instant::now());
+                    match timeout(wait, self.client.recv()).await {
+                        Ok(msg) => msg?,
+                        Err(_) => continue,
+                    }
+                }
+                None => self.client.recv().await?,
+            };
+            let msg = match msg {
+                Some(msg) => msg,
+                None => return Ok(None),
+            };
+
+            match msg.command().as_str() {
+                "PING" => {
+                    let token = msg.params().pop().unwrap_or_default();
+                    self.client.send(&format!("PONG :{}", token)).await?;
+                }
+                "RECONNECT" => self.reconnect().await?,
+                _ => return Ok(Some(msg)),
+            }
+        }
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        self.client = Self::login(&self.config).await?;
+        self.pending_joins = self.channels.iter().cloned().collect();
+        self.send_joins().await.map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::tcp::OwnedReadHalf;
+    use tokio::net::TcpListener;
+
+    async fn read_line(reader: &mut tokio::io::Lines<BufReader<OwnedReadHalf>>) -> String {
+        reader.next_line().await.unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_registration() {
+        let lines = TwitchConfig::new("MyBot", "abc123").registration();
+        assert_eq!(
+            lines,
+            vec![
+                "CAP REQ :twitch.tv/tags twitch.tv/commands twitch.tv/membership",
+                "PASS oauth:abc123",
+                "NICK mybot",
+            ]
+        );
+        let lines = TwitchConfig::anonymous().registration();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("NICK justinfan"));
+    }
+
+    // Time is paused and only advances while every task waits, so the JOIN
+    // rate limit is checked without depending on the machine's speed.
+    #[tokio::test(start_paused = true)]
+    async fn test_fake_tmi() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = TwitchConfig {
+            host: "127.0.0.1".to_string(),
+            port: listener.local_addr().unwrap().port(),
+            channels: vec!["#One".to_string(), "two".to_string(), "three".to_string()],
+            joins_per_window: 2,
+            join_window: Duration::from_millis(200),
+            ..TwitchConfig::new("bot", "oauth:token")
+        };
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader).lines();
+            assert!(read_line(&mut reader).await.starts_with("CAP REQ"));
+            assert_eq!(read_line(&mut reader).await, "PASS oauth:token");
+            assert_eq!(read_line(&mut reader).await, "NICK bot");
+            assert_eq!(read_line(&mut reader).await, "JOIN #one");
+            assert_eq!(read_line(&mut reader).await, "JOIN #two");
+            let started = Instant::now();
+            writer.write_all(b"PING :tmi.twitch.tv\r\n").await.unwrap();
+            assert_eq!(read_line(&mut reader).await, "PONG :tmi.twitch.tv");
+            assert_eq!(read_line(&mut reader).await, "JOIN #three");
+            assert_eq!(started.elapsed(), Duration::from_millis(200));
+
+            writer
+                .write_all(b":tmi.twitch.tv RECONNECT\r\n")
+                .await
+                .unwrap();
+            let (stream, _) = listener.accept().await.unwrap();
+            let (reader, mut writer) = stream.into_split();
+            let mut reader = BufReader::new(reader).lines();
+            assert!(read_line(&mut reader).await.starts_with("CAP REQ"));
+            assert_eq!(read_line(&mut reader).await, "PASS oauth:token");
+            assert_eq!(read_line(&mut reader).await, "NICK bot");
+            assert_eq!(read_line(&mut reader).await, "JOIN #one");
+            writer
+                .write_all(b":bot!bot@bot.tmi.twitch.tv JOIN #one\r\n")
+                .await
+                .unwrap();
+        });
+
+        let mut client = TwitchClient::connect(config).await.unwrap();
+        let msg = client.recv().await.unwrap().unwrap();
+        assert_eq!(msg.command(), "JOIN");
+        assert_eq!(client.channels(), ["#one", "#two", "#three"]);
+        server.await.unwrap();
+    }
+}
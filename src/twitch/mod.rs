@@ -0,0 +1,598 @@
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::ctcp::Ctcp;
+use crate::format::Color;
+use crate::message::{prelude::*, ParsedMessage};
+
+#[cfg(feature = "tokio")]
+pub mod connection;
+pub mod usernotice;
+#[cfg(feature = "tokio")]
+pub use connection::{TwitchClient, TwitchConfig};
+pub use usernotice::{SubPlan, UserNoticeEvent};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Badge {
+    pub name: String,
+    pub version: String,
+}
+
+/// Parses a `badges` or `badge-info` value such as `broadcaster/1,subscriber/12`.
+pub fn parse_badges(value: &str) -> Vec<Badge> {
+    value
+        .split(',')
+        .filter(|badge| !badge.is_empty())
+        .map(|badge| {
+            let (name, version) = badge.split_once('/').unwrap_or((badge, ""));
+            Badge {
+                name: name.to_string(),
+                version: version.to_string(),
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Emote {
+    pub id: String,
+    /// Byte range of the emote within the message text.
+    pub range: Range<usize>,
+    pub code: String,
+}
+
+/// Parses an `emotes` value such as `25:0-4,12-16/1902:6-10` against the message text.
+/// Twitch counts positions in characters, the returned ranges are byte offsets into `text`.
+/// Positions outside of the text are skipped.
+pub fn parse_emotes(value: &str, text: &str) -> Vec<Emote> {
+    let offsets: Vec<usize> = text
+        .char_indices()
+        .map(|(offset, _)| offset)
+        .chain(std::iter::once(text.len()))
+        .collect();
+
+    let mut emotes = Vec::new();
+    for emote in value.split('/').filter(|emote| !emote.is_empty()) {
+        let (id, positions) = match emote.split_once(':') {
+            Some(emote) => emote,
+            None => continue,
+        };
+        for position in positions.split(',') {
+            let (start, end) = match position.split_once('-') {
+                Some((start, end)) => (start.parse::<usize>(), end.parse::<usize>()),
+                None => continue,
+            };
+            let (start, end) = match (start, end) {
+                (Ok(start), Ok(end)) if start <= end && end + 1 < offsets.len() => (start, end),
+                _ => continue,
+            };
+            let range = offsets[start]..offsets[end + 1];
+            emotes.push(Emote {
+                id: id.to_string(),
+                code: text[range.clone()].to_string(),
+                range,
+            });
+        }
+    }
+    emotes.sort_by_key(|emote| emote.range.start);
+    emotes
+}
+
+/// Parses a `color` value such as `#1E90FF`.
+pub fn parse_color(value: &str) -> Option<Color> {
+    let hex = value.strip_prefix('#')?;
+    if hex.len() != 6 {
+        return None;
+    }
+    let rgb = u32::from_str_radix(hex, 16).ok()?;
+    Some(Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8))
+}
+
+/// The tags of a message looked up by key, empty values being treated as absent.
+#[derive(Debug, Clone, Default)]
+pub struct Tags(HashMap<String, String>);
+
+impl Tags {
+    pub fn from_message(msg: &ParsedMessage) -> Self {
+        Self(msg.tags().into_iter().collect())
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.0
+            .get(key)
+            .map(String::as_str)
+            .filter(|value| !value.is_empty())
+    }
+
+    pub fn string(&self, key: &str) -> Option<String> {
+        self.get(key).map(str::to_string)
+    }
+
+    pub fn number<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
+        self.get(key).and_then(|value| value.parse().ok())
+    }
+
+    pub fn flag(&self, key: &str) -> Option<bool> {
+        self.get(key).map(|value| value != "0")
+    }
+
+    pub fn badges(&self, key: &str) -> Vec<Badge> {
+        self.get(key).map(parse_badges).unwrap_or_default()
+    }
+
+    pub fn color(&self) -> Option<Color> {
+        self.get("color").and_then(parse_color)
+    }
+
+    pub fn emote_sets(&self) -> Vec<String> {
+        self.get("emote-sets")
+            .map(|sets| sets.split(',').map(str::to_string).collect())
+            .unwrap_or_default()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.0.iter()
+    }
+
+    pub fn into_inner(self) -> HashMap<String, String> {
+        self.0
+    }
+}
+
+fn channel(msg: &ParsedMessage) -> Option<String> {
+    msg.params()
+        .into_iter()
+        .next()
+        .map(|channel| channel.trim_start_matches('#').to_string())
+}
+
+/// Metadata of the message a reply refers to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReplyParent {
+    pub message_id: String,
+    pub user_id: Option<String>,
+    pub login: Option<String>,
+    pub display_name: Option<String>,
+    pub text: Option<String>,
+}
+
+impl ReplyParent {
+    fn from_tags(tags: &Tags) -> Option<Self> {
+        Some(Self {
+            message_id: tags.string("reply-parent-msg-id")?,
+            user_id: tags.string("reply-parent-user-id"),
+            login: tags.string("reply-parent-user-login"),
+            display_name: tags.string("reply-parent-display-name"),
+            text: tags.string("reply-parent-msg-body"),
+        })
+    }
+}
+
+/// A chat message, `PRIVMSG #channel :text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Privmsg {
+    pub channel: String,
+    pub login: String,
+    pub user_id: Option<String>,
+    pub display_name: Option<String>,
+    pub color: Option<Color>,
+    pub badges: Vec<Badge>,
+    pub badge_info: Vec<Badge>,
+    pub emotes: Vec<Emote>,
+    pub bits: Option<u64>,
+    pub reply_parent: Option<ReplyParent>,
+    pub message_id: Option<String>,
+    pub room_id: Option<String>,
+    /// The text with a `/me` ACTION wrapper removed.
+    pub text: String,
+    pub is_action: bool,
+}
+
+impl Privmsg {
+    pub fn from_message(msg: &ParsedMessage) -> Option<Self> {
+        if msg.command() != "PRIVMSG" {
+            return None;
+        }
+        let tags = Tags::from_message(msg);
+        let raw_text = msg.params().get(1)?.clone();
+        let (text, is_action) = match Ctcp::decode(&raw_text) {
+            Some(ctcp) if ctcp.command == "ACTION" => (ctcp.argument, true),
+            _ => (raw_text, false),
+        };
+        Some(Self {
+            channel: channel(msg)?,
+            login: msg.nick()?,
+            user_id: tags.string("user-id"),
+            display_name: tags.string("display-name"),
+            color: tags.color(),
+            badges: tags.badges("badges"),
+            badge_info: tags.badges("badge-info"),
+            emotes: parse_emotes(tags.get("emotes").unwrap_or(""), &text),
+            bits: tags.number("bits"),
+            reply_parent: ReplyParent::from_tags(&tags),
+            message_id: tags.string("id"),
+            room_id: tags.string("room-id"),
+            text,
+            is_action,
+        })
+    }
+}
+
+/// A channel event such as a subscription or raid.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UserNotice {
+    pub channel: String,
+    pub login: Option<String>,
+    pub user_id: Option<String>,
+    pub display_name: Option<String>,
+    pub color: Option<Color>,
+    pub badges: Vec<Badge>,
+    pub badge_info: Vec<Badge>,
+    pub emotes: Vec<Emote>,
+    pub kind: String,
+    pub event: UserNoticeEvent,
+    pub system_message: Option<String>,
+    pub message_id: Option<String>,
+    pub room_id: Option<String>,
+    /// The optional message the user attached.
+    pub text: Option<String>,
+}
+
+impl UserNotice {
+    pub fn from_message(msg: &ParsedMessage) -> Option<Self> {
+        if msg.command() != "USERNOTICE" {
+            return None;
+        }
+        let tags = Tags::from_message(msg);
+        let text = msg.params().get(1).cloned();
+        Some(Self {
+            channel: channel(msg)?,
+            login: tags.string("login"),
+            user_id: tags.string("user-id"),
+            display_name: tags.string("display-name"),
+            color: tags.color(),
+            badges: tags.badges("badges"),
+            badge_info: tags.badges("badge-info"),
+            emotes: parse_emotes(
+                tags.get("emotes").unwrap_or(""),
+                text.as_deref().unwrap_or(""),
+            ),
+            kind: tags.string("msg-id")?,
+            event: UserNoticeEvent::from_tags(tags.clone())?,
+            system_message: tags.string("system-msg"),
+            message_id: tags.string("id"),
+            room_id: tags.string("room-id"),
+            text,
+        })
+    }
+}
+
+/// A ban, timeout or full chat clear, `CLEARCHAT #channel [:login]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClearChat {
+    pub channel: String,
+    /// The user whose messages were removed, `None` if the whole chat was cleared.
+    pub target_login: Option<String>,
+    pub target_user_id: Option<String>,
+    /// Timeout in seconds, `None` for a permanent ban.
+    pub ban_duration: Option<u64>,
+    pub room_id: Option<String>,
+}
+
+impl ClearChat {
+    pub fn from_message(msg: &ParsedMessage) -> Option<Self> {
+        if msg.command() != "CLEARCHAT" {
+            return None;
+        }
+        let tags = Tags::from_message(msg);
+        Some(Self {
+            channel: channel(msg)?,
+            target_login: msg.params().get(1).cloned(),
+            target_user_id: tags.string("target-user-id"),
+            ban_duration: tags.number("ban-duration"),
+            room_id: tags.string("room-id"),
+        })
+    }
+}
+
+/// A single deleted message, `CLEARMSG #channel :text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClearMsg {
+    pub channel: String,
+    pub login: Option<String>,
+    pub target_message_id: String,
+    pub text: Option<String>,
+}
+
+impl ClearMsg {
+    pub fn from_message(msg: &ParsedMessage) -> Option<Self> {
+        if msg.command() != "CLEARMSG" {
+            return None;
+        }
+        let tags = Tags::from_message(msg);
+        Some(Self {
+            channel: channel(msg)?,
+            login: tags.string("login"),
+            target_message_id: tags.string("target-msg-id")?,
+            text: msg.params().get(1).cloned(),
+        })
+    }
+}
+
+/// Chat room settings. Only the settings that changed are present on updates.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RoomState {
+    pub channel: String,
+    pub room_id: Option<String>,
+    pub emote_only: Option<bool>,
+    /// Minutes a user has to follow before chatting, `-1` when disabled.
+    pub followers_only: Option<i64>,
+    pub r9k: Option<bool>,
+    /// Seconds between messages of a user.
+    pub slow: Option<u64>,
+    pub subs_only: Option<bool>,
+}
+
+impl RoomState {
+    pub fn from_message(msg: &ParsedMessage) -> Option<Self> {
+        if msg.command() != "ROOMSTATE" {
+            return None;
+        }
+        let tags = Tags::from_message(msg);
+        Some(Self {
+            channel: channel(msg)?,
+            room_id: tags.string("room-id"),
+            emote_only: tags.flag("emote-only"),
+            followers_only: tags.number("followers-only"),
+            r9k: tags.flag("r9k"),
+            slow: tags.number("slow"),
+            subs_only: tags.flag("subs-only"),
+        })
+    }
+}
+
+/// Our own state in a channel, sent on join and after each message we send.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UserState {
+    pub channel: String,
+    pub display_name: Option<String>,
+    pub color: Option<Color>,
+    pub badges: Vec<Badge>,
+    pub badge_info: Vec<Badge>,
+    pub emote_sets: Vec<String>,
+    pub moderator: bool,
+}
+
+impl UserState {
+    pub fn from_message(msg: &ParsedMessage) -> Option<Self> {
+        if msg.command() != "USERSTATE" {
+            return None;
+        }
+        let tags = Tags::from_message(msg);
+        Some(Self {
+            channel: channel(msg)?,
+            display_name: tags.string("display-name"),
+            color: tags.color(),
+            badges: tags.badges("badges"),
+            badge_info: tags.badges("badge-info"),
+            emote_sets: tags.emote_sets(),
+            moderator: tags.flag("mod").unwrap_or(false),
+        })
+    }
+}
+
+/// Our own global state, sent once after logging in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GlobalUserState {
+    pub user_id: Option<String>,
+    pub display_name: Option<String>,
+    pub color: Option<Color>,
+    pub badges: Vec<Badge>,
+    pub badge_info: Vec<Badge>,
+    pub emote_sets: Vec<String>,
+}
+
+impl GlobalUserState {
+    pub fn from_message(msg: &ParsedMessage) -> Option<Self> {
+        if msg.command() != "GLOBALUSERSTATE" {
+            return None;
+        }
+        let tags = Tags::from_message(msg);
+        Some(Self {
+            user_id: tags.string("user-id"),
+            display_name: tags.string("display-name"),
+            color: tags.color(),
+            badges: tags.badges("badges"),
+            badge_info: tags.badges("badge-info"),
+            emote_sets: tags.emote_sets(),
+        })
+    }
+}
+
+/// A private message, `WHISPER to_login :text`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Whisper {
+    pub from_login: String,
+    pub to_login: String,
+    pub user_id: Option<String>,
+    pub display_name: Option<String>,
+    pub color: Option<Color>,
+    pub badges: Vec<Badge>,
+    pub emotes: Vec<Emote>,
+    pub message_id: Option<String>,
+    pub thread_id: Option<String>,
+    pub text: String,
+}
+
+impl Whisper {
+    pub fn from_message(msg: &ParsedMessage) -> Option<Self> {
+        if msg.command() != "WHISPER" {
+            return None;
+        }
+        let tags = Tags::from_message(msg);
+        let params = msg.params();
+        let text = params.get(1)?.clone();
+        Some(Self {
+            from_login: msg.nick()?,
+            to_login: params.first()?.clone(),
+            user_id: tags.string("user-id"),
+            display_name: tags.string("display-name"),
+            color: tags.color(),
+            badges: tags.badges("badges"),
+            emotes: parse_emotes(tags.get("emotes").unwrap_or(""), &text),
+            message_id: tags.string("message-id"),
+            thread_id: tags.string("thread-id"),
+            text,
+        })
+    }
+}
+
+/// A message in Twitch's IRC dialect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TwitchMessage {
+    Privmsg(Privmsg),
+    UserNotice(UserNotice),
+    ClearChat(ClearChat),
+    ClearMsg(ClearMsg),
+    RoomState(RoomState),
+    UserState(UserState),
+    GlobalUserState(GlobalUserState),
+    Whisper(Whisper),
+}
+
+impl TwitchMessage {
+    /// Decodes the Twitch-specific commands, `None` for anything else or malformed messages.
+    pub fn from_message(msg: &ParsedMessage) -> Option<Self> {
+        match msg.command().as_str() {
+            "PRIVMSG" => Privmsg::from_message(msg).map(Self::Privmsg),
+            "USERNOTICE" => UserNotice::from_message(msg).map(Self::UserNotice),
+            "CLEARCHAT" => ClearChat::from_message(msg).map(Self::ClearChat),
+            "CLEARMSG" => ClearMsg::from_message(msg).map(Self::ClearMsg),
+            "ROOMSTATE" => RoomState::from_message(msg).map(Self::RoomState),
+            "USERSTATE" => UserState::from_message(msg).map(Self::UserState),
+            "GLOBALUSERSTATE" => GlobalUserState::from_message(msg).map(Self::GlobalUserState),
+            "WHISPER" => Whisper::from_message(msg).map(Self::Whisper),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_privmsg() {
+        let msg = ParsedMessage::parse(
+            "@badge-info=subscriber/8;badges=broadcaster/1,subscriber/6;bits=100;color=#0D4200;display-name=Ronni;emotes=25:0-4,12-16/1902:6-10;id=b34ccfc7-4977-403a-8a94-33c6bac34fb8;room-id=1337;user-id=1337 :ronni!ronni@ronni.tmi.twitch.tv PRIVMSG #ronni :Kappa Keepo Kappa"
+                .to_string(),
+        );
+        let privmsg = Privmsg::from_message(&msg).unwrap();
+
+        assert_eq!(privmsg.channel, "ronni");
+        assert_eq!(privmsg.login, "ronni");
+        assert_eq!(privmsg.display_name.as_deref(), Some("Ronni"));
+        assert_eq!(privmsg.color, Some(Color::Rgb(0x0d, 0x42, 0)));
+        assert_eq!(
+            privmsg.badges,
+            vec![
+                Badge {
+                    name: "broadcaster".to_string(),
+                    version: "1".to_string()
+                },
+                Badge {
+                    name: "subscriber".to_string(),
+                    version: "6".to_string()
+                },
+            ]
+        );
+        assert_eq!(privmsg.badge_info[0].version, "8");
+        assert_eq!(privmsg.bits, Some(100));
+        let codes: Vec<_> = privmsg
+            .emotes
+            .iter()
+            .map(|e| (e.id.as_str(), e.code.as_str()))
+            .collect();
+        assert_eq!(
+            codes,
+            vec![("25", "Kappa"), ("1902", "Keepo"), ("25", "Kappa")]
+        );
+        assert_eq!(privmsg.reply_parent, None);
+        assert!(!privmsg.is_action);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn test_serde_round_trip() {
+        let msg = ParsedMessage::parse(
+            "@badges=moderator/1;color=#FF0000;emotes=25:0-4;id=abc :mod!mod@mod.tmi.twitch.tv PRIVMSG #chan :Kappa"
+                .to_string(),
+        );
+        let event = TwitchMessage::from_message(&msg).unwrap();
+        let json = serde_json::to_string(&event).unwrap();
+        assert_eq!(serde_json::from_str::<TwitchMessage>(&json).unwrap(), event);
+    }
+
+    #[test]
+    fn test_emotes_utf8() {
+        let text = "👉 Kappa über Kappa";
+        let emotes = parse_emotes("25:2-6,13-17", text);
+        assert_eq!(emotes.len(), 2);
+        assert_eq!(emotes[0].code, "Kappa");
+        assert_eq!(&text[emotes[1].range.clone()], "Kappa");
+        assert!(parse_emotes("25:30-34", text).is_empty());
+    }
+
+    #[test]
+    fn test_action_reply() {
+        let msg = ParsedMessage::parse(
+            "@emotes=25:5-9;reply-parent-msg-id=abc;reply-parent-user-login=bob;reply-parent-msg-body=hi\\sthere :alice!alice@alice.tmi.twitch.tv PRIVMSG #chan :\x01ACTION says Kappa\x01"
+                .to_string(),
+        );
+        let privmsg = Privmsg::from_message(&msg).unwrap();
+        assert!(privmsg.is_action);
+        assert_eq!(privmsg.text, "says Kappa");
+        assert_eq!(privmsg.emotes[0].code, "Kappa");
+        let parent = privmsg.reply_parent.unwrap();
+        assert_eq!(parent.message_id, "abc");
+        assert_eq!(parent.login.as_deref(), Some("bob"));
+        assert_eq!(parent.text.as_deref(), Some("hi there"));
+    }
+
+    #[test]
+    fn test_moderation() {
+        let msg = ParsedMessage::parse(
+            "@ban-duration=350;room-id=1;target-user-id=2 :tmi.twitch.tv CLEARCHAT #dallas :ronni"
+                .to_string(),
+        );
+        match TwitchMessage::from_message(&msg) {
+            Some(TwitchMessage::ClearChat(clear)) => {
+                assert_eq!(clear.target_login.as_deref(), Some("ronni"));
+                assert_eq!(clear.ban_duration, Some(350));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let msg = ParsedMessage::parse(
+            "@emote-only=0;followers-only=-1;r9k=0;room-id=1;slow=10;subs-only=1 :tmi.twitch.tv ROOMSTATE #dallas"
+                .to_string(),
+        );
+        let state = RoomState::from_message(&msg).unwrap();
+        assert_eq!(state.emote_only, Some(false));
+        assert_eq!(state.followers_only, Some(-1));
+        assert_eq!(state.slow, Some(10));
+        assert_eq!(state.subs_only, Some(true));
+    }
+}
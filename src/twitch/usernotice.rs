@@ -0,0 +1,251 @@
+use std::collections::HashMap;
+
+use super::Tags;
+use crate::message::ParsedMessage;
+
+const ANONYMOUS_GIFTER: &str = "ananonymousgifter";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SubPlan {
+    Prime,
+    Tier1,
+    Tier2,
+    Tier3,
+    Other(String),
+}
+
+impl SubPlan {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "Prime" => SubPlan::Prime,
+            "1000" => SubPlan::Tier1,
+            "2000" => SubPlan::Tier2,
+            "3000" => SubPlan::Tier3,
+            other => SubPlan::Other(other.to_string()),
+        }
+    }
+}
+
+/// The event announced by a USERNOTICE, selected by its `msg-id` tag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum UserNoticeEvent {
+    /// `sub` and `resub`.
+    Sub {
+        is_resub: bool,
+        cumulative_months: Option<u64>,
+        /// Only present if the user chose to share it.
+        streak_months: Option<u64>,
+        sub_plan: Option<SubPlan>,
+        sub_plan_name: Option<String>,
+    },
+    /// `subgift` and `anonsubgift`.
+    SubGift {
+        /// `None` for anonymous gifts.
+        gifter: Option<String>,
+        recipient_login: Option<String>,
+        recipient_display_name: Option<String>,
+        recipient_id: Option<String>,
+        months: Option<u64>,
+        gift_months: Option<u64>,
+        sub_plan: Option<SubPlan>,
+        /// Total gifts by the gifter in the channel, if they share it.
+        sender_count: Option<u64>,
+    },
+    /// `submysterygift` and `anonsubmysterygift`, followed by one `subgift` per recipient.
+    SubMysteryGift {
+        gifter: Option<String>,
+        count: Option<u64>,
+        sub_plan: Option<SubPlan>,
+        sender_count: Option<u64>,
+    },
+    /// `giftpaidupgrade` and `anongiftpaidupgrade`.
+    GiftPaidUpgrade {
+        gifter: Option<String>,
+        promo_name: Option<String>,
+    },
+    PrimePaidUpgrade {
+        sub_plan: Option<SubPlan>,
+    },
+    Raid {
+        raider_login: Option<String>,
+        raider_display_name: Option<String>,
+        viewer_count: Option<u64>,
+    },
+    Unraid,
+    Announcement {
+        /// `PRIMARY`, `BLUE`, `GREEN`, `ORANGE` or `PURPLE`.
+        color: Option<String>,
+    },
+    BitsBadgeTier {
+        threshold: Option<u64>,
+    },
+    Ritual {
+        name: Option<String>,
+    },
+    /// Any other `msg-id`, with all tags of the message.
+    Other {
+        kind: String,
+        tags: HashMap<String, String>,
+    },
+}
+
+impl UserNoticeEvent {
+    pub fn from_message(msg: &ParsedMessage) -> Option<Self> {
+        Self::from_tags(Tags::from_message(msg))
+    }
+
+    pub fn from_tags(tags: Tags) -> Option<Self> {
+        let kind = tags.string("msg-id")?;
+        let param = |name: &str| tags.string(&format!("msg-param-{}", name));
+        let number = |name: &str| tags.number::<u64>(&format!("msg-param-{}", name));
+        let sub_plan = || param("sub-plan").as_deref().map(SubPlan::parse);
+        let gifter = || {
+            tags.string("login")
+                .filter(|login| !kind.starts_with("anon") && login != ANONYMOUS_GIFTER)
+        };
+
+        let event = match kind.as_str() {
+            "sub" | "resub" => UserNoticeEvent::Sub {
+                is_resub: kind == "resub",
+                cumulative_months: number("cumulative-months"),
+                streak_months: number("streak-months")
+                    .filter(|_| tags.flag("msg-param-should-share-streak") == Some(true)),
+                sub_plan: sub_plan(),
+                sub_plan_name: param("sub-plan-name"),
+            },
+            "subgift" | "anonsubgift" => UserNoticeEvent::SubGift {
+                gifter: gifter(),
+                recipient_login: param("recipient-user-name"),
+                recipient_display_name: param("recipient-display-name"),
+                recipient_id: param("recipient-id"),
+                months: number("months"),
+                gift_months: number("gift-months"),
+                sub_plan: sub_plan(),
+                sender_count: number("sender-count").filter(|&count| count > 0),
+            },
+            "submysterygift" | "anonsubmysterygift" => UserNoticeEvent::SubMysteryGift {
+                gifter: gifter(),
+                count: number("mass-gift-count"),
+                sub_plan: sub_plan(),
+                sender_count: number("sender-count").filter(|&count| count > 0),
+            },
+            "giftpaidupgrade" | "anongiftpaidupgrade" => UserNoticeEvent::GiftPaidUpgrade {
+                gifter: param("sender-login").filter(|_| kind == "giftpaidupgrade"),
+                promo_name: param("promo-name"),
+            },
+            "primepaidupgrade" => UserNoticeEvent::PrimePaidUpgrade {
+                sub_plan: sub_plan(),
+            },
+            "raid" => UserNoticeEvent::Raid {
+                raider_login: param("login"),
+                raider_display_name: param("displayName"),
+                viewer_count: number("viewerCount"),
+            },
+            "unraid" => UserNoticeEvent::Unraid,
+            "announcement" => UserNoticeEvent::Announcement {
+                color: param("color"),
+            },
+            "bitsbadgetier" => UserNoticeEvent::BitsBadgeTier {
+                threshold: number("threshold"),
+            },
+            "ritual" => UserNoticeEvent::Ritual {
+                name: param("ritual-name"),
+            },
+            _ => UserNoticeEvent::Other {
+                kind: kind.clone(),
+                tags: tags.clone().into_inner(),
+            },
+        };
+        Some(event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(line: &str) -> UserNoticeEvent {
+        UserNoticeEvent::from_message(&ParsedMessage::parse(line.to_string())).unwrap()
+    }
+
+    #[test]
+    fn test_resub() {
+        let event = event("@badge-info=subscriber/8;login=ronni;msg-id=resub;msg-param-cumulative-months=8;msg-param-should-share-streak=1;msg-param-streak-months=2;msg-param-sub-plan=Prime;msg-param-sub-plan-name=Prime;system-msg=ronni\\shas\\ssubscribed :tmi.twitch.tv USERNOTICE #dallas :Great stream -- keep it up!");
+        assert_eq!(
+            event,
+            UserNoticeEvent::Sub {
+                is_resub: true,
+                cumulative_months: Some(8),
+                streak_months: Some(2),
+                sub_plan: Some(SubPlan::Prime),
+                sub_plan_name: Some("Prime".to_string()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_gifts() {
+        let gift = event("@login=tww2;msg-id=subgift;msg-param-months=1;msg-param-recipient-display-name=Mr_Woodchuck;msg-param-recipient-id=55554444;msg-param-recipient-user-name=mr_woodchuck;msg-param-sender-count=0;msg-param-sub-plan=1000 :tmi.twitch.tv USERNOTICE #forstycup");
+        assert_eq!(
+            gift,
+            UserNoticeEvent::SubGift {
+                gifter: Some("tww2".to_string()),
+                recipient_login: Some("mr_woodchuck".to_string()),
+                recipient_display_name: Some("Mr_Woodchuck".to_string()),
+                recipient_id: Some("55554444".to_string()),
+                months: Some(1),
+                gift_months: None,
+                sub_plan: Some(SubPlan::Tier1),
+                sender_count: None,
+            }
+        );
+
+        let mystery = event("@login=ananonymousgifter;msg-id=anonsubmysterygift;msg-param-mass-gift-count=5;msg-param-sub-plan=2000 :tmi.twitch.tv USERNOTICE #channel");
+        assert_eq!(
+            mystery,
+            UserNoticeEvent::SubMysteryGift {
+                gifter: None,
+                count: Some(5),
+                sub_plan: Some(SubPlan::Tier2),
+                sender_count: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_raid_announcement_other() {
+        let raid = event("@login=testchannel;msg-id=raid;msg-param-displayName=TestChannel;msg-param-login=testchannel;msg-param-viewerCount=15 :tmi.twitch.tv USERNOTICE #othertestchannel");
+        assert!(matches!(
+            raid,
+            UserNoticeEvent::Raid {
+                viewer_count: Some(15),
+                ..
+            }
+        ));
+
+        let announcement = event(
+            "@msg-id=announcement;msg-param-color=PURPLE :tmi.twitch.tv USERNOTICE #channel :Hello",
+        );
+        assert_eq!(
+            announcement,
+            UserNoticeEvent::Announcement {
+                color: Some("PURPLE".to_string())
+            }
+        );
+
+        match event(
+            "@msg-id=charitydonation;msg-param-charity-name=Foo :tmi.twitch.tv USERNOTICE #channel",
+        ) {
+            UserNoticeEvent::Other { kind, tags } => {
+                assert_eq!(kind, "charitydonation");
+                assert_eq!(
+                    tags.get("msg-param-charity-name").map(String::as_str),
+                    Some("Foo")
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
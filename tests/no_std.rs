@@ -0,0 +1,22 @@
+//! Builds the crate without default features, where it is `#![no_std]`,
+//! the way CI checks the configuration used on embedded targets.
+
+use std::process::Command;
+
+#[test]
+fn builds_without_std() {
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    let output = Command::new(env!("CARGO"))
+        .args(["build", "--lib", "--no-default-features", "--manifest-path"])
+        .arg(format!("{}/Cargo.toml", manifest_dir))
+        // A separate target directory avoids waiting on the lock held by `cargo test`.
+        .arg("--target-dir")
+        .arg(format!("{}/target/no_std", manifest_dir))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}